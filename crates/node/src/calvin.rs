@@ -0,0 +1,194 @@
+//! A Calvin-style deterministic sequencer: servers agree on the order of batches of
+//! transactions rather than of each transaction.
+//!
+//! Every server gathers what its clients send during an epoch into a batch, and appends the
+//! batch to a [`Raft`] log as one command. Every replica then runs the batches in log order,
+//! and the transactions of a batch in order, each on its own copy of a [`StateMachine`], which
+//! has to be deterministic so the copies agree without any further coordination.
+//!
+//! A transaction joins a batch after it's invoked and is answered once its batch is applied, so
+//! the log order respects real time: transactions are strictly serializable.
+
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use serde_json::{Value, json};
+use tokio::sync::oneshot;
+use tokio::time::{self, Duration, MissedTickBehavior};
+
+use crate::consensus::{self, StateMachine};
+use crate::error::Error;
+use crate::handlers::HandlersMap;
+use crate::raft::Raft;
+use crate::server::Server;
+
+/// Type of the commands carrying a batch through the log, and of the requests relaying one to
+/// the leader.
+pub const BATCH: &str = "calvin_batch";
+pub const BATCH_OK: &str = "calvin_batch_ok";
+
+/// How long a server gathers transactions before sequencing them.
+const EPOCH: Duration = Duration::from_millis(10);
+
+type Srv = Arc<Mutex<dyn Server + Send + Sync>>;
+
+/// Runs batches of commands on `S`, replying with what it made of each, errors included.
+#[derive(Debug)]
+pub struct Batches<S> {
+    machine: S,
+}
+
+impl<S: StateMachine> StateMachine for Batches<S> {
+    fn apply(&mut self, command: &Value) -> Result<Value, Error> {
+        let commands = command["commands"]
+            .as_array()
+            .ok_or_else(|| Error::malformed("a batch without commands"))?;
+        let replies: Vec<Value> = commands
+            .iter()
+            .map(|command| {
+                self.machine
+                    .apply(command)
+                    .unwrap_or_else(|e| json!({"type": "error", "code": e.code, "text": e.text}))
+            })
+            .collect();
+        Ok(json!({"type": BATCH_OK, "replies": replies}))
+    }
+}
+
+/// A command waiting for its batch to be applied.
+type Pending = (Value, oneshot::Sender<Result<Value, Error>>);
+
+/// A server's sequencer, and its replica of `S`, running once [`Sequencer::install`]ed.
+///
+/// Clones share the replica.
+pub struct Sequencer<S> {
+    raft: Raft<Batches<S>>,
+    pending: Arc<Mutex<Vec<Pending>>>,
+}
+
+impl<S> Clone for Sequencer<S> {
+    fn clone(&self) -> Self {
+        Self {
+            raft: self.raft.clone(),
+            pending: self.pending.clone(),
+        }
+    }
+}
+
+impl<S: fmt::Debug> fmt::Debug for Sequencer<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sequencer")
+            .field("raft", &self.raft)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<S: StateMachine> Sequencer<S> {
+    pub fn new(machine: S) -> Self {
+        Self {
+            raft: Raft::new(Batches { machine }),
+            pending: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// The log the batches go through.
+    #[must_use]
+    pub const fn raft(&self) -> &Raft<Batches<S>> {
+        &self.raft
+    }
+
+    /// Reads this server's copy of the state machine, which may lag behind the leader's.
+    pub fn read<T>(&self, f: impl FnOnce(&S) -> T) -> T {
+        self.raft.read(|batches| f(&batches.machine))
+    }
+
+    /// Registers the handlers of the log, and of the client requests of every type in
+    /// `client_types`, and wraps the `init` handler so epochs start once the server knows its
+    /// peers.
+    ///
+    /// # Panics
+    /// Panics if `handlers` has no `init` handler, see [`crate::build_default_handlers`].
+    pub fn install(
+        &self,
+        handlers: &mut HandlersMap<dyn Server + Send + Sync>,
+        client_types: &[&'static str],
+    ) {
+        self.raft.install(handlers, &[BATCH]);
+
+        for &r#type in client_types {
+            let pending = self.pending.clone();
+            handlers.insert(
+                r#type,
+                Arc::new(move |srv_mutex, msg| {
+                    let pending = pending.clone();
+                    Box::pin(async move {
+                        let msg = consensus::parse(msg)?;
+                        let (tx, rx) = oneshot::channel();
+                        pending.lock().unwrap().push((msg.body.payload.clone(), tx));
+                        let reply = rx
+                            .await
+                            .map_err(|_| Error::crash("the sequencer dropped the request"))??;
+                        consensus::respond(&srv_mutex, &msg, reply)
+                    })
+                }),
+            );
+        }
+
+        let init = handlers
+            .get("init")
+            .cloned()
+            .expect("sequencer installed without an init handler");
+        let sequencer = self.clone();
+        handlers.insert(
+            "init",
+            Arc::new(move |srv_mutex, msg| {
+                let (init, sequencer) = (init.clone(), sequencer.clone());
+                Box::pin(async move {
+                    let started = !srv_mutex.lock().unwrap().get_id().is_empty();
+                    init(srv_mutex.clone(), msg).await?;
+                    if !started {
+                        let shutdown = srv_mutex.lock().unwrap().get_shutdown();
+                        shutdown.spawn(sequencer.run(srv_mutex));
+                    }
+                    Ok(())
+                })
+            }),
+        );
+    }
+
+    /// Sequences what came in during each [`EPOCH`], until the server shuts down.
+    async fn run(self, srv_mutex: Srv) {
+        let shutdown = srv_mutex.lock().unwrap().get_shutdown();
+        let mut epochs = time::interval(EPOCH);
+        epochs.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                () = shutdown.triggered() => return,
+                _ = epochs.tick() => {}
+            }
+            let batch = std::mem::take(&mut *self.pending.lock().unwrap());
+            if batch.is_empty() {
+                continue;
+            }
+            let (commands, waiters): (Vec<Value>, Vec<_>) = batch.into_iter().unzip();
+            let (raft, srv_mutex) = (self.raft.clone(), srv_mutex.clone());
+            shutdown.spawn(async move {
+                let command = json!({"type": BATCH, "commands": commands});
+                match raft.propose(&srv_mutex, command).await {
+                    Ok(reply) => {
+                        let replies = reply["replies"].as_array().cloned().unwrap_or_default();
+                        for (waiter, reply) in waiters.into_iter().zip(replies) {
+                            let result = Error::from_body(&reply).map_or(Ok(reply), Err);
+                            let _ = waiter.send(result);
+                        }
+                    }
+                    Err(e) => {
+                        for waiter in waiters {
+                            let _ = waiter.send(Err(e.clone()));
+                        }
+                    }
+                }
+            });
+        }
+    }
+}
@@ -0,0 +1,138 @@
+//! Offline checkers for Maelstrom workloads.
+//!
+//! Each checker reads a [`History`] of client operations and returns a [`Report`], listing the
+//! operations behind every anomaly it found.
+
+pub mod broadcast;
+pub mod echo;
+pub mod edn;
+pub mod g_counter;
+mod graph;
+pub mod history;
+pub mod kafka;
+pub mod linearizable;
+pub mod txn_list_append;
+pub mod txn_rw_register;
+pub mod unique_ids;
+
+use serde::Serialize;
+
+pub use history::{History, Op, OpType, Pair};
+
+/// How many operations of a history ended which way.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct OpCounts {
+    pub ok: usize,
+    pub fail: usize,
+    pub info: usize,
+}
+
+impl OpCounts {
+    #[must_use]
+    pub fn of(pairs: &[Pair<'_>]) -> Self {
+        let mut counts = Self::default();
+        for pair in pairs {
+            match pair.outcome() {
+                OpType::Ok => counts.ok += 1,
+                OpType::Fail => counts.fail += 1,
+                OpType::Info | OpType::Invoke => counts.info += 1,
+            }
+        }
+        counts
+    }
+}
+
+/// Something a history shouldn't contain.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Anomaly {
+    /// Short kebab-case name, e.g. `duplicate-id`.
+    pub kind: &'static str,
+    pub message: String,
+    /// The operations involved.
+    pub ops: Vec<Op>,
+}
+
+impl Anomaly {
+    #[must_use]
+    pub fn new(kind: &'static str, message: impl Into<String>, ops: Vec<Op>) -> Self {
+        Self {
+            kind,
+            message: message.into(),
+            ops,
+        }
+    }
+}
+
+/// The invocations and completions of the `pairs` at positions `txns`.
+fn ops_of(pairs: &[Pair<'_>], txns: &[usize]) -> Vec<Op> {
+    txns.iter()
+        .flat_map(|&i| std::iter::once(pairs[i].invoke).chain(pairs[i].completion))
+        .cloned()
+        .collect()
+}
+
+/// Outcome of checking a history.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Report {
+    pub valid: bool,
+    pub counts: OpCounts,
+    pub anomalies: Vec<Anomaly>,
+    /// Workload-specific figures, e.g. broadcast's stale reads.
+    pub stats: serde_json::Value,
+}
+
+impl Report {
+    /// A report that is valid when it has no anomalies.
+    #[must_use]
+    pub const fn new(counts: OpCounts, anomalies: Vec<Anomaly>) -> Self {
+        Self {
+            valid: anomalies.is_empty(),
+            counts,
+            anomalies,
+            stats: serde_json::Value::Null,
+        }
+    }
+
+    #[must_use]
+    pub fn with_stats(mut self, stats: serde_json::Value) -> Self {
+        self.stats = stats;
+        self
+    }
+}
+
+impl std::fmt::Display for Report {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "valid: {}, ok: {}, fail: {}, info: {}",
+            self.valid, self.counts.ok, self.counts.fail, self.counts.info
+        )?;
+        for anomaly in &self.anomalies {
+            writeln!(f, "{}: {}", anomaly.kind, anomaly.message)?;
+            for op in &anomaly.ops {
+                writeln!(
+                    f,
+                    "  {} {} {:?} {} {}",
+                    op.index, op.process, op.kind, op.f, op.value
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Records `f` on `node` by `process`, from `start` to `end` milliseconds, for the checkers'
+/// tests.
+#[cfg(test)]
+fn op(
+    history: &mut History,
+    process: u64,
+    node: &str,
+    (start, end): (u64, u64),
+    request: serde_json::Value,
+    (kind, reply): (OpType, serde_json::Value),
+) {
+    let f = request["type"].as_str().unwrap().to_string();
+    history.invoke(process, &f, request, Some(node), start * 1_000_000);
+    history.complete(process, kind, reply, end * 1_000_000);
+}
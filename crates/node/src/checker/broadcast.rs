@@ -0,0 +1,220 @@
+//! Every acknowledged `broadcast` is eventually read by every node, nothing is read that wasn't
+//! broadcast, and reads missing a message that was already acknowledged are counted as stale.
+//!
+//! The last successful read on each node is taken as its final state, so histories should end
+//! with a read of every node once the cluster had time to converge.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use serde_json::json;
+
+use super::{Anomaly, History, OpCounts, Pair, Report};
+
+/// Messages are compared by their JSON text, they aren't necessarily integers.
+fn messages(reply: &serde_json::Value) -> HashSet<String> {
+    reply["messages"]
+        .as_array()
+        .map(|messages| messages.iter().map(ToString::to_string).collect())
+        .unwrap_or_default()
+}
+
+fn percentile(sorted: &[u64], p: usize) -> Option<u64> {
+    (!sorted.is_empty()).then(|| sorted[(sorted.len() - 1) * p / 100])
+}
+
+/// Reports acknowledged messages some final read misses, returns how many there are.
+fn lost_messages(
+    acked: &BTreeMap<u64, Vec<(String, &Pair<'_>)>>,
+    finals: &[&(Pair<'_>, HashSet<String>)],
+    anomalies: &mut Vec<Anomaly>,
+) -> usize {
+    let mut acked_in_order: Vec<_> = acked.values().flatten().collect();
+    acked_in_order.sort_by_key(|(_, pair)| pair.invoke.index);
+    let mut lost = 0;
+    for (message, broadcast) in acked_in_order {
+        let missing: Vec<_> = finals
+            .iter()
+            .filter(|(_, seen)| !seen.contains(message))
+            .collect();
+        if missing.is_empty() {
+            continue;
+        }
+        lost += 1;
+        let mut ops = vec![broadcast.invoke.clone()];
+        ops.extend(broadcast.completion.cloned());
+        ops.extend(
+            missing
+                .iter()
+                .filter_map(|(read, _)| read.completion.cloned()),
+        );
+        anomalies.push(Anomaly::new(
+            "lost-message",
+            format!(
+                "{message} was acknowledged but {} final reads miss it",
+                missing.len()
+            ),
+            ops,
+        ));
+    }
+    lost
+}
+
+#[must_use]
+pub fn check(history: &History) -> Report {
+    let broadcasts = history.pairs_of("broadcast");
+    let all_reads = history.pairs_of("read");
+    let reads: Vec<(Pair<'_>, HashSet<String>)> = all_reads
+        .iter()
+        .copied()
+        .filter_map(|pair| Some((pair, messages(pair.reply()?))))
+        .collect();
+    let mut anomalies = Vec::new();
+
+    // when each message was first sent, and acknowledged if it was
+    let mut sent: HashMap<String, &Pair<'_>> = HashMap::new();
+    let mut acked: BTreeMap<u64, Vec<(String, &Pair<'_>)>> = BTreeMap::new();
+    for pair in &broadcasts {
+        let message = pair.invoke.value["message"].to_string();
+        if pair.reply().is_some() {
+            acked
+                .entry(pair.end())
+                .or_default()
+                .push((message.clone(), pair));
+        }
+        sent.entry(message).or_insert(pair);
+    }
+
+    for (read, seen) in &reads {
+        let mut unexpected: Vec<&String> = seen
+            .iter()
+            .filter(|message| !sent.contains_key(*message))
+            .collect();
+        if !unexpected.is_empty() {
+            unexpected.sort();
+            anomalies.push(Anomaly::new(
+                "unexpected-message",
+                format!("read {unexpected:?} which nobody broadcast"),
+                read.completion.into_iter().cloned().collect(),
+            ));
+        }
+    }
+
+    // a read is stale when it misses a message acknowledged before it started
+    let mut stale_reads = 0;
+    for (read, seen) in &reads {
+        let stale = acked
+            .range(..read.invoke.time)
+            .flat_map(|(_, messages)| messages)
+            .any(|(message, _)| !seen.contains(message));
+        stale_reads += usize::from(stale);
+    }
+
+    let mut finals: HashMap<Option<&str>, &(Pair<'_>, HashSet<String>)> = HashMap::new();
+    for read in &reads {
+        finals.insert(read.0.invoke.node.as_deref(), read);
+    }
+    let mut finals: Vec<_> = finals.into_values().collect();
+    finals.sort_by_key(|(read, _)| read.invoke.index);
+    let lost = lost_messages(&acked, &finals, &mut anomalies);
+
+    // how long after being sent a message shows up in every read for good
+    let mut stable_latencies: Vec<u64> = sent
+        .iter()
+        .filter(|(message, _)| finals.iter().all(|(_, seen)| seen.contains(*message)))
+        .map(|(message, broadcast)| {
+            let last_miss = reads
+                .iter()
+                .filter(|(_, seen)| !seen.contains(message))
+                .map(|(read, _)| read.end())
+                .max()
+                .unwrap_or(0);
+            last_miss.saturating_sub(broadcast.invoke.time)
+        })
+        .collect();
+    stable_latencies.sort_unstable();
+
+    let mut pairs = broadcasts.clone();
+    pairs.extend(all_reads.iter().copied());
+    let stats = json!({
+        "attempted": sent.len(),
+        "acknowledged": acked.values().map(Vec::len).sum::<usize>(),
+        "lost": lost,
+        "stale_reads": stale_reads,
+        "stable_latency_median": percentile(&stable_latencies, 50),
+        "stable_latency_max": percentile(&stable_latencies, 100),
+    });
+    Report::new(OpCounts::of(&pairs), anomalies).with_stats(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{Value, json};
+
+    use super::check;
+    use crate::checker::{History, OpType, op};
+
+    #[test]
+    fn catches_lost_and_stale_messages() {
+        let mut history = History::new();
+        let ack = (OpType::Ok, json!({"type": "broadcast_ok"}));
+        let read =
+            |messages: &[u64]| (OpType::Ok, json!({"type": "read_ok", "messages": messages}));
+        op(
+            &mut history,
+            0,
+            "n0",
+            (0, 1),
+            json!({"type": "broadcast", "message": 1}),
+            ack.clone(),
+        );
+        op(
+            &mut history,
+            0,
+            "n0",
+            (2, 3),
+            json!({"type": "broadcast", "message": 2}),
+            ack,
+        );
+        let timeout = (OpType::Info, Value::Null);
+        op(
+            &mut history,
+            0,
+            "n1",
+            (4, 5),
+            json!({"type": "broadcast", "message": 3}),
+            timeout,
+        );
+        op(
+            &mut history,
+            1,
+            "n1",
+            (6, 7),
+            json!({"type": "read"}),
+            read(&[1]),
+        );
+        op(
+            &mut history,
+            1,
+            "n0",
+            (8, 9),
+            json!({"type": "read"}),
+            read(&[1, 2]),
+        );
+        op(
+            &mut history,
+            1,
+            "n1",
+            (10, 11),
+            json!({"type": "read"}),
+            read(&[1]),
+        );
+
+        let report = check(&history);
+        assert!(!report.valid);
+        // 3 was never acknowledged, missing it is fine
+        assert_eq!(report.anomalies.len(), 1);
+        assert_eq!(report.anomalies[0].kind, "lost-message");
+        assert_eq!(report.stats["stale_reads"], 2);
+        assert_eq!(report.counts.info, 1);
+    }
+}
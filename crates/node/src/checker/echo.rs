@@ -0,0 +1,58 @@
+//! Every `echo_ok` carries back what its `echo` sent.
+
+use super::{Anomaly, History, OpCounts, Report};
+
+#[must_use]
+pub fn check(history: &History) -> Report {
+    let pairs = history.pairs_of("echo");
+    let anomalies = pairs
+        .iter()
+        .filter_map(|pair| {
+            let reply = pair.reply()?;
+            let completion = pair.completion?;
+            let sent = &pair.invoke.value["echo"];
+            (reply["echo"] != *sent).then(|| {
+                Anomaly::new(
+                    "wrong-echo",
+                    format!("sent {sent}, got back {}", reply["echo"]),
+                    vec![pair.invoke.clone(), completion.clone()],
+                )
+            })
+        })
+        .collect();
+    Report::new(OpCounts::of(&pairs), anomalies)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::check;
+    use crate::checker::{History, OpType, op};
+
+    #[test]
+    fn catches_wrong_replies() {
+        let mut history = History::new();
+        let ok = (OpType::Ok, json!({"type": "echo_ok", "echo": "a"}));
+        op(
+            &mut history,
+            0,
+            "n0",
+            (0, 1),
+            json!({"type": "echo", "echo": "a"}),
+            ok.clone(),
+        );
+        op(
+            &mut history,
+            0,
+            "n0",
+            (2, 3),
+            json!({"type": "echo", "echo": "b"}),
+            ok,
+        );
+        let report = check(&history);
+        assert!(!report.valid);
+        assert_eq!(report.anomalies.len(), 1);
+        assert_eq!(report.anomalies[0].ops[0].index, 2);
+    }
+}
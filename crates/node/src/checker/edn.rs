@@ -0,0 +1,254 @@
+//! Just enough EDN to read the `history.edn` Maelstrom leaves in `store/`.
+//!
+//! Values are converted to JSON on the way: keywords and symbols become strings without their
+//! colon, lists and sets become arrays, map keys that aren't strings become their JSON text and
+//! tags are dropped.
+
+use std::fmt;
+
+use serde_json::{Map, Number, Value};
+
+use super::history::{History, Op, OpType};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EdnError {
+    /// Byte offset of the problem in the input.
+    pub offset: usize,
+    pub message: String,
+}
+
+impl fmt::Display for EdnError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid edn at byte {}: {}", self.offset, self.message)
+    }
+}
+
+impl std::error::Error for EdnError {}
+
+struct Parser<'a> {
+    input: &'a str,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn error<T>(&self, message: impl Into<String>) -> Result<T, EdnError> {
+        Err(EdnError {
+            offset: self.pos,
+            message: message.into(),
+        })
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.input[self.pos..].chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    /// Skips whitespace, commas, comments and `#_` discarded forms.
+    fn skip_blank(&mut self) -> Result<(), EdnError> {
+        loop {
+            match self.peek() {
+                Some(c) if c.is_whitespace() || c == ',' => {
+                    self.bump();
+                }
+                Some(';') => while self.bump().is_some_and(|c| c != '\n') {},
+                Some('#') if self.input[self.pos..].starts_with("#_") => {
+                    self.pos += 2;
+                    self.value()?;
+                }
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    fn token(&mut self) -> &str {
+        let start = self.pos;
+        while self
+            .peek()
+            .is_some_and(|c| !c.is_whitespace() && !",()[]{}\"';".contains(c))
+        {
+            self.bump();
+        }
+        &self.input[start..self.pos]
+    }
+
+    fn until(&mut self, close: char) -> Result<Vec<Value>, EdnError> {
+        let mut values = Vec::new();
+        loop {
+            self.skip_blank()?;
+            match self.peek() {
+                None => return self.error(format!("missing `{close}`")),
+                Some(c) if c == close => {
+                    self.bump();
+                    return Ok(values);
+                }
+                Some(_) => values.push(self.value()?),
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<Value, EdnError> {
+        let mut s = String::new();
+        loop {
+            match self.bump() {
+                None => return self.error("unterminated string"),
+                Some('"') => return Ok(Value::String(s)),
+                Some('\\') => match self.bump() {
+                    Some('n') => s.push('\n'),
+                    Some('t') => s.push('\t'),
+                    Some('r') => s.push('\r'),
+                    Some(c) => s.push(c),
+                    None => return self.error("unterminated string"),
+                },
+                Some(c) => s.push(c),
+            }
+        }
+    }
+
+    fn atom(&mut self) -> Result<Value, EdnError> {
+        let start = self.pos;
+        let token = self.token().to_string();
+        if token.is_empty() {
+            return self.error("unexpected character");
+        }
+        Ok(match token.as_str() {
+            "nil" => Value::Null,
+            "true" => Value::Bool(true),
+            "false" => Value::Bool(false),
+            _ if token.starts_with(':') => Value::String(token[1..].to_string()),
+            _ if token.starts_with(|c: char| c.is_ascii_digit())
+                || (token.len() > 1 && token.starts_with(['-', '+'])) =>
+            {
+                let digits = token.trim_end_matches(['N', 'M']);
+                if let Ok(n) = digits.parse::<i64>() {
+                    n.into()
+                } else if let Some(n) = digits.parse::<f64>().ok().and_then(Number::from_f64) {
+                    Value::Number(n)
+                } else {
+                    self.pos = start;
+                    return self.error(format!("invalid number `{token}`"));
+                }
+            }
+            _ => Value::String(token),
+        })
+    }
+
+    fn value(&mut self) -> Result<Value, EdnError> {
+        self.skip_blank()?;
+        match self.peek() {
+            None => self.error("unexpected end of input"),
+            Some('"') => {
+                self.bump();
+                self.string()
+            }
+            Some('[') => {
+                self.bump();
+                Ok(Value::Array(self.until(']')?))
+            }
+            Some('(') => {
+                self.bump();
+                Ok(Value::Array(self.until(')')?))
+            }
+            Some('{') => {
+                self.bump();
+                let items = self.until('}')?;
+                if items.len() % 2 != 0 {
+                    return self.error("map with an odd number of forms");
+                }
+                let mut map = Map::new();
+                for pair in items.chunks(2) {
+                    let key = match &pair[0] {
+                        Value::String(s) => s.clone(),
+                        other => other.to_string(),
+                    };
+                    map.insert(key, pair[1].clone());
+                }
+                Ok(Value::Object(map))
+            }
+            Some('#') if self.input[self.pos..].starts_with("#{") => {
+                self.pos += 2;
+                Ok(Value::Array(self.until('}')?))
+            }
+            Some('#') => {
+                // a tagged value, e.g. `#jepsen.history.Op{...}`, only the value matters
+                self.bump();
+                self.token();
+                self.value()
+            }
+            Some('\\') => {
+                self.bump();
+                let c = self.token().to_string();
+                Ok(Value::String(c))
+            }
+            Some(_) => self.atom(),
+        }
+    }
+}
+
+/// Parses every top-level form of `input`.
+///
+/// # Errors
+/// - [`EdnError`] on anything that isn't well-formed EDN
+pub fn parse(input: &str) -> Result<Vec<Value>, EdnError> {
+    let mut parser = Parser { input, pos: 0 };
+    let mut values = Vec::new();
+    loop {
+        parser.skip_blank()?;
+        if parser.peek().is_none() {
+            return Ok(values);
+        }
+        values.push(parser.value()?);
+    }
+}
+
+/// Reads a Maelstrom `history.edn`, either one operation per form or a single vector of them.
+///
+/// Operations of the nemesis and other non-client processes are left out.
+///
+/// # Errors
+/// - [`EdnError`] on malformed EDN or operations missing their `:type`, `:f` or `:process`
+pub fn parse_history(input: &str) -> Result<History, EdnError> {
+    let mut forms = parse(input)?;
+    if let [Value::Array(ops)] = forms.as_mut_slice() {
+        forms = std::mem::take(ops);
+    }
+
+    let mut history = History::new();
+    for (i, form) in forms.into_iter().enumerate() {
+        let invalid = |message: &str| EdnError {
+            offset: 0,
+            message: format!("operation {i}: {message}"),
+        };
+        let Some(process) = form.get("process") else {
+            return Err(invalid("missing :process"));
+        };
+        let Some(process) = process.as_u64() else {
+            continue;
+        };
+        let kind = match form.get("type").and_then(Value::as_str) {
+            Some("invoke") => OpType::Invoke,
+            Some("ok") => OpType::Ok,
+            Some("fail") => OpType::Fail,
+            Some("info") => OpType::Info,
+            _ => return Err(invalid("missing or unknown :type")),
+        };
+        let f = form
+            .get("f")
+            .and_then(Value::as_str)
+            .ok_or_else(|| invalid("missing :f"))?;
+        history.push(Op {
+            index: 0,
+            process,
+            kind,
+            f: f.to_string(),
+            value: form.get("value").cloned().unwrap_or_default(),
+            node: None,
+            time: form.get("time").and_then(Value::as_u64).unwrap_or_default(),
+        });
+    }
+    Ok(history)
+}
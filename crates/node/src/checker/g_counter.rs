@@ -0,0 +1,184 @@
+//! Each node's final read falls between what the adds before it certainly did and what the adds
+//! that may precede it possibly did.
+//!
+//! Counters are only eventually consistent, so earlier reads may miss acknowledged adds: those
+//! are counted as stale rather than reported, as long as they stay within what the adds that
+//! may precede them could add up to.
+//!
+//! Deltas are signed so the same checker serves `pn-counter`.
+
+use std::collections::HashMap;
+
+use serde_json::json;
+
+use super::{Anomaly, History, OpCounts, OpType, Pair, Report};
+
+/// The smallest and largest values `read` can see, counting the adds acknowledged before it
+/// started as certain when `settled`, as possibly missed otherwise.
+fn bounds(adds: &[Pair<'_>], read: &Pair<'_>, settled: bool) -> (i64, i64) {
+    let (mut lower, mut upper) = (0, 0);
+    for add in adds {
+        let delta = add.invoke.value["delta"].as_i64().unwrap_or(0);
+        let done_before = add.outcome() == OpType::Ok && add.end() < read.invoke.time;
+        let may_precede = add.outcome() != OpType::Fail && add.invoke.time < read.end();
+        if done_before && settled {
+            lower += delta;
+            upper += delta;
+        } else if may_precede {
+            lower += delta.min(0);
+            upper += delta.max(0);
+        }
+    }
+    (lower, upper)
+}
+
+#[must_use]
+pub fn check(history: &History) -> Report {
+    let adds = history.pairs_of("add");
+    let reads = history.pairs_of("read");
+
+    let mut finals: HashMap<Option<&str>, usize> = HashMap::new();
+    for (i, read) in reads.iter().enumerate() {
+        if read.outcome() == OpType::Ok {
+            finals.insert(read.invoke.node.as_deref(), i);
+        }
+    }
+
+    let mut anomalies = Vec::new();
+    let mut stale_reads = 0;
+    for (i, read) in reads.iter().enumerate() {
+        let (Some(reply), Some(completion)) = (read.reply(), read.completion) else {
+            continue;
+        };
+        let value = reply["value"].as_i64();
+        let within = |(lower, upper)| value.is_some_and(|v| (lower..=upper).contains(&v));
+        let is_final = finals.get(&read.invoke.node.as_deref()) == Some(&i);
+        let (lower, upper) = bounds(&adds, read, is_final);
+        if !within((lower, upper)) {
+            let which = if is_final { "final read" } else { "read" };
+            anomalies.push(Anomaly::new(
+                "out-of-bounds-read",
+                format!(
+                    "{which} {}, expected between {lower} and {upper}",
+                    reply["value"]
+                ),
+                vec![read.invoke.clone(), completion.clone()],
+            ));
+        } else if !is_final && !within(bounds(&adds, read, true)) {
+            stale_reads += 1;
+        }
+    }
+    let last = finals
+        .values()
+        .max()
+        .and_then(|&i| reads[i].reply()?["value"].as_i64());
+
+    let acknowledged: i64 = adds
+        .iter()
+        .filter(|add| add.outcome() == OpType::Ok)
+        .filter_map(|add| add.invoke.value["delta"].as_i64())
+        .sum();
+    let attempted: i64 = adds
+        .iter()
+        .filter(|add| add.outcome() != OpType::Fail)
+        .filter_map(|add| add.invoke.value["delta"].as_i64())
+        .sum();
+    let mut pairs = adds;
+    pairs.extend(reads);
+    let stats = json!({
+        "acknowledged_total": acknowledged,
+        "attempted_total": attempted,
+        "final_read": last,
+        "stale_reads": stale_reads,
+    });
+    Report::new(OpCounts::of(&pairs), anomalies).with_stats(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{Value, json};
+
+    use super::check;
+    use crate::checker::{History, OpType, op};
+
+    #[test]
+    fn bounds_final_reads() {
+        let mut history = History::new();
+        let ack = (OpType::Ok, json!({"type": "add_ok"}));
+        let read = |value: u64| (OpType::Ok, json!({"type": "read_ok", "value": value}));
+        op(
+            &mut history,
+            0,
+            "n0",
+            (0, 1),
+            json!({"type": "add", "delta": 2}),
+            ack,
+        );
+        let timeout = (OpType::Info, Value::Null);
+        op(
+            &mut history,
+            1,
+            "n0",
+            (2, 3),
+            json!({"type": "add", "delta": 5}),
+            timeout,
+        );
+        // the add that timed out may or may not have happened
+        op(
+            &mut history,
+            2,
+            "n1",
+            (4, 5),
+            json!({"type": "read"}),
+            read(2),
+        );
+        op(
+            &mut history,
+            2,
+            "n1",
+            (6, 7),
+            json!({"type": "read"}),
+            read(7),
+        );
+        op(
+            &mut history,
+            2,
+            "n1",
+            (8, 9),
+            json!({"type": "read"}),
+            read(1),
+        );
+        // n1's final read, more than every add together
+        op(
+            &mut history,
+            2,
+            "n1",
+            (10, 11),
+            json!({"type": "read"}),
+            read(8),
+        );
+        // n2's only read is its final one, it may not miss the acknowledged add
+        op(
+            &mut history,
+            3,
+            "n2",
+            (10, 11),
+            json!({"type": "read"}),
+            read(0),
+        );
+
+        let report = check(&history);
+        let finals: Vec<_> = report.anomalies.iter().map(|a| &a.message).collect();
+        assert_eq!(
+            finals,
+            [
+                "final read 8, expected between 2 and 7",
+                "final read 0, expected between 2 and 7"
+            ]
+        );
+        // an earlier read missing the acknowledged add is only stale
+        assert_eq!(report.stats["stale_reads"], 1);
+        assert_eq!(report.stats["acknowledged_total"], 2);
+        assert_eq!(report.stats["attempted_total"], 7);
+    }
+}
@@ -0,0 +1,94 @@
+//! Dependency graphs between the transactions of a history, and the cycles in them.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Edges between transactions, by their position in the history's pairs.
+#[derive(Debug, Clone, Default)]
+pub struct Graph {
+    edges: BTreeMap<usize, BTreeSet<usize>>,
+}
+
+impl Graph {
+    /// Adds the edge `from -> to`, ignoring self-loops.
+    pub fn link(&mut self, from: usize, to: usize) {
+        if from != to {
+            self.edges.entry(from).or_default().insert(to);
+        }
+    }
+
+    pub fn union(&self, other: &Self) -> Self {
+        let mut union = self.clone();
+        for (&from, tos) in &other.edges {
+            union.edges.entry(from).or_default().extend(tos);
+        }
+        union
+    }
+
+    pub fn len(&self) -> usize {
+        self.edges.values().map(BTreeSet::len).sum()
+    }
+
+    fn successors(&self, node: usize) -> impl Iterator<Item = usize> + '_ {
+        self.edges.get(&node).into_iter().flatten().copied()
+    }
+
+    /// The strongly connected components with more than one transaction, each sorted: every
+    /// transaction in one depends on every other, so each holds a cycle.
+    ///
+    /// Kosaraju's, with explicit stacks so long dependency chains don't overflow.
+    pub fn cycles(&self) -> Vec<Vec<usize>> {
+        let mut reversed = Self::default();
+        let mut nodes = BTreeSet::new();
+        for (&from, tos) in &self.edges {
+            nodes.insert(from);
+            for &to in tos {
+                nodes.insert(to);
+                reversed.link(to, from);
+            }
+        }
+
+        // nodes by increasing finish time
+        let mut finished = Vec::new();
+        let mut visited = BTreeSet::new();
+        for &root in &nodes {
+            if !visited.insert(root) {
+                continue;
+            }
+            let mut stack = vec![(root, self.successors(root).collect::<Vec<_>>())];
+            while let Some((node, pending)) = stack.last_mut() {
+                if let Some(next) = pending.pop() {
+                    if visited.insert(next) {
+                        stack.push((next, self.successors(next).collect()));
+                    }
+                } else {
+                    finished.push(*node);
+                    stack.pop();
+                }
+            }
+        }
+
+        let mut assigned = BTreeSet::new();
+        let mut cycles = Vec::new();
+        for &root in finished.iter().rev() {
+            if !assigned.insert(root) {
+                continue;
+            }
+            let mut component = vec![root];
+            let mut stack = vec![root];
+            while let Some(node) = stack.pop() {
+                for next in reversed.successors(node) {
+                    if assigned.insert(next) {
+                        component.push(next);
+                        stack.push(next);
+                    }
+                }
+            }
+            if component.len() > 1 {
+                component.sort_unstable();
+                cycles.push(component);
+            }
+        }
+        cycles.sort();
+        cycles
+    }
+}
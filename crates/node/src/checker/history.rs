@@ -0,0 +1,166 @@
+//! Operation histories, in the shape Maelstrom and Jepsen record them.
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OpType {
+    /// A client started the operation.
+    Invoke,
+    /// The operation happened.
+    Ok,
+    /// The operation definitely didn't happen.
+    Fail,
+    /// Nobody knows whether the operation happened, e.g. it timed out.
+    Info,
+}
+
+/// One event of a history.
+///
+/// Invocations carry the request body as their value, `ok` completions the reply body and `fail`
+/// completions the error body. `info` completions carry nothing.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Op {
+    pub index: usize,
+    pub process: u64,
+    #[serde(rename = "type")]
+    pub kind: OpType,
+    /// The function called, e.g. `broadcast` or `read`.
+    pub f: String,
+    pub value: Value,
+    /// The server the request went to, when known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub node: Option<String>,
+    /// Nanoseconds since the start of the test.
+    pub time: u64,
+}
+
+impl Op {
+    #[must_use]
+    pub const fn is_invoke(&self) -> bool {
+        matches!(self.kind, OpType::Invoke)
+    }
+}
+
+/// An invocation together with its completion, if it ever completed.
+#[derive(Debug, Clone, Copy)]
+pub struct Pair<'a> {
+    pub invoke: &'a Op,
+    pub completion: Option<&'a Op>,
+}
+
+impl Pair<'_> {
+    /// The type of the completion, an operation that never completed is as good as `info`.
+    #[must_use]
+    pub fn outcome(&self) -> OpType {
+        self.completion.map_or(OpType::Info, |op| op.kind)
+    }
+
+    /// The reply body, for operations that completed `ok`.
+    #[must_use]
+    pub fn reply(&self) -> Option<&Value> {
+        self.completion
+            .filter(|op| op.kind == OpType::Ok)
+            .map(|op| &op.value)
+    }
+
+    /// When the operation completed, or `u64::MAX` if it may still be in flight.
+    #[must_use]
+    pub fn end(&self) -> u64 {
+        match self.completion {
+            Some(op) if op.kind != OpType::Info => op.time,
+            _ => u64::MAX,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct History {
+    ops: Vec<Op>,
+}
+
+impl History {
+    #[must_use]
+    pub const fn new() -> Self {
+        Self { ops: Vec::new() }
+    }
+
+    #[must_use]
+    pub fn ops(&self) -> &[Op] {
+        &self.ops
+    }
+
+    /// Appends `op`, renumbering it to its position in the history.
+    pub fn push(&mut self, mut op: Op) {
+        op.index = self.ops.len();
+        self.ops.push(op);
+    }
+
+    /// Records that `process` invoked `f` with `value` on `node` at `time`.
+    pub fn invoke(&mut self, process: u64, f: &str, value: Value, node: Option<&str>, time: u64) {
+        self.push(Op {
+            index: 0,
+            process,
+            kind: OpType::Invoke,
+            f: f.to_string(),
+            value,
+            node: node.map(str::to_string),
+            time,
+        });
+    }
+
+    /// Records the completion of the operation `process` has in flight.
+    ///
+    /// # Panics
+    /// Panics if `kind` is [`OpType::Invoke`] or `process` has nothing in flight.
+    pub fn complete(&mut self, process: u64, kind: OpType, value: Value, time: u64) {
+        assert_ne!(kind, OpType::Invoke, "a completion can't be an invocation");
+        let invoke = self
+            .ops
+            .iter()
+            .rev()
+            .find(|op| op.process == process)
+            .filter(|op| op.is_invoke())
+            .unwrap_or_else(|| panic!("process {process} has nothing in flight"))
+            .clone();
+        self.push(Op {
+            kind,
+            value,
+            time,
+            ..invoke
+        });
+    }
+
+    /// Matches every invocation with the next operation of the same process, which is its
+    /// completion since a process runs one operation at a time.
+    #[must_use]
+    pub fn pairs(&self) -> Vec<Pair<'_>> {
+        let mut in_flight: HashMap<u64, usize> = HashMap::new();
+        let mut pairs: Vec<Pair<'_>> = Vec::new();
+        for op in &self.ops {
+            if op.is_invoke() {
+                in_flight.insert(op.process, pairs.len());
+                pairs.push(Pair {
+                    invoke: op,
+                    completion: None,
+                });
+            } else if let Some(i) = in_flight.remove(&op.process) {
+                pairs[i].completion = Some(op);
+            }
+        }
+        pairs
+    }
+
+    /// Pairs of the calls to `f`.
+    #[must_use]
+    pub fn pairs_of(&self, f: &str) -> Vec<Pair<'_>> {
+        self.pairs()
+            .into_iter()
+            .filter(|pair| pair.invoke.f == f)
+            .collect()
+    }
+}
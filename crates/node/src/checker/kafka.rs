@@ -0,0 +1,377 @@
+//! Every key has a single log.
+//!
+//! An offset holds one message, polls see each key's messages in offset order, the messages at
+//! an offset are the ones sends put there, and a poll skips no acknowledged send between the
+//! first and last offsets it returned for a key.
+//!
+//! A send acknowledged only after a poll left its offset out isn't lost yet, but a consumer
+//! moving past it later, polling from a later offset or committing it, never sees it.
+//!
+//! Committed offsets aren't checked, Maelstrom only reports them too.
+
+use std::collections::{BTreeMap, HashMap};
+
+use serde_json::{Value, json};
+
+use super::{Anomaly, History, Op, OpCounts, OpType, Pair, Report};
+
+/// The `[offset, message]` entries a poll returned, per key.
+fn polled(reply: &Value) -> BTreeMap<&str, Vec<(u64, &Value)>> {
+    let Some(msgs) = reply["msgs"].as_object() else {
+        return BTreeMap::new();
+    };
+    msgs.iter()
+        .map(|(key, entries)| {
+            let entries = entries
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|entry| Some((entry[0].as_u64()?, &entry[1])))
+                .collect();
+            (key.as_str(), entries)
+        })
+        .collect()
+}
+
+fn ops_of(pairs: &[&Pair<'_>]) -> Vec<Op> {
+    pairs
+        .iter()
+        .flat_map(|pair| std::iter::once(pair.invoke).chain(pair.completion))
+        .cloned()
+        .collect()
+}
+
+/// The first acknowledged send at each offset of each key.
+type Logs<'h, 'p> = HashMap<&'h str, BTreeMap<u64, &'p Pair<'h>>>;
+
+fn logs<'h, 'p>(sends: &'p [Pair<'h>], anomalies: &mut Vec<Anomaly>) -> Logs<'h, 'p> {
+    let mut logs: Logs<'h, 'p> = HashMap::new();
+    for send in sends {
+        let (Some(reply), Some(key)) = (send.reply(), send.invoke.value["key"].as_str()) else {
+            continue;
+        };
+        let Some(offset) = reply["offset"].as_u64() else {
+            continue;
+        };
+        let log = logs.entry(key).or_default();
+        match log.get(&offset) {
+            Some(first) if first.invoke.value["msg"] != send.invoke.value["msg"] => {
+                anomalies.push(Anomaly::new(
+                    "duplicate-offset",
+                    format!("two sends to {key} got offset {offset}"),
+                    ops_of(&[first, send]),
+                ));
+            }
+            Some(_) => {}
+            None => {
+                log.insert(offset, send);
+            }
+        }
+    }
+    logs
+}
+
+/// Checks what `poll` returned for `key` against the `log` of acknowledged sends.
+fn check_polled(
+    poll: &Pair<'_>,
+    key: &str,
+    entries: &[(u64, &Value)],
+    log: Option<&BTreeMap<u64, &Pair<'_>>>,
+    sends: &[Pair<'_>],
+    anomalies: &mut Vec<Anomaly>,
+) {
+    if entries.windows(2).any(|w| w[0].0 >= w[1].0) {
+        anomalies.push(Anomaly::new(
+            "nonmonotonic-poll",
+            format!("{key}'s offsets aren't increasing"),
+            ops_of(&[poll]),
+        ));
+    }
+
+    for &(offset, msg) in entries {
+        match log.and_then(|log| log.get(&offset)) {
+            Some(send) if &send.invoke.value["msg"] != msg => {
+                let expected = &send.invoke.value["msg"];
+                anomalies.push(Anomaly::new(
+                    "inconsistent-offsets",
+                    format!("polled {msg} at {key}/{offset}, but {expected} was sent there"),
+                    ops_of(&[poll, send]),
+                ));
+            }
+            Some(_) => {}
+            // an unacknowledged send may still have made it
+            None if sends.iter().any(|send| {
+                send.outcome() != OpType::Fail
+                    && send.invoke.value["key"] == key
+                    && &send.invoke.value["msg"] == msg
+            }) => {}
+            None => anomalies.push(Anomaly::new(
+                "unexpected-message",
+                format!("polled {msg} at {key}/{offset}, which nobody sent"),
+                ops_of(&[poll]),
+            )),
+        }
+    }
+
+    let (Some(&(first, _)), Some(&(last, _)), Some(log)) = (entries.first(), entries.last(), log)
+    else {
+        return;
+    };
+    for (offset, send) in log.range(first..=last) {
+        // a send acknowledged after the poll started may not have been there yet
+        if send.end() < poll.invoke.time && !entries.iter().any(|e| e.0 == *offset) {
+            anomalies.push(Anomaly::new(
+                "lost-write",
+                format!("poll skipped {key}/{offset}"),
+                ops_of(&[poll, send]),
+            ));
+        }
+    }
+}
+
+/// Offsets a poll left out before they were acknowledged, by process, key and offset, along with
+/// the poll.
+type Missed<'h, 'p> = HashMap<u64, HashMap<&'h str, BTreeMap<u64, &'p Pair<'h>>>>;
+
+/// Reports the offsets a process moved past, with `op` at `positions`, once acknowledged.
+/// Commits go past the offset they commit, polls only up to the one they start from.
+fn moved_past<'h>(
+    missed: &mut Missed<'h, '_>,
+    logs: &Logs<'h, '_>,
+    op: &Pair<'h>,
+    positions: &'h Value,
+    anomalies: &mut Vec<Anomaly>,
+) {
+    let Some(missed) = missed.get_mut(&op.invoke.process) else {
+        return;
+    };
+    let commit = op.invoke.f == "commit_offsets";
+    for (key, position) in positions.as_object().into_iter().flatten() {
+        let (Some(position), Some(offsets)) = (position.as_u64(), missed.get_mut(key.as_str()))
+        else {
+            continue;
+        };
+        let past = if commit { position + 1 } else { position };
+        let skipped: Vec<u64> = offsets
+            .range(..past)
+            .map(|(&offset, _)| offset)
+            .filter(|offset| logs[key.as_str()][offset].end() < op.invoke.time)
+            .collect();
+        for offset in skipped {
+            let poll = offsets.remove(&offset).unwrap();
+            let send = logs[key.as_str()][&offset];
+            anomalies.push(Anomaly::new(
+                "skipped-offset",
+                format!(
+                    "process {} moved past {key}/{offset}, acknowledged after a poll left it out",
+                    op.invoke.process
+                ),
+                ops_of(&[poll, send, op]),
+            ));
+        }
+    }
+}
+
+/// Checks no process polls or commits past an offset its polls left out, once acknowledged.
+fn check_consumers<'h>(
+    polls: &[Pair<'h>],
+    commits: &[Pair<'h>],
+    logs: &Logs<'h, '_>,
+    anomalies: &mut Vec<Anomaly>,
+) {
+    let mut ops: Vec<&Pair<'h>> = polls.iter().chain(commits).collect();
+    ops.sort_by_key(|op| op.invoke.index);
+
+    let mut missed: Missed<'h, '_> = HashMap::new();
+    for op in ops {
+        moved_past(
+            &mut missed,
+            logs,
+            op,
+            &op.invoke.value["offsets"],
+            anomalies,
+        );
+        // borrowed from the history rather than the pair, like the keys it holds
+        let reply = op
+            .completion
+            .filter(|c| c.kind == OpType::Ok)
+            .map(|c| &c.value);
+        let Some(reply) = reply.filter(|_| op.invoke.f == "poll") else {
+            continue;
+        };
+        let process = missed.entry(op.invoke.process).or_default();
+        for (key, entries) in polled(reply) {
+            let (Some(from), Some(log)) = (op.invoke.value["offsets"][key].as_u64(), logs.get(key))
+            else {
+                continue;
+            };
+            let offsets = process.entry(key).or_default();
+            for &(offset, _) in &entries {
+                offsets.remove(&offset);
+            }
+            let Some(&(last, _)) = entries.last() else {
+                continue;
+            };
+            // sends acknowledged before the poll are lost writes already
+            for (&offset, send) in log.range(from..=last) {
+                if send.end() >= op.invoke.time && !entries.iter().any(|e| e.0 == offset) {
+                    offsets.insert(offset, op);
+                }
+            }
+        }
+    }
+}
+
+#[must_use]
+pub fn check(history: &History) -> Report {
+    let sends = history.pairs_of("send");
+    let polls = history.pairs_of("poll");
+
+    let mut anomalies = Vec::new();
+    let logs = logs(&sends, &mut anomalies);
+    let mut polled_msgs = 0;
+    for poll in &polls {
+        let Some(reply) = poll.reply() else {
+            continue;
+        };
+        for (key, entries) in polled(reply) {
+            polled_msgs += entries.len();
+            let log = logs.get(key);
+            check_polled(poll, key, &entries, log, &sends, &mut anomalies);
+        }
+    }
+
+    let commits = history.pairs_of("commit_offsets");
+    check_consumers(&polls, &commits, &logs, &mut anomalies);
+
+    let acknowledged: usize = logs.values().map(BTreeMap::len).sum();
+    let keys = logs.len();
+    drop(logs);
+    let mut pairs = sends;
+    pairs.extend(polls);
+    pairs.extend(commits);
+    pairs.extend(history.pairs_of("list_committed_offsets"));
+    let stats = json!({
+        "keys": keys,
+        "acknowledged_sends": acknowledged,
+        "polled_msgs": polled_msgs,
+    });
+    Report::new(OpCounts::of(&pairs), anomalies).with_stats(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{Value, json};
+
+    use super::check;
+    use crate::checker::{History, OpType, op};
+
+    #[test]
+    fn catches_reused_offsets_and_skipped_sends() {
+        let mut history = History::new();
+        let sent = |offset: u64| (OpType::Ok, json!({"type": "send_ok", "offset": offset}));
+        for (process, msg, offset) in [(0, 10, 0), (1, 11, 1), (2, 12, 1), (0, 13, 2)] {
+            op(
+                &mut history,
+                process,
+                "n0",
+                (process * 2, process * 2 + 1),
+                json!({"type": "send", "key": "k", "msg": msg}),
+                sent(offset),
+            );
+        }
+        let polled = |msgs: Value| (OpType::Ok, json!({"type": "poll_ok", "msgs": msgs}));
+        // skips offset 1
+        op(
+            &mut history,
+            3,
+            "n1",
+            (10, 11),
+            json!({"type": "poll", "offsets": {"k": 0}}),
+            polled(json!({"k": [[0, 10], [2, 13]]})),
+        );
+        // 99 was never sent
+        op(
+            &mut history,
+            3,
+            "n1",
+            (12, 13),
+            json!({"type": "poll", "offsets": {"k": 2}}),
+            polled(json!({"k": [[2, 13], [3, 99]]})),
+        );
+
+        let report = check(&history);
+        let kinds: Vec<&str> = report.anomalies.iter().map(|a| a.kind).collect();
+        assert_eq!(
+            kinds,
+            ["duplicate-offset", "lost-write", "unexpected-message"]
+        );
+        assert_eq!(report.stats["acknowledged_sends"], 3);
+    }
+
+    #[test]
+    fn catches_consumers_moving_past_offsets_acknowledged_late() {
+        let mut history = History::new();
+        let sent = |offset: u64| (OpType::Ok, json!({"type": "send_ok", "offset": offset}));
+        // offset 1 is only acknowledged once both polls are done
+        for (process, msg, offset, time) in
+            [(0, 10, 0, (0, 1)), (1, 11, 1, (2, 8)), (2, 12, 2, (3, 4))]
+        {
+            op(
+                &mut history,
+                process,
+                "n0",
+                time,
+                json!({"type": "send", "key": "k", "msg": msg}),
+                sent(offset),
+            );
+        }
+        let polled = (
+            OpType::Ok,
+            json!({"type": "poll_ok", "msgs": {"k": [[0, 10], [2, 12]]}}),
+        );
+        for process in [3, 4] {
+            op(
+                &mut history,
+                process,
+                "n1",
+                (5, 6),
+                json!({"type": "poll", "offsets": {"k": 0}}),
+                polled.clone(),
+            );
+        }
+        // 3 commits what it polled, 4 polls again from the offset it missed
+        op(
+            &mut history,
+            3,
+            "n1",
+            (9, 10),
+            json!({"type": "commit_offsets", "offsets": {"k": 2}}),
+            (OpType::Ok, json!({"type": "commit_offsets_ok"})),
+        );
+        op(
+            &mut history,
+            4,
+            "n1",
+            (9, 10),
+            json!({"type": "poll", "offsets": {"k": 1}}),
+            (
+                OpType::Ok,
+                json!({"type": "poll_ok", "msgs": {"k": [[1, 11], [2, 12]]}}),
+            ),
+        );
+        op(
+            &mut history,
+            4,
+            "n1",
+            (11, 12),
+            json!({"type": "poll", "offsets": {"k": 3}}),
+            (OpType::Ok, json!({"type": "poll_ok", "msgs": {}})),
+        );
+
+        let report = check(&history);
+        let kinds: Vec<&str> = report.anomalies.iter().map(|a| a.kind).collect();
+        assert_eq!(kinds, ["skipped-offset"], "{report}");
+        assert_eq!(report.anomalies[0].ops[4].process, 3);
+    }
+}
@@ -0,0 +1,449 @@
+//! Linearizability of key-value histories, by the Wing & Gong search with Lowe's memoization,
+//! like Knossos and Porcupine.
+//!
+//! Every key is an independent register, so histories are split by key and each key is checked
+//! on its own. Operations come in either shape:
+//! - Maelstrom's `lin-kv` workload, values being `[k v]` tuples, `[k [from to]]` for `cas`
+//! - request and reply bodies as recorded by the simulator, with `key`, `value`, `from` and `to`
+//!   fields, where a read failing with `key-does-not-exist` reads `nil`
+//!
+//! Failed operations didn't happen and are left out. Operations that may have happened (`info`)
+//! can take effect anywhere after their invocation, or never.
+
+use std::collections::{BTreeMap, HashSet};
+
+use serde_json::{Value, json};
+
+use super::history::{History, OpType, Pair};
+use super::{Anomaly, OpCounts, Report};
+use crate::error::ErrorCode;
+
+/// Values are compared by their JSON text, `None` is a key that doesn't exist.
+type State = Option<String>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Call {
+    /// Saw the given value.
+    Read(State),
+    Write(String),
+    Cas {
+        from: String,
+        to: String,
+        create_if_not_exists: bool,
+    },
+}
+
+impl Call {
+    /// The state after the call, or `None` if it can't happen in `state`.
+    fn step(&self, state: &State) -> Option<State> {
+        match self {
+            Self::Read(seen) => (seen == state).then(|| state.clone()),
+            Self::Write(value) => Some(Some(value.clone())),
+            Self::Cas {
+                from,
+                to,
+                create_if_not_exists,
+            } => (state.as_ref() == Some(from) || (*create_if_not_exists && state.is_none()))
+                .then(|| Some(to.clone())),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct KvOp<'a> {
+    pair: Pair<'a>,
+    call: Call,
+    start: u64,
+    /// `u64::MAX` for operations that may still take effect.
+    end: u64,
+}
+
+fn text(value: &Value) -> String {
+    value.to_string()
+}
+
+/// The key and call of `pair`, `None` if it didn't happen or isn't a kv operation.
+fn kv_op<'a>(pair: &Pair<'a>) -> Option<(String, KvOp<'a>)> {
+    let invoke = &pair.invoke.value;
+    let completion = pair.completion.map(|op| &op.value);
+    let outcome = pair.outcome();
+
+    let (key, call) = if let Value::Array(tuple) = invoke {
+        // Maelstrom: `[k v]`, reads complete with the value they saw
+        if outcome == OpType::Fail {
+            return None;
+        }
+        let key = text(tuple.first()?);
+        let call = match pair.invoke.f.as_str() {
+            "read" if outcome == OpType::Ok => {
+                let seen = completion
+                    .and_then(|value| value.get(1))
+                    .unwrap_or(&Value::Null);
+                Call::Read((!seen.is_null()).then(|| text(seen)))
+            }
+            "write" => Call::Write(text(tuple.get(1)?)),
+            "cas" => Call::Cas {
+                from: text(tuple.get(1)?.get(0)?),
+                to: text(tuple.get(1)?.get(1)?),
+                create_if_not_exists: false,
+            },
+            _ => return None,
+        };
+        (key, call)
+    } else {
+        let key = text(invoke.get("key")?);
+        let missing = outcome == OpType::Fail
+            && completion.and_then(|error| error["code"].as_u64())
+                == Some(ErrorCode::KeyDoesNotExist.code());
+        let call = match pair.invoke.f.as_str() {
+            "read" if missing => Call::Read(None),
+            "read" if outcome == OpType::Ok => Call::Read(Some(text(&pair.reply()?["value"]))),
+            _ if outcome == OpType::Fail => return None,
+            "write" => Call::Write(text(invoke.get("value")?)),
+            "cas" => Call::Cas {
+                from: text(invoke.get("from")?),
+                to: text(invoke.get("to")?),
+                create_if_not_exists: invoke["create_if_not_exists"].as_bool() == Some(true),
+            },
+            _ => return None,
+        };
+        (key, call)
+    };
+    // a read that may not have happened says nothing
+    if matches!(call, Call::Read(_)) && outcome != OpType::Ok && outcome != OpType::Fail {
+        return None;
+    }
+
+    let op = KvOp {
+        pair: *pair,
+        call,
+        start: pair.invoke.time,
+        end: if outcome == OpType::Info {
+            u64::MAX
+        } else {
+            pair.end()
+        },
+    };
+    Some((key, op))
+}
+
+/// Whether some order of `ops`, consistent with real time, is valid for a register starting
+/// out empty.
+fn linearizable(ops: &[KvOp<'_>]) -> bool {
+    // call and return events, calls first at equal times since those overlap
+    let mut events: Vec<(u64, bool, usize)> = Vec::new();
+    for (i, op) in ops.iter().enumerate() {
+        events.push((op.start, false, i));
+        if op.end != u64::MAX {
+            events.push((op.end, true, i));
+        }
+    }
+    events.sort_unstable();
+
+    // doubly linked list over the events, `head` being the sentinel
+    let head = events.len();
+    let mut next: Vec<usize> = (1..=events.len()).chain([0]).collect();
+    let mut prev: Vec<usize> = std::iter::once(head).chain(0..events.len()).collect();
+    let mut return_of = vec![None; ops.len()];
+    for (e, &(_, is_return, i)) in events.iter().enumerate() {
+        if is_return {
+            return_of[i] = Some(e);
+        }
+    }
+    let unlink = |next: &mut [usize], prev: &mut [usize], e: usize| {
+        next[prev[e]] = next[e];
+        prev[next[e]] = prev[e];
+    };
+    let relink = |next: &mut [usize], prev: &mut [usize], e: usize| {
+        next[prev[e]] = e;
+        prev[next[e]] = e;
+    };
+
+    let mut returns_left = return_of.iter().flatten().count();
+    let mut linearized = vec![0_u64; ops.len().div_ceil(64)];
+    let mut seen: HashSet<(Vec<u64>, State)> = HashSet::new();
+    let mut state: State = None;
+    let mut stack: Vec<(usize, State)> = Vec::new();
+    let mut e = next[head];
+    loop {
+        if returns_left == 0 {
+            return true;
+        }
+        let (_, is_return, i) = events[e];
+        if is_return {
+            // everything that could go before this return was tried, undo the last choice
+            let Some((call, previous)) = stack.pop() else {
+                return false;
+            };
+            let i = events[call].2;
+            state = previous;
+            linearized[i / 64] &= !(1 << (i % 64));
+            if let Some(ret) = return_of[i] {
+                relink(&mut next, &mut prev, ret);
+                returns_left += 1;
+            }
+            relink(&mut next, &mut prev, call);
+            e = next[call];
+            continue;
+        }
+
+        let stepped = ops[i].call.step(&state).filter(|after| {
+            let mut with = linearized.clone();
+            with[i / 64] |= 1 << (i % 64);
+            seen.insert((with, after.clone()))
+        });
+        let Some(after) = stepped else {
+            e = next[e];
+            continue;
+        };
+        linearized[i / 64] |= 1 << (i % 64);
+        stack.push((e, std::mem::replace(&mut state, after)));
+        unlink(&mut next, &mut prev, e);
+        if let Some(ret) = return_of[i] {
+            unlink(&mut next, &mut prev, ret);
+            returns_left -= 1;
+        }
+        e = next[head];
+    }
+}
+
+/// Shrinks the operations of a key that can't be linearized to a smaller set that can't either:
+/// the shortest prefix that fails, without the reads it doesn't need.
+///
+/// Both steps keep the result a genuine counterexample: a prefix of a linearizable history is
+/// linearizable, with the operations still running treated as `info`, and so is a linearizable
+/// history without one of its reads.
+fn counterexample<'a>(ops: &[KvOp<'a>]) -> Vec<KvOp<'a>> {
+    let prefix = |until: u64| -> Vec<KvOp<'a>> {
+        ops.iter()
+            .filter(|op| op.start <= until)
+            .map(|op| KvOp {
+                end: if op.end > until { u64::MAX } else { op.end },
+                ..op.clone()
+            })
+            .collect()
+    };
+    let mut returns: Vec<u64> = ops
+        .iter()
+        .map(|op| op.end)
+        .filter(|&end| end != u64::MAX)
+        .collect();
+    returns.sort_unstable();
+    returns.dedup();
+    let shortest = returns.partition_point(|&until| linearizable(&prefix(until)));
+    let mut ops = returns
+        .get(shortest)
+        .map_or_else(|| ops.to_vec(), |&until| prefix(until));
+
+    let mut i = 0;
+    while i < ops.len() {
+        if matches!(ops[i].call, Call::Read(_)) {
+            let op = ops.remove(i);
+            if linearizable(&ops) {
+                ops.insert(i, op);
+                i += 1;
+            }
+        } else {
+            i += 1;
+        }
+    }
+    ops
+}
+
+#[must_use]
+pub fn check(history: &History) -> Report {
+    let pairs: Vec<Pair<'_>> = history
+        .pairs()
+        .into_iter()
+        .filter(|pair| matches!(pair.invoke.f.as_str(), "read" | "write" | "cas"))
+        .collect();
+    let mut by_key: BTreeMap<String, Vec<KvOp<'_>>> = BTreeMap::new();
+    for pair in &pairs {
+        if let Some((key, op)) = kv_op(pair) {
+            by_key.entry(key).or_default().push(op);
+        }
+    }
+
+    let mut anomalies = Vec::new();
+    for (key, ops) in &by_key {
+        if linearizable(ops) {
+            continue;
+        }
+        let ops = counterexample(ops);
+        anomalies.push(Anomaly::new(
+            "nonlinearizable",
+            format!(
+                "key {key}: no valid order for these {} operations",
+                ops.len()
+            ),
+            ops.iter()
+                .flat_map(|op| [Some(op.pair.invoke), op.pair.completion])
+                .flatten()
+                .cloned()
+                .collect(),
+        ));
+    }
+    let stats = json!({"keys": by_key.len(), "ops": by_key.values().map(Vec::len).sum::<usize>()});
+    Report::new(OpCounts::of(&pairs), anomalies).with_stats(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{Value, json};
+
+    use super::check;
+    use crate::checker::edn::parse_history;
+    use crate::checker::{History, OpType, op};
+
+    fn ok(value: Value) -> (OpType, Value) {
+        (OpType::Ok, value)
+    }
+
+    #[test]
+    fn accepts_concurrent_operations_in_some_order() {
+        let mut history = History::new();
+        op(
+            &mut history,
+            0,
+            "n0",
+            (0, 10),
+            json!({"type": "write", "key": 1, "value": 1}),
+            ok(json!({})),
+        );
+        op(
+            &mut history,
+            1,
+            "n0",
+            (1, 9),
+            json!({"type": "write", "key": 1, "value": 2}),
+            ok(json!({})),
+        );
+        op(
+            &mut history,
+            2,
+            "n0",
+            (2, 3),
+            json!({"type": "read", "key": 1}),
+            ok(json!({"value": 2})),
+        );
+        op(
+            &mut history,
+            2,
+            "n0",
+            (4, 5),
+            json!({"type": "read", "key": 1}),
+            ok(json!({"value": 1})),
+        );
+        op(
+            &mut history,
+            3,
+            "n0",
+            (11, 12),
+            json!({"type": "cas", "key": 1, "from": 1, "to": 3}),
+            ok(json!({})),
+        );
+        let missing = (OpType::Fail, json!({"code": 20}));
+        op(
+            &mut history,
+            3,
+            "n0",
+            (13, 14),
+            json!({"type": "read", "key": 2}),
+            missing,
+        );
+        let report = check(&history);
+        assert!(report.valid, "{report}");
+        assert_eq!(report.stats["keys"], 2);
+    }
+
+    #[test]
+    fn operations_that_timed_out_may_or_may_not_happen() {
+        let mut history = History::new();
+        let timeout = (OpType::Info, Value::Null);
+        op(
+            &mut history,
+            0,
+            "n0",
+            (0, 1),
+            json!({"type": "write", "key": 0, "value": 1}),
+            timeout.clone(),
+        );
+        op(
+            &mut history,
+            1,
+            "n0",
+            (2, 3),
+            json!({"type": "write", "key": 0, "value": 2}),
+            timeout,
+        );
+        op(
+            &mut history,
+            2,
+            "n0",
+            (4, 5),
+            json!({"type": "read", "key": 0}),
+            ok(json!({"value": 2})),
+        );
+        op(
+            &mut history,
+            2,
+            "n0",
+            (6, 7),
+            json!({"type": "read", "key": 0}),
+            ok(json!({"value": 1})),
+        );
+        assert!(check(&history).valid);
+    }
+
+    #[test]
+    fn shrinks_stale_reads_to_a_counterexample() {
+        let mut history = History::new();
+        let write = |value: u64| json!({"type": "write", "key": "x", "value": value});
+        let read = json!({"type": "read", "key": "x"});
+        op(&mut history, 0, "n0", (0, 1), write(1), ok(json!({})));
+        op(
+            &mut history,
+            0,
+            "n0",
+            (2, 3),
+            read.clone(),
+            ok(json!({"value": 1})),
+        );
+        op(&mut history, 0, "n0", (4, 5), write(2), ok(json!({})));
+        op(
+            &mut history,
+            1,
+            "n0",
+            (6, 7),
+            read.clone(),
+            ok(json!({"value": 1})),
+        );
+        op(&mut history, 1, "n0", (8, 9), read, ok(json!({"value": 2})));
+        op(&mut history, 0, "n0", (10, 11), write(3), ok(json!({})));
+
+        let report = check(&history);
+        assert!(!report.valid);
+        // both writes and the stale read, as invocations and completions
+        let ops: Vec<usize> = report.anomalies[0].ops.iter().map(|op| op.index).collect();
+        assert_eq!(ops, [0, 1, 4, 5, 6, 7]);
+    }
+
+    #[test]
+    fn reads_maelstrom_histories() {
+        let edn = r"
+            {:type :invoke, :f :write, :value [0 3], :time 10, :process 0, :index 0}
+            {:type :invoke, :f :cas, :value [0 [3 4]], :time 11, :process 1, :index 1}
+            {:type :info, :f :start-partition, :value nil, :time 12, :process :nemesis}
+            {:type :ok, :f :write, :value [0 3], :time 20, :process 0, :index 2}
+            {:type :ok, :f :cas, :value [0 [3 4]], :time 21, :process 1, :index 3}
+            {:type :invoke, :f :read, :value [0 nil], :time 30, :process 0, :index 4}
+            {:type :ok, :f :read, :value [0 3], :time 40, :process 0, :index 5}
+            ; the cas took effect before the read started
+        ";
+        let history = parse_history(edn).unwrap();
+        assert_eq!(history.ops().len(), 6);
+        let report = check(&history);
+        assert!(!report.valid);
+        assert_eq!(report.anomalies[0].ops.len(), 6);
+    }
+}
@@ -0,0 +1,390 @@
+//! Transactions over lists are strictly serializable, after Elle's list-append analysis.
+//!
+//! Values appended to a key are unique and reads return whole lists, so a read tells which
+//! transactions appended to the key and in which order their appends took effect. Every read
+//! of a key should be a prefix of its longest read, the version order of the key. Between
+//! transactions, that gives dependencies:
+//!
+//! - ww: one appended the element right after the other's in a version order
+//! - wr: one read a list ending with the other's append
+//! - rw: one read a list the other's append came right after, an anti-dependency
+//! - rt: one completed before the other was invoked
+//!
+//! and anomalies:
+//!
+//! - G0, dirty writes: a cycle of ww dependencies
+//! - G1a, aborted reads: reading an append of a transaction that failed
+//! - G1b, intermediate reads: reading a list ending with an append its transaction followed
+//!   with another to the same key
+//! - G1c, circular information flow: a cycle of ww and wr dependencies
+//! - G2, anti-dependency cycles: a cycle that needs rw dependencies, no serial order explains
+//!   what the transactions read
+//! - G-realtime: a cycle that needs rt dependencies, serializable but not strictly
+//!
+//! besides reads that aren't prefixes of one another, that repeat or make up elements, or that
+//! miss their own transaction's appends.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+use serde_json::json;
+
+use super::graph::Graph;
+use super::{Anomaly, History, OpCounts, OpType, Pair, Report, ops_of};
+use crate::txn::list_append::ListOp;
+
+/// The micro-ops of `pair`: what an `ok` transaction replied, what the others asked for.
+fn micro_ops(pair: &Pair<'_>) -> Vec<ListOp> {
+    let value = pair.reply().unwrap_or(&pair.invoke.value);
+    serde_json::from_value(value["txn"].clone()).unwrap_or_default()
+}
+
+/// Who appended each value to each key.
+#[derive(Debug, Default)]
+struct Appends {
+    /// By transactions that didn't fail.
+    writers: HashMap<(u64, u64), usize>,
+    /// Appends their transaction followed with another to the same key.
+    intermediate: HashSet<(u64, u64)>,
+    aborted: HashMap<(u64, u64), usize>,
+}
+
+impl Appends {
+    fn of(pairs: &[Pair<'_>], txns: &[Vec<ListOp>]) -> Self {
+        let mut appends = Self::default();
+        for (i, (pair, txn)) in pairs.iter().zip(txns).enumerate() {
+            let mut last: HashMap<u64, u64> = HashMap::new();
+            for op in txn {
+                let &ListOp::Append { key, value } = op else {
+                    continue;
+                };
+                if let Some(previous) = last.insert(key, value) {
+                    appends.intermediate.insert((key, previous));
+                }
+                let writers = if pair.outcome() == OpType::Fail {
+                    &mut appends.aborted
+                } else {
+                    &mut appends.writers
+                };
+                writers.insert((key, value), i);
+            }
+        }
+        appends
+    }
+}
+
+/// The reads of `ok` transactions: who read which list of which key.
+fn reads<'a>(
+    pairs: &[Pair<'_>],
+    txns: &'a [Vec<ListOp>],
+) -> impl Iterator<Item = (usize, u64, &'a [u64])> {
+    let ok: Vec<bool> = pairs.iter().map(|p| p.outcome() == OpType::Ok).collect();
+    txns.iter()
+        .enumerate()
+        .filter(move |(i, _)| ok[*i])
+        .flat_map(|(i, txn)| {
+            txn.iter().filter_map(move |op| match op {
+                ListOp::Read {
+                    key,
+                    value: Some(list),
+                } => Some((i, *key, list.as_slice())),
+                _ => None,
+            })
+        })
+}
+
+/// The version order of every key read, its longest read, along with who read it. Reads that
+/// aren't a prefix of it are anomalies.
+fn version_orders(
+    pairs: &[Pair<'_>],
+    txns: &[Vec<ListOp>],
+    anomalies: &mut Vec<Anomaly>,
+) -> BTreeMap<u64, (usize, Vec<u64>)> {
+    let mut orders: BTreeMap<u64, (usize, Vec<u64>)> = BTreeMap::new();
+    for (i, key, list) in reads(pairs, txns) {
+        let longest = orders.entry(key).or_insert_with(|| (i, Vec::new()));
+        if list.len() > longest.1.len() {
+            *longest = (i, list.to_vec());
+        }
+    }
+    for (i, key, list) in reads(pairs, txns) {
+        let (reader, order) = &orders[&key];
+        if !order.starts_with(list) {
+            anomalies.push(Anomaly::new(
+                "incompatible-order",
+                format!("read {list:?} from {key}, which isn't a prefix of {order:?}"),
+                ops_of(pairs, &[*reader, i]),
+            ));
+        }
+    }
+    orders
+}
+
+/// Links `ok` transactions to those invoked after they completed, leaving out the links implied
+/// by others, as Elle does: each transaction is linked from the frontier of those that completed
+/// last before its invocation, and one completing replaces the frontier it was linked from. The
+/// edges grow with the concurrency rather than the square of the history.
+fn realtime(pairs: &[Pair<'_>]) -> Graph {
+    // invocations before completions at the same time, those don't come strictly after
+    let mut events: Vec<(u64, bool, usize)> = Vec::new();
+    for (i, pair) in pairs.iter().enumerate() {
+        match pair.outcome() {
+            OpType::Fail => continue,
+            OpType::Ok => events.push((pair.end(), true, i)),
+            _ => {}
+        }
+        events.push((pair.invoke.time, false, i));
+    }
+    events.sort_unstable();
+
+    let mut rt = Graph::default();
+    let mut frontier: BTreeSet<usize> = BTreeSet::new();
+    let mut preceding: HashMap<usize, BTreeSet<usize>> = HashMap::new();
+    for (_, completed, i) in events {
+        if completed {
+            let before = preceding.remove(&i).unwrap_or_default();
+            frontier.retain(|j| !before.contains(j));
+            frontier.insert(i);
+        } else {
+            for &j in &frontier {
+                rt.link(j, i);
+            }
+            preceding.insert(i, frontier.clone());
+        }
+    }
+    rt
+}
+
+/// Links the appenders of consecutive elements in every version order.
+fn write_order(orders: &BTreeMap<u64, (usize, Vec<u64>)>, appends: &Appends) -> Graph {
+    let mut ww = Graph::default();
+    for (key, (_, order)) in orders {
+        let writers: Vec<Option<usize>> = order
+            .iter()
+            .map(|&value| appends.writers.get(&(*key, value)).copied())
+            .collect();
+        for pair in writers.windows(2) {
+            if let &[Some(from), Some(to)] = pair {
+                ww.link(from, to);
+            }
+        }
+    }
+    ww
+}
+
+/// The cycles of the ww, wr, rw and rt graphs, each named after the weakest dependencies that
+/// close it.
+fn cycles(pairs: &[Pair<'_>], [ww, wr, rw, rt]: [&Graph; 4]) -> Vec<Anomaly> {
+    let g1 = ww.union(wr);
+    let g2 = g1.union(rw);
+    let graphs = [
+        ("G0", ww, "append after"),
+        ("G1c", &g1, "read from or append after"),
+        ("G2", &g2, "read from, append after or miss the appends of"),
+        (
+            "G-realtime",
+            &g2.union(rt),
+            "depend on or come in real time after",
+        ),
+    ];
+    let mut found = Vec::new();
+    let mut anomalies = Vec::new();
+    for (kind, graph, relation) in graphs {
+        for cycle in graph.cycles() {
+            if found.contains(&cycle) {
+                continue;
+            }
+            anomalies.push(Anomaly::new(
+                kind,
+                format!("transactions {cycle:?} {relation} each other in a cycle"),
+                ops_of(pairs, &cycle),
+            ));
+            found.push(cycle);
+        }
+    }
+    anomalies
+}
+
+#[must_use]
+pub fn check(history: &History) -> Report {
+    let pairs = history.pairs_of("txn");
+    let txns: Vec<Vec<ListOp>> = pairs.iter().map(micro_ops).collect();
+    let appends = Appends::of(&pairs, &txns);
+    let mut anomalies = Vec::new();
+    let orders = version_orders(&pairs, &txns, &mut anomalies);
+
+    let ww = write_order(&orders, &appends);
+    let (mut wr, mut rw) = (Graph::default(), Graph::default());
+
+    for (i, txn) in txns.iter().enumerate() {
+        if pairs[i].outcome() != OpType::Ok {
+            continue;
+        }
+        let mut own: HashMap<u64, Vec<u64>> = HashMap::new();
+        for op in txn {
+            let (key, list) = match op {
+                ListOp::Append { key, value } => {
+                    own.entry(*key).or_default().push(*value);
+                    continue;
+                }
+                ListOp::Read { key, value } => (*key, value.as_deref().unwrap_or_default()),
+            };
+            let distinct: HashSet<&u64> = list.iter().collect();
+            if distinct.len() != list.len() {
+                anomalies.push(Anomaly::new(
+                    "duplicate-elements",
+                    format!("read {list:?} from {key}"),
+                    ops_of(&pairs, &[i]),
+                ));
+            }
+            if let Some(appended) = own.get(&key) {
+                if !list.ends_with(appended) {
+                    anomalies.push(Anomaly::new(
+                        "internal",
+                        format!("read {list:?} from {key} after appending {appended:?} to it"),
+                        ops_of(&pairs, &[i]),
+                    ));
+                }
+                continue;
+            }
+
+            for &value in list {
+                if let Some(&writer) = appends.aborted.get(&(key, value)) {
+                    anomalies.push(Anomaly::new(
+                        "G1a",
+                        format!("read {value} from {key}, appended by a failed transaction"),
+                        ops_of(&pairs, &[writer, i]),
+                    ));
+                } else if !appends.writers.contains_key(&(key, value)) {
+                    anomalies.push(Anomaly::new(
+                        "garbage-read",
+                        format!("read {value} from {key}, which nobody appended"),
+                        ops_of(&pairs, &[i]),
+                    ));
+                }
+            }
+            if let Some(&last) = list.last()
+                && let Some(&writer) = appends.writers.get(&(key, last))
+            {
+                if appends.intermediate.contains(&(key, last)) {
+                    anomalies.push(Anomaly::new(
+                        "G1b",
+                        format!("read {list:?} from {key}, its transaction appended after {last}"),
+                        ops_of(&pairs, &[writer, i]),
+                    ));
+                } else {
+                    wr.link(writer, i);
+                }
+            }
+            // the append that came next is one the read missed
+            if let Some((_, order)) = orders.get(&key)
+                && order.starts_with(list)
+                && let Some(&next) = order.get(list.len())
+                && let Some(&writer) = appends.writers.get(&(key, next))
+            {
+                rw.link(i, writer);
+            }
+        }
+    }
+
+    let rt = realtime(&pairs);
+    anomalies.extend(cycles(&pairs, [&ww, &wr, &rw, &rt]));
+    let stats = json!({
+        "keys": orders.len(),
+        "ww_edges": ww.len(),
+        "wr_edges": wr.len(),
+        "rw_edges": rw.len(),
+        "rt_edges": rt.len(),
+    });
+    Report::new(OpCounts::of(&pairs), anomalies).with_stats(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::check;
+    use crate::checker::{History, OpType, op};
+
+    #[test]
+    fn names_cycles_by_their_dependencies() {
+        let mut history = History::new();
+        let txns = [
+            // 0 then 1 append to 1, as 2 reads
+            (json!([["append", 1, 1]]), OpType::Ok),
+            (json!([["append", 1, 2]]), OpType::Ok),
+            (json!([["r", 1, [1, 2]]]), OpType::Ok),
+            // 3 and 4 each miss the other's append, as 5 reads
+            (json!([["r", 2, []], ["append", 3, 1]]), OpType::Ok),
+            (json!([["r", 3, []], ["append", 2, 1]]), OpType::Ok),
+            (json!([["r", 2, [1]], ["r", 3, [1]]]), OpType::Ok),
+            // 6 failed, yet 7 reads its append
+            (json!([["append", 4, 9]]), OpType::Fail),
+            (json!([["r", 4, [9]]]), OpType::Ok),
+            // 10 and 11 disagree on the order of 8 and 9
+            (json!([["append", 5, 1]]), OpType::Ok),
+            (json!([["append", 5, 2]]), OpType::Ok),
+            (json!([["r", 5, [1, 2]]]), OpType::Ok),
+            (json!([["r", 5, [2]]]), OpType::Ok),
+            // placeholders keeping the times of the others apart
+            (json!([]), OpType::Ok),
+            (json!([]), OpType::Ok),
+            // 14 and 15 append to 7 and 8 in opposite orders, as 16 reads
+            (json!([["append", 7, 1], ["append", 8, 2]]), OpType::Ok),
+            (json!([["append", 8, 1], ["append", 7, 2]]), OpType::Ok),
+            (json!([["r", 7, [1, 2]], ["r", 8, [1, 2]]]), OpType::Ok),
+            // 18 reads what 17 appended before appending again
+            (json!([["append", 9, 1], ["append", 9, 2]]), OpType::Ok),
+            (json!([["r", 9, [1]]]), OpType::Ok),
+            (json!([["append", 10, 5], ["r", 10, []]]), OpType::Ok),
+            // 21 misses the append of 20, which completed before 21 began
+            (json!([["append", 6, 1]]), OpType::Ok),
+            (json!([["r", 6, []]]), OpType::Ok),
+            (json!([["r", 6, [1]]]), OpType::Ok),
+            // 23 and 24 each read the other's append
+            (json!([["append", 11, 1], ["r", 12, [1]]]), OpType::Ok),
+            (json!([["append", 12, 1], ["r", 11, [1]]]), OpType::Ok),
+        ];
+        for (process, (txn, kind)) in (0..).zip(txns) {
+            let reply = match kind {
+                OpType::Ok => json!({"type": "txn_ok", "txn": txn}),
+                _ => json!({"code": 30}),
+            };
+            op(
+                &mut history,
+                process,
+                "n0",
+                (process * 2, process * 2 + 1),
+                json!({"type": "txn", "txn": txn}),
+                (kind, reply),
+            );
+        }
+
+        let report = check(&history);
+        let kinds: Vec<&str> = report.anomalies.iter().map(|a| a.kind).collect();
+        assert_eq!(
+            kinds,
+            [
+                "incompatible-order",
+                "G1a",
+                "G1b",
+                "internal",
+                "G0",
+                "G1c",
+                "G2",
+                "G-realtime"
+            ],
+            "{report}"
+        );
+        let txns_of = |kind| {
+            let anomaly = report.anomalies.iter().find(|a| a.kind == kind).unwrap();
+            anomaly.ops.iter().map(|op| op.process).collect::<Vec<_>>()
+        };
+        assert_eq!(txns_of("G0"), [14, 14, 15, 15]);
+        assert_eq!(txns_of("G1c"), [23, 23, 24, 24]);
+        assert_eq!(txns_of("G2"), [3, 3, 4, 4]);
+        assert_eq!(txns_of("G-realtime"), [20, 20, 21, 21]);
+        assert_eq!(report.stats["keys"], 12);
+        // one after the other, each transaction is only linked from the one before it
+        assert_eq!(report.stats["rt_edges"], 23);
+    }
+}
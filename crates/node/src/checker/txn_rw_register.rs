@@ -0,0 +1,285 @@
+//! Transactions over registers stay within their isolation level.
+//!
+//! Values written to a key are unique, so a read names the transaction that wrote what it read:
+//! a write-read (wr) dependency. A transaction that reads a key and then writes it also comes
+//! after the writer of what it read on that key: a write-write (ww) dependency.
+//!
+//! Other write orders can't be told from reads: neither level promises monotonic reads, so the
+//! order successive reads see values in says nothing about the order the writes took effect in.
+//! Replies carrying `stamps`, as [`TxnExecutor::with_stamps`](crate::TxnExecutor::with_stamps)
+//! sends them, order every write of a key by its stamp instead, each writer a ww dependency of
+//! the one before. Without them, blind writes never get ww dependencies and a G0 cycle made only
+//! of them goes unnoticed.
+//!
+//! - G0, dirty writes: a cycle of ww dependencies
+//! - G1a, aborted reads: reading a write of a transaction that failed
+//! - G1b, intermediate reads: reading a write its transaction overwrote
+//! - G1c, circular information flow: a cycle of ww and wr dependencies
+//!
+//! Read uncommitted only rules out G0, read committed rules out all of them and expects
+//! transactions to read their own writes.
+
+use std::collections::HashMap;
+
+use serde_json::json;
+
+use super::graph::Graph;
+use super::{Anomaly, History, OpCounts, OpType, Pair, Report, ops_of};
+use crate::txn::{Isolation, MicroOp, Stamp};
+
+/// The micro-ops of `pair`: what an `ok` transaction replied, what the others asked for.
+fn micro_ops(pair: &Pair<'_>) -> Vec<MicroOp> {
+    let value = pair.reply().unwrap_or(&pair.invoke.value);
+    serde_json::from_value(value["txn"].clone()).unwrap_or_default()
+}
+
+/// The stamps `pair` replied with, none if it didn't.
+fn stamps(pair: &Pair<'_>) -> Vec<Option<Stamp>> {
+    pair.reply()
+        .and_then(|reply| serde_json::from_value(reply["stamps"].clone()).ok())
+        .unwrap_or_default()
+}
+
+/// Links the writers of every key in the order of their stamps.
+fn version_order(pairs: &[Pair<'_>], txns: &[Vec<MicroOp>], ww: &mut Graph) {
+    let mut versions: HashMap<u64, Vec<(Stamp, usize)>> = HashMap::new();
+    for (i, (pair, txn)) in pairs.iter().zip(txns).enumerate() {
+        for (op, stamp) in txn.iter().zip(stamps(pair)) {
+            if let (MicroOp::Write { key, .. }, Some(stamp)) = (op, stamp) {
+                versions.entry(*key).or_default().push((stamp, i));
+            }
+        }
+    }
+    for mut versions in versions.into_values() {
+        versions.sort();
+        for next in versions.windows(2) {
+            ww.link(next[0].1, next[1].1);
+        }
+    }
+}
+
+/// Who wrote each value: the last write of a transaction to a key, the writes it overwrote,
+/// and the writes of failed transactions.
+#[derive(Debug, Default)]
+struct Writes {
+    last: HashMap<(u64, u64), usize>,
+    intermediate: HashMap<(u64, u64), usize>,
+    aborted: HashMap<(u64, u64), usize>,
+}
+
+impl Writes {
+    fn of(pairs: &[Pair<'_>], txns: &[Vec<MicroOp>]) -> Self {
+        let mut writes = Self::default();
+        for (i, (pair, txn)) in pairs.iter().zip(txns).enumerate() {
+            let mut last: HashMap<u64, u64> = HashMap::new();
+            for op in txn {
+                let &MicroOp::Write { key, value } = op else {
+                    continue;
+                };
+                if let Some(overwritten) = last.insert(key, value) {
+                    writes.intermediate.insert((key, overwritten), i);
+                }
+            }
+            let finals = if pair.outcome() == OpType::Fail {
+                &mut writes.aborted
+            } else {
+                &mut writes.last
+            };
+            finals.extend(last.into_iter().map(|write| (write, i)));
+        }
+        writes
+    }
+}
+
+#[must_use]
+pub fn check(history: &History, isolation: Isolation) -> Report {
+    let pairs = history.pairs_of("txn");
+    let txns: Vec<Vec<MicroOp>> = pairs.iter().map(micro_ops).collect();
+    let writes = Writes::of(&pairs, &txns);
+    let read_committed = isolation == Isolation::ReadCommitted;
+
+    let mut anomalies = Vec::new();
+    let (mut ww, mut wr) = (Graph::default(), Graph::default());
+    version_order(&pairs, &txns, &mut ww);
+    for (i, txn) in txns.iter().enumerate() {
+        if pairs[i].outcome() != OpType::Ok {
+            continue;
+        }
+        let mut own: HashMap<u64, u64> = HashMap::new();
+        // who wrote what the transaction read of each key before writing it
+        let mut read_from: HashMap<u64, usize> = HashMap::new();
+        for op in txn {
+            let (key, read) = match *op {
+                MicroOp::Write { key, value } => {
+                    if !own.contains_key(&key)
+                        && let Some(&writer) = read_from.get(&key)
+                    {
+                        ww.link(writer, i);
+                    }
+                    own.insert(key, value);
+                    continue;
+                }
+                MicroOp::Read { key, value } => (key, value),
+            };
+            if let Some(&written) = own.get(&key) {
+                if read_committed && read != Some(written) {
+                    anomalies.push(Anomaly::new(
+                        "internal",
+                        format!("read {read:?} from {key} after writing {written} to it"),
+                        ops_of(&pairs, &[i]),
+                    ));
+                }
+                continue;
+            }
+            let Some(value) = read else {
+                continue;
+            };
+            let write = (key, value);
+            if let Some(&writer) = writes.aborted.get(&write) {
+                if read_committed {
+                    anomalies.push(Anomaly::new(
+                        "G1a",
+                        format!("read {value} from {key}, written by a failed transaction"),
+                        ops_of(&pairs, &[writer, i]),
+                    ));
+                }
+            } else if let Some(&writer) = writes.intermediate.get(&write) {
+                if read_committed && writer != i {
+                    anomalies.push(Anomaly::new(
+                        "G1b",
+                        format!("read {value} from {key}, which its transaction overwrote"),
+                        ops_of(&pairs, &[writer, i]),
+                    ));
+                }
+            } else if let Some(&writer) = writes.last.get(&write) {
+                wr.link(writer, i);
+                read_from.insert(key, writer);
+            } else {
+                anomalies.push(Anomaly::new(
+                    "garbage-read",
+                    format!("read {value} from {key}, which nobody wrote"),
+                    ops_of(&pairs, &[i]),
+                ));
+            }
+        }
+    }
+
+    let dirty = ww.cycles();
+    for cycle in &dirty {
+        anomalies.push(Anomaly::new(
+            "G0",
+            format!("transactions {cycle:?} overwrite each other in a cycle"),
+            ops_of(&pairs, cycle),
+        ));
+    }
+    if read_committed {
+        for cycle in ww.union(&wr).cycles() {
+            if dirty.contains(&cycle) {
+                continue;
+            }
+            anomalies.push(Anomaly::new(
+                "G1c",
+                format!("transactions {cycle:?} read from or overwrite each other in a cycle"),
+                ops_of(&pairs, &cycle),
+            ));
+        }
+    }
+
+    let stats = json!({"ww_edges": ww.len(), "wr_edges": wr.len()});
+    Report::new(OpCounts::of(&pairs), anomalies).with_stats(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::check;
+    use crate::checker::{History, OpType, op};
+    use crate::txn::Isolation;
+
+    #[test]
+    fn catches_anomalies_by_isolation() {
+        let mut history = History::new();
+        let txns = [
+            // 0 overwrites its own x=1, which 1 then reads
+            (json!([["w", 1, 1], ["w", 1, 2]]), OpType::Ok),
+            (json!([["r", 1, 1]]), OpType::Ok),
+            // 2 failed, yet 3 reads its write
+            (json!([["w", 2, 5]]), OpType::Fail),
+            (json!([["r", 2, 5]]), OpType::Ok),
+            // 4 and 5 each overwrite what they read from the other
+            (json!([["r", 4, 2], ["w", 4, 3], ["w", 3, 1]]), OpType::Ok),
+            (json!([["r", 3, 1], ["w", 3, 4], ["w", 4, 2]]), OpType::Ok),
+            // 6 and 7 each read what the other wrote
+            (json!([["r", 5, 1], ["w", 6, 1]]), OpType::Ok),
+            (json!([["r", 6, 1], ["w", 5, 1]]), OpType::Ok),
+        ];
+        for (process, (txn, kind)) in (0..).zip(txns) {
+            let reply = match kind {
+                OpType::Ok => json!({"type": "txn_ok", "txn": txn}),
+                _ => json!({"code": 30}),
+            };
+            op(
+                &mut history,
+                process,
+                "n0",
+                (process, process + 1),
+                json!({"type": "txn", "txn": txn}),
+                (kind, reply),
+            );
+        }
+
+        let report = check(&history, Isolation::ReadCommitted);
+        let kinds: Vec<&str> = report.anomalies.iter().map(|a| a.kind).collect();
+        assert_eq!(kinds, ["G1b", "G1a", "G0", "G1c"]);
+        assert_eq!(report.anomalies[3].ops.len(), 4);
+
+        let report = check(&history, Isolation::ReadUncommitted);
+        let kinds: Vec<&str> = report.anomalies.iter().map(|a| a.kind).collect();
+        assert_eq!(kinds, ["G0"]);
+    }
+
+    #[test]
+    fn orders_blind_writes_by_their_stamps() {
+        let mut history = History::new();
+        let txns = [
+            // 0 and 1 write both keys, their stamps have 0 first on 1 and 1 first on 2: the
+            // writes interleaved, a G0 cycle the reads after them can't tell
+            (
+                json!([["w", 1, 1], ["w", 2, 2]]),
+                json!([[1, "n0", 0], [3, "n0", 1]]),
+            ),
+            (
+                json!([["w", 1, 3], ["w", 2, 4]]),
+                json!([[2, "n1", 0], [2, "n1", 1]]),
+            ),
+            (json!([["r", 1, 3], ["r", 2, 2]]), json!([null, null])),
+            (json!([["r", 1, 3], ["r", 2, 2]]), json!([null, null])),
+        ];
+        for (process, (txn, stamps)) in (0..).zip(txns) {
+            op(
+                &mut history,
+                process,
+                "n0",
+                (process / 2 * 2, process / 2 * 2 + 1),
+                json!({"type": "txn", "txn": txn}),
+                (
+                    OpType::Ok,
+                    json!({"type": "txn_ok", "txn": txn, "stamps": stamps}),
+                ),
+            );
+        }
+
+        let report = check(&history, Isolation::ReadUncommitted);
+        let kinds: Vec<&str> = report.anomalies.iter().map(|a| a.kind).collect();
+        assert_eq!(kinds, ["G0"], "{report}");
+        let txns: Vec<u64> = report.anomalies[0]
+            .ops
+            .iter()
+            .map(|op| op.process)
+            .collect();
+        assert_eq!(txns, [0, 0, 1, 1]);
+        assert_eq!(report.stats["ww_edges"], 2);
+        assert_eq!(report.stats["wr_edges"], 4);
+    }
+}
@@ -0,0 +1,66 @@
+//! No two `generate_ok` replies carry the same id.
+
+use std::collections::HashMap;
+
+use serde_json::json;
+
+use super::{Anomaly, History, Op, OpCounts, Report};
+
+#[must_use]
+pub fn check(history: &History) -> Report {
+    let pairs = history.pairs_of("generate");
+    // ids aren't necessarily strings, their JSON text is what has to be unique
+    let mut by_id: HashMap<String, Vec<&Op>> = HashMap::new();
+    for pair in &pairs {
+        if let (Some(reply), Some(completion)) = (pair.reply(), pair.completion) {
+            by_id
+                .entry(reply["id"].to_string())
+                .or_default()
+                .push(completion);
+        }
+    }
+
+    let mut anomalies: Vec<Anomaly> = by_id
+        .iter()
+        .filter(|(_, ops)| ops.len() > 1)
+        .map(|(id, ops)| {
+            Anomaly::new(
+                "duplicate-id",
+                format!("{id} was generated {} times", ops.len()),
+                ops.iter().map(|&op| op.clone()).collect(),
+            )
+        })
+        .collect();
+    anomalies.sort_by_key(|anomaly| anomaly.ops[0].index);
+
+    let stats = json!({"distinct_ids": by_id.len()});
+    Report::new(OpCounts::of(&pairs), anomalies).with_stats(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::check;
+    use crate::checker::{History, OpType, op};
+
+    #[test]
+    fn catches_duplicates() {
+        let mut history = History::new();
+        for (process, id) in [(0, "x"), (1, "y"), (2, "x")] {
+            let reply = (OpType::Ok, json!({"type": "generate_ok", "id": id}));
+            op(
+                &mut history,
+                process,
+                "n0",
+                (0, 1),
+                json!({"type": "generate"}),
+                reply,
+            );
+        }
+        let report = check(&history);
+        assert_eq!(report.anomalies.len(), 1);
+        assert_eq!(report.anomalies[0].ops.len(), 2);
+        assert_eq!(report.stats["distinct_ids"], 2);
+    }
+}
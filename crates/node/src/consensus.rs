@@ -0,0 +1,393 @@
+//! What the consensus engines, [`Raft`](crate::Raft) and [`MultiPaxos`](crate::MultiPaxos),
+//! have in common: the state machine their log drives, and how client requests reach it.
+//!
+//! Each is an [`Engine`], which a [`Replica`] runs on a server.
+
+pub mod kv;
+
+use std::fmt;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use serde_json::{Value, json};
+use tokio::sync::oneshot;
+use tokio::time::{self, Duration, MissedTickBehavior};
+
+use crate::error::{Error, ErrorCode};
+use crate::handlers::HandlersMap;
+use crate::partition::relay;
+use crate::payload::Body;
+use crate::rpc::{RpcError, rpc};
+use crate::server::Server;
+use crate::types::Message;
+
+/// How long a client request waits for its command to be applied, or for the leader's reply.
+const APPLY_TIMEOUT: Duration = Duration::from_secs(1);
+/// How often the timers of a replica are checked.
+const TICK: Duration = Duration::from_millis(10);
+
+/// What a replicated log drives.
+pub trait StateMachine: Send + 'static {
+    /// Applies a committed `command`, the body of a client request, returning the body of the
+    /// reply to it.
+    ///
+    /// # Errors
+    /// - the error to reply with instead, e.g. `key-does-not-exist`
+    fn apply(&mut self, command: &Value) -> Result<Value, Error>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Follower,
+    /// Standing for election, or preparing a ballot.
+    Candidate,
+    Leader,
+}
+
+/// What a client waits on once its command is in the log.
+pub type Applied = oneshot::Receiver<Result<Value, Error>>;
+
+/// Bodies to send, by destination.
+pub type Outgoing = Vec<(String, Value)>;
+
+type Srv = Arc<Mutex<dyn Server + Send + Sync>>;
+
+/// The state of a consensus engine on one server, which a [`Replica`] drives.
+pub trait Engine: Send + 'static {
+    /// What the log drives.
+    type Machine;
+    /// Name of the engine, for messages.
+    const NAME: &'static str;
+    /// Types of the messages the servers exchange.
+    const MESSAGES: &'static [&'static str];
+
+    /// Starts the timers, once the server knows its `id` and every member of the cluster.
+    fn start(&mut self, id: String, nodes: Vec<String>);
+
+    /// Handles one of the [`Engine::MESSAGES`], returning the messages to send in response.
+    ///
+    /// # Errors
+    /// - `malformed-request` or `not-supported` if `msg` isn't one of them
+    fn handle(&mut self, msg: &Message) -> Result<Outgoing, Error>;
+
+    /// Checks the timers, returning the messages to send for those that went off.
+    fn tick(&mut self) -> Outgoing;
+
+    /// Appends `command` to the log if this server leads, or says which server does if it
+    /// knows.
+    ///
+    /// # Errors
+    /// - the leader this server knows of, if any, when it doesn't lead
+    fn submit(&mut self, command: Value) -> Result<Applied, Option<String>>;
+
+    fn role(&self) -> Role;
+
+    /// The leader this server knows of.
+    fn leader(&self) -> Option<String>;
+
+    fn machine(&self) -> &Self::Machine;
+}
+
+/// A server's replica of the log `E` keeps, running once [`Replica::install`]ed.
+///
+/// Clones share the replica.
+pub struct Replica<E> {
+    core: Arc<Mutex<E>>,
+}
+
+impl<E> Clone for Replica<E> {
+    fn clone(&self) -> Self {
+        Self {
+            core: self.core.clone(),
+        }
+    }
+}
+
+impl<E: fmt::Debug> fmt::Debug for Replica<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Replica").field("core", &self.core).finish()
+    }
+}
+
+impl<E: Engine> Replica<E> {
+    pub(crate) fn from_engine(engine: E) -> Self {
+        Self {
+            core: Arc::new(Mutex::new(engine)),
+        }
+    }
+
+    /// # Panics
+    /// Panics if the mutex on the replica is poisoned.
+    pub(crate) fn lock(&self) -> MutexGuard<'_, E> {
+        self.core.lock().unwrap()
+    }
+
+    /// # Panics
+    /// Panics if the mutex on the replica is poisoned.
+    #[must_use]
+    pub fn role(&self) -> Role {
+        self.lock().role()
+    }
+
+    /// The leader this server knows of.
+    ///
+    /// # Panics
+    /// Panics if the mutex on the replica is poisoned.
+    #[must_use]
+    pub fn leader(&self) -> Option<String> {
+        self.lock().leader()
+    }
+
+    /// Reads this server's copy of the state machine, which may lag behind the leader's.
+    ///
+    /// # Panics
+    /// Panics if the mutex on the replica is poisoned.
+    pub fn read<T>(&self, f: impl FnOnce(&E::Machine) -> T) -> T {
+        f(self.lock().machine())
+    }
+
+    /// Runs `command` through the log, relaying it to the leader if this server doesn't lead,
+    /// and returns what the state machine made of it. The leader has to serve the type of
+    /// `command` as a client request, see [`Replica::install`].
+    ///
+    /// # Errors
+    /// - as [`submit`]
+    ///
+    /// # Panics
+    /// Panics if the mutex on the replica is poisoned.
+    pub async fn propose(&self, srv_mutex: &Srv, command: Value) -> Result<Value, Error> {
+        propose(srv_mutex, command, |command| self.lock().submit(command)).await
+    }
+
+    /// Registers the handlers of the [`Engine::MESSAGES`], and of the client requests of every
+    /// type in `client_types`, and wraps the `init` handler so the timers start once the server
+    /// knows its peers.
+    ///
+    /// # Panics
+    /// Panics if `handlers` has no `init` handler, see [`crate::build_default_handlers`].
+    pub fn install(
+        &self,
+        handlers: &mut HandlersMap<dyn Server + Send + Sync>,
+        client_types: &[&'static str],
+    ) {
+        for &r#type in client_types {
+            let replica = self.clone();
+            handlers.insert(
+                r#type,
+                Arc::new(move |srv_mutex, msg| {
+                    let replica = replica.clone();
+                    Box::pin(async move {
+                        submit(&srv_mutex, msg, |command| replica.lock().submit(command)).await
+                    })
+                }),
+            );
+        }
+
+        for &r#type in E::MESSAGES {
+            let replica = self.clone();
+            handlers.insert(
+                r#type,
+                Arc::new(move |srv_mutex, msg| {
+                    let replica = replica.clone();
+                    Box::pin(async move {
+                        let outgoing = replica.lock().handle(&msg)?;
+                        send_all(&srv_mutex, outgoing);
+                        Ok(())
+                    })
+                }),
+            );
+        }
+
+        let init = handlers
+            .get("init")
+            .cloned()
+            .unwrap_or_else(|| panic!("{} installed without an init handler", E::NAME));
+        let replica = self.clone();
+        handlers.insert(
+            "init",
+            Arc::new(move |srv_mutex, msg| {
+                let (init, replica) = (init.clone(), replica.clone());
+                Box::pin(async move {
+                    let started = !srv_mutex.lock().unwrap().get_id().is_empty();
+                    init(srv_mutex.clone(), msg).await?;
+                    if !started {
+                        let srv = srv_mutex.lock().unwrap();
+                        let (id, nodes, shutdown) =
+                            (srv.get_id(), srv.get_topology(), srv.get_shutdown());
+                        drop(srv);
+                        replica.lock().start(id, nodes);
+                        shutdown.spawn(replica.run(srv_mutex));
+                    }
+                    Ok(())
+                })
+            }),
+        );
+    }
+
+    /// Checks the timers every [`TICK`], until the server shuts down.
+    async fn run(self, srv_mutex: Srv) {
+        let shutdown = srv_mutex.lock().unwrap().get_shutdown();
+        let mut ticks = time::interval(TICK);
+        ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                () = shutdown.triggered() => return,
+                _ = ticks.tick() => {}
+            }
+            let outgoing = self.lock().tick();
+            send_all(&srv_mutex, outgoing);
+        }
+    }
+}
+
+/// Sends each body to its destination, logging what couldn't be.
+///
+/// # Panics
+/// Panics if the mutex on the server is poisoned.
+pub fn send_all(srv_mutex: &Srv, outgoing: Outgoing) {
+    if outgoing.is_empty() {
+        return;
+    }
+    let srv = srv_mutex.lock().unwrap();
+    let src = srv.get_id();
+    for (dest, body) in outgoing {
+        let msg = Message {
+            src: src.clone(),
+            dest,
+            body,
+        };
+        if let Err(e) = srv.send(&msg) {
+            log::error!("failed to send {} to {}: {e}", msg.body["type"], msg.dest);
+        }
+    }
+}
+
+/// Runs the client request `msg` through a log with `submit`, which appends a command if this
+/// server leads or says which server does, and replies with what the state machine made of it.
+///
+/// # Errors
+/// - what the state machine replied with
+/// - `temporarily-unavailable` if no leader is known, or leadership changed before the command
+///   committed
+/// - `timeout` if the command wasn't applied in time, it may still be
+///
+/// # Panics
+/// Panics if the mutex on the server is poisoned.
+pub async fn submit(
+    srv_mutex: &Srv,
+    msg: Message,
+    submit: impl FnOnce(Value) -> Result<Applied, Option<String>>,
+) -> Result<(), Error> {
+    let msg = parse(msg)?;
+    let applied = match submit(msg.body.payload.clone()) {
+        Ok(applied) => applied,
+        Err(Some(leader)) => return relay(srv_mutex, &msg, &leader, APPLY_TIMEOUT).await,
+        Err(None) => return Err(no_leader()),
+    };
+    let reply = wait(applied).await?;
+    respond(srv_mutex, &msg, reply)
+}
+
+/// Runs `command` through a log with `submit`, or through the log of the leader it names,
+/// returning what the state machine made of it. The leader has to serve the type of `command`
+/// as a client request.
+///
+/// # Errors
+/// - as [`submit`]
+pub async fn propose(
+    srv_mutex: &Srv,
+    command: Value,
+    submit: impl FnOnce(Value) -> Result<Applied, Option<String>>,
+) -> Result<Value, Error> {
+    let applied = match submit(command.clone()) {
+        Ok(applied) => applied,
+        Err(Some(leader)) => {
+            return match rpc(srv_mutex, &leader, command, APPLY_TIMEOUT).await {
+                Ok(reply) => Ok(reply.body),
+                Err(RpcError::Remote(e)) => Err(e),
+                Err(RpcError::Timeout) => Err(Error::new(
+                    ErrorCode::Timeout,
+                    format!("{leader} didn't reply in time"),
+                )),
+                Err(e) => Err(Error::crash(format!("proposing to {leader} failed: {e}"))),
+            };
+        }
+        Err(None) => return Err(no_leader()),
+    };
+    wait(applied).await
+}
+
+/// Splits the bookkeeping off a client request, leaving the command.
+///
+/// # Errors
+/// - `malformed-request` if `msg` has no `type`
+pub fn parse(msg: Message) -> Result<Message<Body<Value>>, Error> {
+    Ok(Message {
+        src: msg.src,
+        dest: msg.dest,
+        body: serde_json::from_value(msg.body).map_err(|e| Error::malformed(e.to_string()))?,
+    })
+}
+
+/// Replies to the client request `msg` with what the state machine made of its command.
+///
+/// # Errors
+/// - forwards `io` errors from sending
+///
+/// # Panics
+/// Panics if the mutex on the server is poisoned.
+pub fn respond(srv_mutex: &Srv, msg: &Message<Body<Value>>, mut reply: Value) -> Result<(), Error> {
+    reply["in_reply_to"] = json!(msg.body.msg_id);
+    let srv = srv_mutex.lock().unwrap();
+    let reply = Message {
+        src: srv.get_id(),
+        dest: msg.src.clone(),
+        body: reply,
+    };
+    Ok(srv.send(&reply)?)
+}
+
+fn no_leader() -> Error {
+    Error::new(
+        ErrorCode::TemporarilyUnavailable,
+        "no leader to take the request",
+    )
+}
+
+/// Waits for a command to be applied, at most [`APPLY_TIMEOUT`].
+async fn wait(applied: Applied) -> Result<Value, Error> {
+    match time::timeout(APPLY_TIMEOUT, applied).await {
+        Ok(Ok(result)) => result,
+        Ok(Err(_)) => Err(Error::crash("the replica dropped the request")),
+        Err(_) => Err(Error::new(ErrorCode::Timeout, "not applied in time")),
+    }
+}
+
+/// Runs `scenario` on five servers replicating a [`LinKv`](kv::LinKv) with the replicas `new`
+/// makes, given those replicas.
+#[cfg(all(test, feature = "sim"))]
+pub(crate) fn sim_replicas<E: Engine>(
+    seed: u64,
+    new: fn() -> Replica<E>,
+    scenario: impl AsyncFnOnce(crate::sim::Cluster, Vec<Replica<E>>),
+) {
+    use crate::sim::{Sim, SimConfig};
+
+    let replicas = Arc::new(Mutex::new(Vec::new()));
+    let factory_replicas = replicas.clone();
+    Sim::new(SimConfig {
+        seed,
+        drop_rate: 0.05,
+        ..SimConfig::default()
+    })
+    .nodes(5, move || {
+        let replica = new();
+        factory_replicas.lock().unwrap().push(replica.clone());
+        let mut handlers = crate::build_default_handlers();
+        replica.install(&mut handlers, kv::KV_TYPES);
+        (Arc::new(Mutex::new(crate::Node::default())), handlers)
+    })
+    .run(|cluster| async move {
+        let replicas = replicas.lock().unwrap().clone();
+        scenario(cluster, replicas).await;
+    });
+}
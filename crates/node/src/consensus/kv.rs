@@ -0,0 +1,101 @@
+//! A key-value store speaking Maelstrom's `read`, `write` and `cas`, linearizable once driven by
+//! a replicated log.
+
+use std::collections::HashMap;
+
+use serde::Deserialize;
+use serde_json::{Value, json};
+
+use super::StateMachine;
+use crate::error::{Error, ErrorCode};
+
+/// Client types to install a [`LinKv`] for, e.g. with [`Replica::install`](crate::Replica::install).
+pub const KV_TYPES: &[&str] = &["read", "write", "cas"];
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Command {
+    Read {
+        key: Value,
+    },
+    Write {
+        key: Value,
+        value: Value,
+    },
+    Cas {
+        key: Value,
+        from: Value,
+        to: Value,
+        #[serde(default)]
+        create_if_not_exists: bool,
+    },
+}
+
+/// Whether a `cas` from `from` may go ahead on a key holding `current`, `None` if it doesn't
+/// exist.
+///
+/// # Errors
+/// - `key-does-not-exist` if it doesn't, unless `create_if_not_exists`
+/// - `precondition-failed` if it holds something else than `from`
+pub fn cas_precondition(
+    current: Option<&Value>,
+    from: &Value,
+    create_if_not_exists: bool,
+) -> Result<(), Error> {
+    match current {
+        None if create_if_not_exists => Ok(()),
+        None => Err(Error::new(ErrorCode::KeyDoesNotExist, "key does not exist")),
+        Some(current) if current != from => Err(Error::new(
+            ErrorCode::PreconditionFailed,
+            format!("expected {from}, found {current}"),
+        )),
+        Some(_) => Ok(()),
+    }
+}
+
+/// Values by the JSON text of their key, as keys can be any JSON value.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LinKv {
+    values: HashMap<String, Value>,
+}
+
+impl LinKv {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[must_use]
+    pub fn get(&self, key: &Value) -> Option<&Value> {
+        self.values.get(&key.to_string())
+    }
+}
+
+impl StateMachine for LinKv {
+    fn apply(&mut self, command: &Value) -> Result<Value, Error> {
+        let command = Command::deserialize(command)
+            .map_err(|e| Error::new(ErrorCode::NotSupported, e.to_string()))?;
+        match command {
+            Command::Read { key } => {
+                let value = self
+                    .get(&key)
+                    .ok_or_else(|| Error::new(ErrorCode::KeyDoesNotExist, "key does not exist"))?;
+                Ok(json!({"type": "read_ok", "value": value}))
+            }
+            Command::Write { key, value } => {
+                self.values.insert(key.to_string(), value);
+                Ok(json!({"type": "write_ok"}))
+            }
+            Command::Cas {
+                key,
+                from,
+                to,
+                create_if_not_exists,
+            } => {
+                cas_precondition(self.get(&key), &from, create_if_not_exists)?;
+                self.values.insert(key.to_string(), to);
+                Ok(json!({"type": "cas_ok"}))
+            }
+        }
+    }
+}
@@ -0,0 +1,257 @@
+//! State-based CRDTs and the gossip replicating them between servers.
+
+pub mod counter;
+pub mod gossip;
+pub mod map;
+pub mod register;
+pub mod set;
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+pub use counter::{GCounter, PNCounter};
+pub use gossip::{Gossip, GossipMetrics};
+pub use map::LwwMap;
+pub use register::{LwwRegister, MvRegister};
+pub use set::{GSet, ORSet, TwoPSet};
+
+/// State that replicas converge on by merging each other's copies, in any order and any number
+/// of times.
+///
+/// The default value is the empty state, the one merging changes nothing.
+pub trait Crdt:
+    Clone + Default + PartialEq + Serialize + DeserializeOwned + Send + 'static
+{
+    /// Folds `other` into `self`. Must be commutative, associative and idempotent.
+    fn merge(&mut self, other: &Self);
+
+    /// The part of `self` that `since` is missing: merging it into `since` gives the same state
+    /// as merging the whole of `self`. The empty state if `since` has it all.
+    #[must_use]
+    fn delta(&self, since: &Self) -> Self;
+
+    /// # Errors
+    /// - fails if the state has map keys that aren't strings in JSON
+    fn to_value(&self) -> serde_json::Result<Value> {
+        serde_json::to_value(self)
+    }
+
+    /// # Errors
+    /// - fails if `value` isn't a state of this CRDT
+    fn from_value(value: Value) -> serde_json::Result<Self> {
+        serde_json::from_value(value)
+    }
+}
+
+/// (De)serializes a map as a list of `[key, value]` pairs, since JSON only has string keys.
+mod pairs {
+    use std::collections::BTreeMap;
+
+    use serde::de::DeserializeOwned;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<K, V, S>(map: &BTreeMap<K, V>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        K: Serialize,
+        V: Serialize,
+        S: Serializer,
+    {
+        serializer.collect_seq(map)
+    }
+
+    pub fn deserialize<'de, K, V, D>(deserializer: D) -> Result<BTreeMap<K, V>, D::Error>
+    where
+        K: DeserializeOwned + Ord,
+        V: DeserializeOwned,
+        D: Deserializer<'de>,
+    {
+        Ok(Vec::<(K, V)>::deserialize(deserializer)?
+            .into_iter()
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fmt::Debug;
+
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use super::{Crdt, GCounter, GSet, LwwMap, LwwRegister, MvRegister, ORSet, PNCounter, TwoPSet};
+
+    const NODES: [&str; 3] = ["n0", "n1", "n2"];
+
+    /// Every state three replicas went through while updating with `update` and merging each
+    /// other's states at random.
+    fn states<C: Crdt>(
+        seed: u64,
+        initial: &C,
+        update: impl Fn(&mut StdRng, &str, &mut C),
+    ) -> Vec<C> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut replicas = vec![initial.clone(); NODES.len()];
+        let mut states = replicas.clone();
+        for _ in 0..40 {
+            let i = rng.random_range(0..NODES.len());
+            if rng.random_bool(0.3) {
+                let other = replicas[rng.random_range(0..NODES.len())].clone();
+                replicas[i].merge(&other);
+            } else {
+                update(&mut rng, NODES[i], &mut replicas[i]);
+            }
+            states.push(replicas[i].clone());
+        }
+        states
+    }
+
+    fn merged<C: Crdt>(a: &C, b: &C) -> C {
+        let mut a = a.clone();
+        a.merge(b);
+        a
+    }
+
+    /// Checks the merge is commutative, associative and idempotent, and that deltas and the
+    /// JSON round trip lose nothing.
+    fn check_laws<C: Crdt + PartialEq + Debug>(
+        initial: &C,
+        update: impl Fn(&mut StdRng, &str, &mut C),
+    ) {
+        for seed in 0..20 {
+            let states = states(seed, initial, &update);
+            let mut rng = StdRng::seed_from_u64(seed);
+            for _ in 0..50 {
+                let mut pick = || &states[rng.random_range(0..states.len())];
+                let (a, b, c) = (pick(), pick(), pick());
+
+                assert_eq!(merged(a, b), merged(b, a), "merge isn't commutative");
+                assert_eq!(
+                    merged(&merged(a, b), c),
+                    merged(a, &merged(b, c)),
+                    "merge isn't associative"
+                );
+                assert_eq!(&merged(a, a), a, "merge isn't idempotent");
+                assert_eq!(merged(b, &a.delta(b)), merged(b, a), "delta lost updates");
+                assert_eq!(&C::from_value(a.to_value().unwrap()).unwrap(), a);
+            }
+        }
+    }
+
+    #[test]
+    fn counters_follow_the_laws() {
+        check_laws(&GCounter::new(), |rng, node, counter| {
+            counter.increment(node, rng.random_range(0..5));
+        });
+        check_laws(&PNCounter::new(), |rng, node, counter| {
+            counter.add(node, rng.random_range(-5..5));
+        });
+    }
+
+    #[test]
+    fn sets_follow_the_laws() {
+        check_laws(&GSet::new(), |rng, _, set| {
+            set.insert(rng.random_range(0..10_u64));
+        });
+        check_laws(&TwoPSet::new(), |rng, _, set| {
+            let element = rng.random_range(0..10_u64);
+            if rng.random_bool(0.3) {
+                set.remove(&element);
+            } else {
+                set.insert(element);
+            }
+        });
+        check_laws(&ORSet::new(), |rng, node, set| {
+            let element = rng.random_range(0..5_u64);
+            if rng.random_bool(0.3) {
+                set.remove(&element);
+            } else {
+                set.insert(node, element);
+            }
+        });
+    }
+
+    #[test]
+    fn registers_and_maps_follow_the_laws() {
+        check_laws(&LwwRegister::new(), |rng, node, register| {
+            register.set(node, Some(rng.random_range(0..10_u64)));
+        });
+        check_laws(&MvRegister::new(), |rng, node, register| {
+            register.set(node, rng.random_range(0..10_u64));
+        });
+        check_laws(&LwwMap::new(), |rng, node, map| {
+            let key = rng.random_range(0..5_u64);
+            if rng.random_bool(0.2) {
+                map.remove(node, key);
+            } else {
+                map.insert(node, key, rng.random_range(0..10_u64));
+            }
+        });
+    }
+
+    #[test]
+    fn counters_converge_whatever_the_merge_order() {
+        let mut a = PNCounter::new();
+        let mut b = PNCounter::new();
+        a.add("n0", 5);
+        a.add("n0", -2);
+        b.add("n1", 3);
+        b.add("n1", -7);
+
+        let mut ab = a.clone();
+        ab.merge(&b);
+        let mut ba = b.clone();
+        ba.merge(&a);
+        assert_eq!(ab, ba);
+        assert_eq!(ab.value(), -1);
+
+        // merging a state twice, or an older one, changes nothing
+        ab.merge(&b);
+        ab.merge(&PNCounter::new());
+        assert_eq!(ab, ba);
+    }
+
+    #[test]
+    fn g_counters_keep_the_highest_subtotals() {
+        let mut a = GCounter::new();
+        a.increment("n0", 3);
+        let mut b = a.clone();
+        b.increment("n0", 2);
+        b.increment("n1", 1);
+
+        a.merge(&b);
+        assert_eq!(a.get("n0"), 5);
+        assert_eq!(a.value(), 6);
+    }
+
+    #[test]
+    fn or_sets_keep_concurrent_additions() {
+        let mut a = ORSet::new();
+        a.insert("n0", 1);
+        let mut b = a.clone();
+        // n1 removes the 1 it saw while n0 adds it again
+        b.remove(&1);
+        a.insert("n0", 1);
+        a.merge(&b);
+        assert!(a.contains(&1));
+
+        b.merge(&a);
+        b.remove(&1);
+        a.merge(&b);
+        assert!(!a.contains(&1));
+    }
+
+    #[test]
+    fn mv_registers_keep_concurrent_writes() {
+        let mut a = MvRegister::new();
+        let mut b = MvRegister::new();
+        a.set("n0", 'a');
+        b.set("n1", 'b');
+        a.merge(&b);
+        assert_eq!(a.values().copied().collect::<Vec<_>>(), ['a', 'b']);
+
+        a.set("n0", 'c');
+        b.merge(&a);
+        assert_eq!(b.values().copied().collect::<Vec<_>>(), ['c']);
+    }
+}
@@ -0,0 +1,104 @@
+//! Counters kept as one subtotal per node.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use super::Crdt;
+
+/// Counter that only goes up: every node increments its own entry, merging keeps the highest
+/// of each.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct GCounter {
+    counts: BTreeMap<String, u64>,
+}
+
+impl GCounter {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn increment(&mut self, node: &str, delta: u64) {
+        // no entry rather than a zero one, so equal counts are equal states
+        if delta == 0 {
+            return;
+        }
+        *self.counts.entry(node.to_string()).or_default() += delta;
+    }
+
+    #[must_use]
+    pub fn value(&self) -> u64 {
+        self.counts.values().sum()
+    }
+
+    /// What `node` added so far.
+    #[must_use]
+    pub fn get(&self, node: &str) -> u64 {
+        self.counts.get(node).copied().unwrap_or_default()
+    }
+}
+
+impl Crdt for GCounter {
+    fn merge(&mut self, other: &Self) {
+        for (node, &count) in &other.counts {
+            let entry = self.counts.entry(node.clone()).or_default();
+            *entry = (*entry).max(count);
+        }
+    }
+
+    fn delta(&self, since: &Self) -> Self {
+        let counts = self
+            .counts
+            .iter()
+            .filter(|&(node, &count)| count > since.get(node))
+            .map(|(node, &count)| (node.clone(), count))
+            .collect();
+        Self { counts }
+    }
+}
+
+/// Counter going both ways, as a grow-only counter of increments and one of decrements.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PNCounter {
+    inc: GCounter,
+    dec: GCounter,
+}
+
+impl PNCounter {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, node: &str, delta: i64) {
+        if delta >= 0 {
+            self.inc.increment(node, delta.unsigned_abs());
+        } else {
+            self.dec.increment(node, delta.unsigned_abs());
+        }
+    }
+
+    /// # Panics
+    /// Panics if the value doesn't fit in an `i64`.
+    #[must_use]
+    pub fn value(&self) -> i64 {
+        let value = i128::from(self.inc.value()) - i128::from(self.dec.value());
+        i64::try_from(value).expect("counter overflowed an i64")
+    }
+}
+
+impl Crdt for PNCounter {
+    fn merge(&mut self, other: &Self) {
+        self.inc.merge(&other.inc);
+        self.dec.merge(&other.dec);
+    }
+
+    fn delta(&self, since: &Self) -> Self {
+        Self {
+            inc: self.inc.delta(&since.inc),
+            dec: self.dec.delta(&since.dec),
+        }
+    }
+}
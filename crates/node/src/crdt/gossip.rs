@@ -0,0 +1,420 @@
+//! Replication of a CRDT by periodically shipping it to every other server.
+//!
+//! By default every round ships the whole state. With [`Gossip::with_deltas`], each peer only
+//! gets the part of the state it hasn't acknowledged yet, the delta-interval, and a peer silent
+//! for too long gets the full state again.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use serde_json::{Value, json};
+use tokio::time::{self, Duration, MissedTickBehavior};
+
+use super::Crdt;
+use crate::error::Error;
+use crate::handlers::HandlersMap;
+use crate::server::Server;
+use crate::types::Message;
+
+/// Type of the messages carrying a replica's state, or a delta of it, fire-and-forget.
+pub const GOSSIP: &str = "crdt_gossip";
+/// Type of the messages acknowledging a delta, fire-and-forget too.
+pub const GOSSIP_ACK: &str = "crdt_gossip_ack";
+
+/// Silent rounds after which [`Gossip::with_deltas`] ships a peer the full state again.
+///
+/// That's a second at the default interval, long enough for acks to come back from a slow peer,
+/// short enough that one healing from a partition soon catches up.
+pub const FULL_STATE_AFTER: u32 = 10;
+
+/// A snapshot of the gossip counters.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GossipMetrics {
+    pub rounds: u64,
+    /// Messages shipping a delta-interval.
+    pub deltas: u64,
+    /// Messages shipping the whole state.
+    pub full_states: u64,
+    /// Bytes of the gossip message bodies, over every round.
+    pub bytes_shipped: u64,
+    pub last_round_bytes: u64,
+}
+
+/// What a peer is known to have, and the deltas it hasn't acknowledged yet.
+#[derive(Debug, Default)]
+struct Peer<C> {
+    acked: C,
+    in_flight: BTreeMap<u64, C>,
+    /// Rounds since the last ack while deltas were in flight.
+    silent_rounds: u32,
+}
+
+#[derive(Debug, Default)]
+struct Replica<C> {
+    state: C,
+    peers: HashMap<String, Peer<C>>,
+    next_seq: u64,
+    metrics: GossipMetrics,
+}
+
+impl<C: Crdt> Replica<C> {
+    /// `peer` merged the delta `seq`, and so everything it was sent before.
+    fn acknowledged(&mut self, peer: &str, seq: u64) {
+        let Some(peer) = self.peers.get_mut(peer) else {
+            return;
+        };
+        if let Some(delta) = peer.in_flight.remove(&seq) {
+            peer.acked.merge(&delta);
+        }
+        // earlier deltas were lost or are late, later ones include what they had
+        peer.in_flight = peer.in_flight.split_off(&seq);
+        peer.silent_rounds = 0;
+    }
+
+    /// The bodies to ship to each of `peers` this round.
+    fn round(&mut self, peers: Vec<String>, full_state_after: Option<u32>) -> Vec<(String, Value)> {
+        let Self {
+            state,
+            peers: known,
+            next_seq,
+            metrics,
+        } = self;
+
+        let Some(full_state_after) = full_state_after else {
+            metrics.full_states += peers.len() as u64;
+            return peers
+                .into_iter()
+                .map(|peer| (peer, json!({"type": GOSSIP, "state": state})))
+                .collect();
+        };
+
+        let mut bodies = Vec::new();
+        for id in peers {
+            let peer = known.entry(id.clone()).or_default();
+            if peer.in_flight.is_empty() {
+                peer.silent_rounds = 0;
+            } else {
+                peer.silent_rounds += 1;
+            }
+            if peer.silent_rounds > full_state_after {
+                log::debug!("{id} went silent, shipping it the full state");
+                *peer = Peer::default();
+            }
+
+            let delta = state.delta(&peer.acked);
+            if delta == C::default() {
+                continue;
+            }
+            if peer.acked == C::default() {
+                metrics.full_states += 1;
+            } else {
+                metrics.deltas += 1;
+            }
+            let seq = *next_seq;
+            *next_seq += 1;
+            bodies.push((id, json!({"type": GOSSIP, "state": delta, "seq": seq})));
+            peer.in_flight.insert(seq, delta);
+        }
+        bodies
+    }
+
+    const fn shipped(&mut self, bytes: u64) {
+        self.metrics.rounds += 1;
+        self.metrics.bytes_shipped += bytes;
+        self.metrics.last_round_bytes = bytes;
+    }
+}
+
+/// A replica of `C`, gossiped to the other servers once [`Gossip::install`]ed.
+///
+/// Clones share the replica, so handlers capture one to read and update it.
+pub struct Gossip<C> {
+    replica: Arc<Mutex<Replica<C>>>,
+    interval: Duration,
+    /// Silent rounds after which a peer gets the full state, `None` to always ship it.
+    full_state_after: Option<u32>,
+}
+
+impl<C> Clone for Gossip<C> {
+    fn clone(&self) -> Self {
+        Self {
+            replica: self.replica.clone(),
+            interval: self.interval,
+            full_state_after: self.full_state_after,
+        }
+    }
+}
+
+impl<C: fmt::Debug> fmt::Debug for Gossip<C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Gossip")
+            .field("replica", &self.replica)
+            .field("interval", &self.interval)
+            .field("full_state_after", &self.full_state_after)
+            .finish()
+    }
+}
+
+impl<C: Crdt> Gossip<C> {
+    pub fn new(state: C) -> Self {
+        Self {
+            replica: Arc::new(Mutex::new(Replica {
+                state,
+                peers: HashMap::new(),
+                next_seq: 0,
+                metrics: GossipMetrics::default(),
+            })),
+            interval: Duration::from_millis(100),
+            full_state_after: None,
+        }
+    }
+
+    /// How often the state is shipped, every 100ms by default.
+    #[must_use]
+    pub const fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Ships peers only the deltas they haven't acknowledged, and the full state once one went
+    /// [`FULL_STATE_AFTER`] rounds without acknowledging anything, e.g. across a partition.
+    #[must_use]
+    pub const fn with_deltas(self) -> Self {
+        self.with_full_state_after(FULL_STATE_AFTER)
+    }
+
+    /// Ships deltas like [`Gossip::with_deltas`], the full state after `rounds` silent rounds.
+    #[must_use]
+    pub const fn with_full_state_after(mut self, rounds: u32) -> Self {
+        self.full_state_after = Some(rounds);
+        self
+    }
+
+    /// Applies a local update to the replica, it reaches the others with the next round.
+    ///
+    /// # Panics
+    /// Panics if the mutex on the replica is poisoned.
+    pub fn update<T>(&self, f: impl FnOnce(&mut C) -> T) -> T {
+        f(&mut self.replica.lock().unwrap().state)
+    }
+
+    /// # Panics
+    /// Panics if the mutex on the replica is poisoned.
+    pub fn read<T>(&self, f: impl FnOnce(&C) -> T) -> T {
+        f(&self.replica.lock().unwrap().state)
+    }
+
+    /// # Panics
+    /// Panics if the mutex on the replica is poisoned.
+    #[must_use]
+    pub fn metrics(&self) -> GossipMetrics {
+        self.replica.lock().unwrap().metrics
+    }
+
+    /// Registers the [`GOSSIP`] handler merging incoming states and the [`GOSSIP_ACK`] one,
+    /// and wraps the `init` handler so every round is started once the server knows its peers.
+    ///
+    /// # Panics
+    /// Panics if `handlers` has no `init` handler, see [`crate::build_default_handlers`].
+    pub fn install(&self, handlers: &mut HandlersMap<dyn Server + Send + Sync>) {
+        let gossip = self.clone();
+        handlers.insert(
+            GOSSIP,
+            Arc::new(move |srv_mutex, msg| {
+                let gossip = gossip.clone();
+                Box::pin(async move {
+                    let other = C::from_value(msg.body["state"].clone())
+                        .map_err(|e| Error::malformed(e.to_string()))?;
+                    gossip.update(|state| state.merge(&other));
+
+                    let Some(seq) = msg.body["seq"].as_u64() else {
+                        return Ok(());
+                    };
+                    let srv = srv_mutex.lock().unwrap();
+                    let ack = Message {
+                        src: srv.get_id(),
+                        dest: msg.src,
+                        body: json!({"type": GOSSIP_ACK, "seq": seq}),
+                    };
+                    Ok(srv.send(&ack)?)
+                })
+            }),
+        );
+
+        let gossip = self.clone();
+        handlers.insert(
+            GOSSIP_ACK,
+            Arc::new(move |_, msg| {
+                let gossip = gossip.clone();
+                Box::pin(async move {
+                    let seq = msg.body["seq"]
+                        .as_u64()
+                        .ok_or_else(|| Error::malformed("gossip ack without a `seq`"))?;
+                    gossip.replica.lock().unwrap().acknowledged(&msg.src, seq);
+                    Ok(())
+                })
+            }),
+        );
+
+        let init = handlers
+            .get("init")
+            .cloned()
+            .expect("gossip installed without an init handler");
+        let gossip = self.clone();
+        handlers.insert(
+            "init",
+            Arc::new(move |srv_mutex, msg| {
+                let (init, gossip) = (init.clone(), gossip.clone());
+                Box::pin(async move {
+                    let started = !srv_mutex.lock().unwrap().get_id().is_empty();
+                    init(srv_mutex.clone(), msg).await?;
+                    if !started {
+                        let shutdown = srv_mutex.lock().unwrap().get_shutdown();
+                        shutdown.spawn(gossip.run(srv_mutex));
+                    }
+                    Ok(())
+                })
+            }),
+        );
+    }
+
+    /// Ships the state to every other server each interval, until the server shuts down.
+    async fn run(self, srv_mutex: Arc<Mutex<dyn Server + Send + Sync>>) {
+        let shutdown = srv_mutex.lock().unwrap().get_shutdown();
+        let mut ticks = time::interval(self.interval);
+        ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                () = shutdown.triggered() => return,
+                _ = ticks.tick() => {}
+            }
+
+            let srv = srv_mutex.lock().unwrap();
+            let node_id = srv.get_id();
+            let peers = srv
+                .get_topology()
+                .into_iter()
+                .filter(|peer| *peer != node_id)
+                .collect();
+
+            let mut bytes = 0;
+            let bodies = self
+                .replica
+                .lock()
+                .unwrap()
+                .round(peers, self.full_state_after);
+            for (peer, body) in bodies {
+                bytes += body.to_string().len() as u64;
+                let msg = Message {
+                    src: node_id.clone(),
+                    dest: peer,
+                    body,
+                };
+                if let Err(e) = srv.send(&msg) {
+                    log::error!("failed to gossip to {}: {e}", msg.dest);
+                }
+            }
+            drop(srv);
+
+            self.replica.lock().unwrap().shipped(bytes);
+            log::trace!("gossip round shipped {bytes} bytes");
+        }
+    }
+}
+
+#[cfg(all(test, feature = "sim"))]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use serde_json::json;
+    use tokio::time::Duration;
+
+    use super::Gossip;
+    use crate::crdt::GSet;
+    use crate::sim::{Fault, Nemesis, Sim, SimConfig};
+    use crate::{Node, build_default_handlers};
+
+    /// The replicas of five nodes after 50 `add`s, partitioned as `nemesis` says.
+    fn replicate(full_state_after: Option<u32>, nemesis: Nemesis) -> Vec<Gossip<GSet<u64>>> {
+        let replicas = Arc::new(Mutex::new(Vec::new()));
+        let factory_replicas = replicas.clone();
+        Sim::new(SimConfig {
+            seed: 11,
+            drop_rate: 0.1,
+            ..SimConfig::default()
+        })
+        .nodes(5, move || {
+            let mut gossip = Gossip::new(GSet::new());
+            if let Some(rounds) = full_state_after {
+                gossip = gossip.with_full_state_after(rounds);
+            }
+            factory_replicas.lock().unwrap().push(gossip.clone());
+
+            let mut handlers = build_default_handlers();
+            gossip.install(&mut handlers);
+            handlers.insert(
+                "add",
+                Arc::new(move |srv, msg| {
+                    let gossip = gossip.clone();
+                    Box::pin(async move {
+                        let element = msg.body["element"].as_u64().unwrap();
+                        gossip.update(|set| set.insert(element));
+                        let srv = srv.lock().unwrap();
+                        let reply = srv.build_reply("add_ok", &msg, json!({})).unwrap();
+                        Ok(srv.send(&reply)?)
+                    })
+                }),
+            );
+            (Arc::new(Mutex::new(Node::default())), handlers)
+        })
+        .nemesis(nemesis)
+        .run(|cluster| async move {
+            let mut client = cluster.client();
+            for element in 0..50_u64 {
+                let dest = &cluster.node_ids()[usize::try_from(element).unwrap() % 5];
+                let body = json!({"type": "add", "element": element});
+                client
+                    .request(dest, body, Duration::from_secs(1))
+                    .await
+                    .unwrap();
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+            tokio::time::sleep(Duration::from_secs(2)).await;
+        });
+
+        let replicas = replicas.lock().unwrap().clone();
+        for replica in &replicas {
+            assert_eq!(replica.read(GSet::len), 50, "a replica is missing elements");
+        }
+        replicas
+    }
+
+    fn bytes_shipped(replicas: &[Gossip<GSet<u64>>]) -> u64 {
+        replicas.iter().map(|r| r.metrics().bytes_shipped).sum()
+    }
+
+    #[test]
+    fn deltas_ship_less_than_full_states() {
+        let full = replicate(None, Nemesis::new());
+        let deltas = replicate(Some(10), Nemesis::new());
+        assert!(
+            bytes_shipped(&deltas) * 3 < bytes_shipped(&full),
+            "deltas shipped {} bytes, full states {}",
+            bytes_shipped(&deltas),
+            bytes_shipped(&full),
+        );
+        assert!(deltas.iter().all(|r| r.metrics().deltas > 0));
+    }
+
+    #[test]
+    fn silent_peers_get_the_full_state() {
+        let nemesis = Nemesis::new()
+            .at(Duration::from_millis(500), Fault::MajorityMinority)
+            .at(Duration::from_secs(2), Fault::Heal);
+        let replicas = replicate(Some(5), nemesis);
+        // every node ships its first state in full to each of the 4 others
+        let full_states: u64 = replicas.iter().map(|r| r.metrics().full_states).sum();
+        assert!(full_states > 5 * 4, "only {full_states} full states");
+    }
+}
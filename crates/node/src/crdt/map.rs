@@ -0,0 +1,82 @@
+//! Maps of CRDTs.
+
+use std::collections::BTreeMap;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use super::{Crdt, LwwRegister};
+
+/// Map whose keys are last-write-wins registers, a removal being a write of nothing.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(
+    transparent,
+    bound(
+        serialize = "K: Serialize, V: Serialize",
+        deserialize = "K: DeserializeOwned, V: DeserializeOwned"
+    )
+)]
+pub struct LwwMap<K: Ord, V: Ord> {
+    #[serde(with = "super::pairs")]
+    entries: BTreeMap<K, LwwRegister<V>>,
+}
+
+impl<K: Ord, V: Ord> Default for LwwMap<K, V> {
+    fn default() -> Self {
+        Self {
+            entries: BTreeMap::new(),
+        }
+    }
+}
+
+impl<K: Ord, V: Ord> LwwMap<K, V> {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Writes `value` under `key` on behalf of `node`.
+    pub fn insert(&mut self, node: &str, key: K, value: V) {
+        self.entries.entry(key).or_default().set(node, Some(value));
+    }
+
+    /// Removes `key` on behalf of `node`, unless a later concurrent write wins.
+    pub fn remove(&mut self, node: &str, key: K) {
+        self.entries.entry(key).or_default().set(node, None);
+    }
+
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.entries.get(key)?.get()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.entries
+            .iter()
+            .filter_map(|(key, register)| Some((key, register.get()?)))
+    }
+}
+
+impl<K, V> Crdt for LwwMap<K, V>
+where
+    K: Ord + Clone + Serialize + DeserializeOwned + Send + 'static,
+    V: Ord + Clone + Serialize + DeserializeOwned + Send + 'static,
+{
+    fn merge(&mut self, other: &Self) {
+        for (key, register) in &other.entries {
+            self.entries.entry(key.clone()).or_default().merge(register);
+        }
+    }
+
+    fn delta(&self, since: &Self) -> Self {
+        let bottom = LwwRegister::default();
+        let entries = self
+            .entries
+            .iter()
+            .filter_map(|(key, register)| {
+                let delta = register.delta(since.entries.get(key).unwrap_or(&bottom));
+                (delta != bottom).then(|| (key.clone(), delta))
+            })
+            .collect();
+        Self { entries }
+    }
+}
@@ -0,0 +1,138 @@
+//! Registers holding a single value, resolving concurrent writes by picking one or keeping all.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use super::Crdt;
+
+/// Lamport time of a write, and the node that wrote it to break ties.
+type Stamp = (u64, String);
+
+/// Register where the last write wins, going by Lamport timestamps.
+///
+/// Concurrent writes with the same timestamp are ordered by node, then by value, so merging
+/// never depends on which replica merges.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LwwRegister<T: Ord> {
+    stamp: Stamp,
+    value: Option<T>,
+}
+
+impl<T: Ord> Default for LwwRegister<T> {
+    fn default() -> Self {
+        Self {
+            stamp: (0, String::new()),
+            value: None,
+        }
+    }
+}
+
+impl<T: Ord> LwwRegister<T> {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Writes `value` on behalf of `node`, `None` clearing the register.
+    pub fn set(&mut self, node: &str, value: Option<T>) {
+        self.stamp = (self.stamp.0 + 1, node.to_string());
+        self.value = value;
+    }
+
+    pub const fn get(&self) -> Option<&T> {
+        self.value.as_ref()
+    }
+
+    fn wins_over(&self, other: &Self) -> bool {
+        (&self.stamp, &self.value) > (&other.stamp, &other.value)
+    }
+}
+
+impl<T> Crdt for LwwRegister<T>
+where
+    T: Ord + Clone + Serialize + DeserializeOwned + Send + 'static,
+{
+    fn merge(&mut self, other: &Self) {
+        if other.wins_over(self) {
+            self.clone_from(other);
+        }
+    }
+
+    fn delta(&self, since: &Self) -> Self {
+        if self.wins_over(since) {
+            self.clone()
+        } else {
+            Self::default()
+        }
+    }
+}
+
+/// How many writes of each node a write had seen.
+type VersionVector = BTreeMap<String, u64>;
+
+/// Multi-value register: concurrent writes are all kept until a write that saw them replaces
+/// them.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct MvRegister<T: Ord> {
+    entries: BTreeSet<(VersionVector, T)>,
+}
+
+impl<T: Ord> Default for MvRegister<T> {
+    fn default() -> Self {
+        Self {
+            entries: BTreeSet::new(),
+        }
+    }
+}
+
+/// Whether `a` happened before `b`.
+fn precedes(a: &VersionVector, b: &VersionVector) -> bool {
+    a != b
+        && a.iter()
+            .all(|(node, &seen)| b.get(node).is_some_and(|&other| seen <= other))
+}
+
+impl<T: Ord> MvRegister<T> {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Writes `value` on behalf of `node`, replacing every value seen so far.
+    pub fn set(&mut self, node: &str, value: T) {
+        let mut version = VersionVector::new();
+        for (seen, _) in &self.entries {
+            for (by, &count) in seen {
+                let entry = version.entry(by.clone()).or_default();
+                *entry = (*entry).max(count);
+            }
+        }
+        *version.entry(node.to_string()).or_default() += 1;
+        self.entries = BTreeSet::from([(version, value)]);
+    }
+
+    /// The values of the concurrent writes nothing replaced yet.
+    pub fn values(&self) -> impl Iterator<Item = &T> {
+        self.entries.iter().map(|(_, value)| value)
+    }
+}
+
+impl<T> Crdt for MvRegister<T>
+where
+    T: Ord + Clone + Serialize + DeserializeOwned + Send + 'static,
+{
+    fn merge(&mut self, other: &Self) {
+        self.entries.extend(other.entries.iter().cloned());
+        let versions: Vec<_> = self.entries.iter().map(|(v, _)| v.clone()).collect();
+        self.entries
+            .retain(|(version, _)| !versions.iter().any(|v| precedes(version, v)));
+    }
+
+    fn delta(&self, since: &Self) -> Self {
+        let entries = self.entries.difference(&since.entries).cloned().collect();
+        Self { entries }
+    }
+}
@@ -0,0 +1,213 @@
+//! Sets, from the grow-only one to one where elements come and go as often as they like.
+
+use std::collections::BTreeSet;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use super::Crdt;
+
+/// Set that only grows, merged by union.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct GSet<T: Ord> {
+    elements: BTreeSet<T>,
+}
+
+impl<T: Ord> Default for GSet<T> {
+    fn default() -> Self {
+        Self {
+            elements: BTreeSet::new(),
+        }
+    }
+}
+
+impl<T: Ord> GSet<T> {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns whether `element` is new.
+    pub fn insert(&mut self, element: T) -> bool {
+        self.elements.insert(element)
+    }
+
+    pub fn contains(&self, element: &T) -> bool {
+        self.elements.contains(element)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.elements.iter()
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.elements.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.elements.is_empty()
+    }
+}
+
+impl<T> Crdt for GSet<T>
+where
+    T: Ord + Clone + Serialize + DeserializeOwned + Send + 'static,
+{
+    fn merge(&mut self, other: &Self) {
+        self.elements.extend(other.elements.iter().cloned());
+    }
+
+    fn delta(&self, since: &Self) -> Self {
+        let elements = self.elements.difference(&since.elements).cloned().collect();
+        Self { elements }
+    }
+}
+
+/// Set whose elements can be removed once, and never added back.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TwoPSet<T: Ord> {
+    added: GSet<T>,
+    removed: GSet<T>,
+}
+
+impl<T: Ord> Default for TwoPSet<T> {
+    fn default() -> Self {
+        Self {
+            added: GSet::new(),
+            removed: GSet::new(),
+        }
+    }
+}
+
+impl<T: Ord + Clone> TwoPSet<T> {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns whether `element` is in the set now, which it isn't if it was ever removed.
+    pub fn insert(&mut self, element: T) -> bool {
+        let removed = self.removed.contains(&element);
+        self.added.insert(element);
+        !removed
+    }
+
+    /// Removes `element` for good, returns whether it was in the set.
+    pub fn remove(&mut self, element: &T) -> bool {
+        self.contains(element) && self.removed.insert(element.clone())
+    }
+
+    pub fn contains(&self, element: &T) -> bool {
+        self.added.contains(element) && !self.removed.contains(element)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.added.iter().filter(|e| !self.removed.contains(e))
+    }
+}
+
+impl<T> Crdt for TwoPSet<T>
+where
+    T: Ord + Clone + Serialize + DeserializeOwned + Send + 'static,
+{
+    fn merge(&mut self, other: &Self) {
+        self.added.merge(&other.added);
+        self.removed.merge(&other.removed);
+    }
+
+    fn delta(&self, since: &Self) -> Self {
+        Self {
+            added: self.added.delta(&since.added),
+            removed: self.removed.delta(&since.removed),
+        }
+    }
+}
+
+/// Node that added an element, and how many elements it added before.
+type Tag = (String, u64);
+
+/// Observed-remove set: a removal only removes the additions the replica has seen, so an
+/// element added concurrently with its removal stays.
+///
+/// Every addition gets a unique tag, removed tags are kept as tombstones.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ORSet<T: Ord> {
+    added: BTreeSet<(T, Tag)>,
+    removed: BTreeSet<(T, Tag)>,
+}
+
+impl<T: Ord> Default for ORSet<T> {
+    fn default() -> Self {
+        Self {
+            added: BTreeSet::new(),
+            removed: BTreeSet::new(),
+        }
+    }
+}
+
+impl<T: Ord + Clone> ORSet<T> {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `element` on behalf of `node`.
+    pub fn insert(&mut self, node: &str, element: T) {
+        // a node's replica has every tag it handed out, even the removed ones
+        let seq = self
+            .added
+            .iter()
+            .filter(|(_, (by, _))| by == node)
+            .map(|(_, (_, seq))| seq + 1)
+            .max()
+            .unwrap_or_default();
+        self.added.insert((element, (node.to_string(), seq)));
+    }
+
+    /// Removes every addition of `element` seen so far, returns whether it was in the set.
+    pub fn remove(&mut self, element: &T) -> bool {
+        let observed: Vec<_> = self
+            .added
+            .iter()
+            .filter(|entry| entry.0 == *element && !self.removed.contains(entry))
+            .cloned()
+            .collect();
+        let contained = !observed.is_empty();
+        self.removed.extend(observed);
+        contained
+    }
+
+    pub fn contains(&self, element: &T) -> bool {
+        self.iter().any(|e| e == element)
+    }
+
+    /// The elements, each once.
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        let mut last = None;
+        self.added
+            .iter()
+            .filter(|entry| !self.removed.contains(entry))
+            .map(|(element, _)| element)
+            .filter(move |&element| last.replace(element) != Some(element))
+    }
+}
+
+impl<T> Crdt for ORSet<T>
+where
+    T: Ord + Clone + Serialize + DeserializeOwned + Send + 'static,
+{
+    fn merge(&mut self, other: &Self) {
+        self.added.extend(other.added.iter().cloned());
+        self.removed.extend(other.removed.iter().cloned());
+    }
+
+    fn delta(&self, since: &Self) -> Self {
+        Self {
+            added: self.added.difference(&since.added).cloned().collect(),
+            removed: self.removed.difference(&since.removed).cloned().collect(),
+        }
+    }
+}
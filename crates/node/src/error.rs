@@ -0,0 +1,153 @@
+use std::fmt;
+use std::io;
+
+/// The error codes from the Maelstrom protocol.
+///
+/// Codes Maelstrom doesn't define (custom ones start at 1000) are kept as [`ErrorCode::Other`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorCode {
+    Timeout,
+    NodeNotFound,
+    NotSupported,
+    TemporarilyUnavailable,
+    MalformedRequest,
+    Crash,
+    Abort,
+    KeyDoesNotExist,
+    KeyAlreadyExists,
+    PreconditionFailed,
+    TxnConflict,
+    Other(u64),
+}
+
+impl ErrorCode {
+    #[must_use]
+    pub const fn code(self) -> u64 {
+        match self {
+            Self::Timeout => 0,
+            Self::NodeNotFound => 1,
+            Self::NotSupported => 10,
+            Self::TemporarilyUnavailable => 11,
+            Self::MalformedRequest => 12,
+            Self::Crash => 13,
+            Self::Abort => 14,
+            Self::KeyDoesNotExist => 20,
+            Self::KeyAlreadyExists => 21,
+            Self::PreconditionFailed => 22,
+            Self::TxnConflict => 30,
+            Self::Other(code) => code,
+        }
+    }
+
+    /// Whether the error guarantees the request took no effect.
+    ///
+    /// `timeout` and `crash` are indefinite: the operation may or may not have happened.
+    #[must_use]
+    pub const fn is_definite(self) -> bool {
+        !matches!(self, Self::Timeout | Self::Crash | Self::Other(_))
+    }
+}
+
+impl From<u64> for ErrorCode {
+    fn from(code: u64) -> Self {
+        match code {
+            0 => Self::Timeout,
+            1 => Self::NodeNotFound,
+            10 => Self::NotSupported,
+            11 => Self::TemporarilyUnavailable,
+            12 => Self::MalformedRequest,
+            13 => Self::Crash,
+            14 => Self::Abort,
+            20 => Self::KeyDoesNotExist,
+            21 => Self::KeyAlreadyExists,
+            22 => Self::PreconditionFailed,
+            30 => Self::TxnConflict,
+            code => Self::Other(code),
+        }
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Timeout => "timeout",
+            Self::NodeNotFound => "node-not-found",
+            Self::NotSupported => "not-supported",
+            Self::TemporarilyUnavailable => "temporarily-unavailable",
+            Self::MalformedRequest => "malformed-request",
+            Self::Crash => "crash",
+            Self::Abort => "abort",
+            Self::KeyDoesNotExist => "key-does-not-exist",
+            Self::KeyAlreadyExists => "key-already-exists",
+            Self::PreconditionFailed => "precondition-failed",
+            Self::TxnConflict => "txn-conflict",
+            Self::Other(code) => return write!(f, "error {code}"),
+        };
+        f.write_str(name)
+    }
+}
+
+impl serde::Serialize for ErrorCode {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(self.code())
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for ErrorCode {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        <u64 as serde::Deserialize>::deserialize(deserializer).map(Self::from)
+    }
+}
+
+/// An error as carried by a Maelstrom `error` message.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Error {
+    pub code: ErrorCode,
+    #[serde(default)]
+    pub text: String,
+}
+
+impl Error {
+    pub fn new(code: ErrorCode, text: impl Into<String>) -> Self {
+        Self {
+            code,
+            text: text.into(),
+        }
+    }
+
+    pub fn crash(text: impl Into<String>) -> Self {
+        Self::new(ErrorCode::Crash, text)
+    }
+
+    pub fn malformed(text: impl Into<String>) -> Self {
+        Self::new(ErrorCode::MalformedRequest, text)
+    }
+
+    /// Reads the error out of a message body, if it is an `error` one.
+    #[must_use]
+    pub fn from_body(body: &serde_json::Value) -> Option<Self> {
+        if body["type"].as_str() != Some("error") {
+            return None;
+        }
+        serde_json::from_value(body.clone()).ok()
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.text.is_empty() {
+            write!(f, "{}", self.code)
+        } else {
+            write!(f, "{}: {}", self.code, self.text)
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+// a handler that can't write out its reply has crashed as far as the client can tell
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Self::crash(e.to_string())
+    }
+}
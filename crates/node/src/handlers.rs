@@ -4,17 +4,65 @@ use std::sync::{Arc, Mutex};
 
 use serde_json::json;
 
+use crate::error::Error;
+use crate::payload::{Body, Payload};
 use crate::server::Server;
 use crate::types::Message;
 
 pub type FnHandler<T> = Arc<
-    dyn Fn(Arc<Mutex<T>>, Message) -> Pin<Box<dyn Future<Output = Result<(), ()>> + Send>>
+    dyn Fn(Arc<Mutex<T>>, Message) -> Pin<Box<dyn Future<Output = Result<(), Error>> + Send>>
         + Send
         + Sync,
 >;
 
 pub type HandlersMap<T> = HashMap<&'static str, FnHandler<T>>;
 
+/// Key of the hook called with replies whose `in_reply_to` doesn't match any pending request,
+/// e.g. a late ack for a request that was already acknowledged or given up on.
+///
+/// It can never clash with a message type, since those are plain `snake_case`.
+pub const ORPHAN_REPLY: &str = "<orphan reply>";
+
+/// Registration of handlers taking typed messages.
+pub trait TypedHandlers<T: ?Sized> {
+    /// Registers `handler` for every variant of `P`.
+    ///
+    /// Requests whose body doesn't parse into `P` get a `malformed-request` error reply instead of
+    /// reaching the handler.
+    fn insert_typed<P, F, Fut>(&mut self, handler: F)
+    where
+        P: Payload,
+        F: Fn(Arc<Mutex<T>>, Message<Body<P>>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), Error>> + Send + 'static;
+}
+
+impl TypedHandlers<dyn Server + Send + Sync> for HandlersMap<dyn Server + Send + Sync> {
+    fn insert_typed<P, F, Fut>(&mut self, handler: F)
+    where
+        P: Payload,
+        F: Fn(Arc<Mutex<dyn Server + Send + Sync>>, Message<Body<P>>) -> Fut
+            + Send
+            + Sync
+            + 'static,
+        Fut: Future<Output = Result<(), Error>> + Send + 'static,
+    {
+        let handler = Arc::new(handler);
+        let erased: FnHandler<dyn Server + Send + Sync> = Arc::new(move |srv_mutex, msg| {
+            let handler = handler.clone();
+            Box::pin(async move {
+                let msg = msg
+                    .parse::<P>()
+                    .map_err(|e| Error::malformed(e.to_string()))?;
+                handler(srv_mutex, msg).await
+            })
+        });
+
+        for r#type in P::TYPES {
+            self.insert(r#type, erased.clone());
+        }
+    }
+}
+
 /// Returns a map of default message handlers for the node.
 /// # Panics
 /// The handlers created by this function may panic if the mutex on the node is poisoned.
@@ -33,9 +81,7 @@ pub fn build_default_handlers() -> HandlersMap<dyn Server + Send + Sync> {
                 srv.set_id(
                     msg.body["node_id"]
                         .as_str()
-                        .ok_or_else(|| {
-                            log::error!("ignoring invalid init message :(");
-                        })?
+                        .ok_or_else(|| Error::malformed("init message without a `node_id`"))?
                         .to_string(),
                 );
 
@@ -51,10 +97,23 @@ pub fn build_default_handlers() -> HandlersMap<dyn Server + Send + Sync> {
                     srv.set_topology(topo);
                 }
 
-                let reply = srv.build_reply("init_ok", &msg, json!({})).ok_or(())?;
-                srv.send(&reply).map_err(|e| {
-                    log::error!("failed to send init_ok: {e}");
-                })
+                let reply = srv
+                    .build_reply("init_ok", &msg, json!({}))
+                    .ok_or_else(|| Error::malformed("init message without a `msg_id`"))?;
+                Ok(srv.send(&reply)?)
+            })
+        }),
+    );
+    handlers.insert(
+        ORPHAN_REPLY,
+        Arc::new(|_, msg| {
+            Box::pin(async move {
+                log::debug!(
+                    "dropping orphan reply from {src} to message {in_reply_to}",
+                    src = msg.src,
+                    in_reply_to = msg.body["in_reply_to"]
+                );
+                Ok(())
             })
         }),
     );
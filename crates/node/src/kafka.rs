@@ -0,0 +1,190 @@
+//! Replicated logs, as Maelstrom's `kafka` workload sends them.
+//!
+//! A server keeps the messages of the keys it owns in [`Logs`]. [`Offsets`] hands out the offsets
+//! of new messages and keeps the offsets consumers committed, both in `lin-kv`: they survive the
+//! server, and any server can serve committed offsets.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+
+use serde_json::Value;
+
+use crate::kv::{KvClient, KvError};
+use crate::server::Server;
+
+/// Messages a poll returns per key at most.
+pub const POLL_LIMIT: usize = 100;
+
+crate::payload! {
+    pub enum Request {
+        "send" => Send { key: String, msg: Value },
+        "poll" => Poll { offsets: HashMap<String, u64> },
+        "commit_offsets" => CommitOffsets { offsets: HashMap<String, u64> },
+        "list_committed_offsets" => ListCommittedOffsets { keys: Vec<String> },
+    }
+}
+
+crate::payload! {
+    pub enum Reply {
+        "send_ok" => Send { offset: u64 },
+        "poll_ok" => Poll { msgs: HashMap<String, Vec<(u64, Value)>> },
+        "commit_offsets_ok" => CommitOffsets,
+        "list_committed_offsets_ok" => ListCommittedOffsets { offsets: HashMap<String, u64> },
+    }
+}
+
+/// The messages of every key by offset.
+#[derive(Debug, Default)]
+pub struct Logs {
+    logs: HashMap<String, BTreeMap<u64, Value>>,
+}
+
+impl Logs {
+    /// Appends `msg` to the log of `key`, returning its offset.
+    pub fn append(&mut self, key: String, msg: Value) -> u64 {
+        let log = self.logs.entry(key).or_default();
+        let offset = log.last_key_value().map_or(0, |(offset, _)| offset + 1);
+        log.insert(offset, msg);
+        offset
+    }
+
+    /// Stores `msg` at an `offset` of `key` taken from [`Offsets::allocate`].
+    pub fn insert(&mut self, key: String, offset: u64, msg: Value) {
+        self.logs.entry(key).or_default().insert(offset, msg);
+    }
+
+    /// Up to [`POLL_LIMIT`] messages of each key, starting at its offset in `offsets`. Keys
+    /// without messages there are left out.
+    #[must_use]
+    pub fn poll(&self, offsets: &HashMap<String, u64>) -> HashMap<String, Vec<(u64, Value)>> {
+        offsets
+            .iter()
+            .filter_map(|(key, &from)| {
+                let msgs: Vec<(u64, Value)> = self
+                    .logs
+                    .get(key)?
+                    .range(from..)
+                    .take(POLL_LIMIT)
+                    .map(|(&offset, msg)| (offset, msg.clone()))
+                    .collect();
+                (!msgs.is_empty()).then(|| (key.clone(), msgs))
+            })
+            .collect()
+    }
+}
+
+/// Offsets of every key kept in `lin-kv`: the last one handed out, and the one consumers
+/// committed.
+#[derive(Debug, Clone)]
+pub struct Offsets {
+    kv: KvClient,
+}
+
+impl Offsets {
+    #[must_use]
+    pub fn new(server: Arc<Mutex<dyn Server + Send + Sync>>) -> Self {
+        Self {
+            kv: KvClient::lin_kv(server),
+        }
+    }
+
+    /// Reads `key`, a key nobody wrote to yet being `None`.
+    async fn read(&self, key: &str) -> Result<Option<u64>, KvError> {
+        match self.kv.read(key).await {
+            Err(KvError::KeyDoesNotExist) => Ok(None),
+            result => result.map(Some),
+        }
+    }
+
+    /// Takes the next offset of `key`, which no other send gets even if the key moves to another
+    /// server.
+    ///
+    /// Offsets taken concurrently may be stored in either order, so callers allocating and
+    /// storing them one key at a time keep polls from seeing a later offset before an earlier one.
+    ///
+    /// # Errors
+    /// - forwards [`KvError`]s other than a lost race, which is retried
+    pub async fn allocate(&self, key: &str) -> Result<u64, KvError> {
+        let key = format!("offset-{key}");
+        loop {
+            let current = self.read(&key).await?;
+            let next = current.map_or(0, |offset| offset + 1);
+            match self.kv.cas(&*key, current, next, true).await {
+                Err(KvError::PreconditionFailed) => {
+                    log::debug!("{key} changed since it was read, retrying");
+                }
+                result => return result.map(|()| next),
+            }
+        }
+    }
+
+    /// Moves the committed offset of `key` up to `offset`, leaving it if it's already past it.
+    ///
+    /// # Errors
+    /// - forwards [`KvError`]s other than a lost race, which is retried
+    pub async fn commit(&self, key: &str, offset: u64) -> Result<(), KvError> {
+        let key = committed_key(key);
+        loop {
+            let current = self.read(&key).await?;
+            if current.is_some_and(|current| current >= offset) {
+                return Ok(());
+            }
+            match self.kv.cas(&*key, current, offset, true).await {
+                Err(KvError::PreconditionFailed) => {
+                    log::debug!("{key} changed since it was read, retrying");
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// The committed offsets of `keys`, leaving out those nobody committed.
+    ///
+    /// # Errors
+    /// - forwards [`KvError`]s
+    pub async fn committed(&self, keys: Vec<String>) -> Result<HashMap<String, u64>, KvError> {
+        let mut offsets = HashMap::new();
+        for key in keys {
+            if let Some(offset) = self.read(&committed_key(&key)).await? {
+                offsets.insert(key, offset);
+            }
+        }
+        Ok(offsets)
+    }
+}
+
+fn committed_key(key: &str) -> String {
+    format!("committed-{key}")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use serde_json::json;
+
+    use super::{Logs, POLL_LIMIT};
+
+    #[test]
+    fn polls_from_each_offset_up_to_the_limit() {
+        let mut logs = Logs::default();
+        for i in 0..150 {
+            assert_eq!(logs.append("a".to_string(), json!(i)), i);
+        }
+        // stored out of order, as allocated offsets may be
+        logs.insert("b".to_string(), 1, json!("y"));
+        logs.insert("b".to_string(), 0, json!("x"));
+
+        let offsets = HashMap::from([
+            ("a".to_string(), 10),
+            ("b".to_string(), 0),
+            ("c".to_string(), 0),
+        ]);
+        let msgs = logs.poll(&offsets);
+        assert_eq!(msgs["a"].len(), POLL_LIMIT);
+        assert_eq!(msgs["a"][0], (10, json!(10)));
+        assert_eq!(msgs["b"], [(0, json!("x")), (1, json!("y"))]);
+        assert!(!msgs.contains_key("c"));
+        assert_eq!(logs.append("b".to_string(), json!("z")), 2);
+    }
+}
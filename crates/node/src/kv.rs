@@ -0,0 +1,218 @@
+//! Clients for Maelstrom's key-value services.
+
+use std::error;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use serde::de::DeserializeOwned;
+use serde_json::{Value, json};
+
+use crate::error::ErrorCode;
+use crate::rpc::RpcError;
+use crate::server::Server;
+use crate::service::{LIN_KV, LWW_KV, SEQ_KV, Service};
+
+#[derive(Debug)]
+pub enum KvError {
+    /// The key was never written.
+    KeyDoesNotExist,
+    /// A `cas` found another value than `from`.
+    PreconditionFailed,
+    /// The reply didn't hold a value of the expected type.
+    Malformed(serde_json::Error),
+    /// Anything else on the way, including other `error` replies from the service.
+    Rpc(RpcError),
+}
+
+impl fmt::Display for KvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::KeyDoesNotExist => write!(f, "{}", ErrorCode::KeyDoesNotExist),
+            Self::PreconditionFailed => write!(f, "{}", ErrorCode::PreconditionFailed),
+            Self::Malformed(e) => write!(f, "unexpected value in reply: {e}"),
+            Self::Rpc(e) => e.fmt(f),
+        }
+    }
+}
+
+impl error::Error for KvError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::Malformed(e) => Some(e),
+            Self::Rpc(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<RpcError> for KvError {
+    fn from(e: RpcError) -> Self {
+        match e {
+            RpcError::Remote(e) if e.code == ErrorCode::KeyDoesNotExist => Self::KeyDoesNotExist,
+            RpcError::Remote(e) if e.code == ErrorCode::PreconditionFailed => {
+                Self::PreconditionFailed
+            }
+            e => Self::Rpc(e),
+        }
+    }
+}
+
+// handlers can bail out with `?` on a kv error, replying with its code
+impl From<KvError> for crate::error::Error {
+    fn from(e: KvError) -> Self {
+        match e {
+            KvError::KeyDoesNotExist => Self::new(ErrorCode::KeyDoesNotExist, ""),
+            KvError::PreconditionFailed => Self::new(ErrorCode::PreconditionFailed, ""),
+            KvError::Rpc(RpcError::Remote(e)) => e,
+            KvError::Rpc(RpcError::Timeout) => Self::new(ErrorCode::Timeout, e.to_string()),
+            e => Self::crash(e.to_string()),
+        }
+    }
+}
+
+/// Talks to one of Maelstrom's key-value services on behalf of a server.
+#[derive(Debug, Clone)]
+pub struct KvClient {
+    service: Service,
+}
+
+impl KvClient {
+    #[must_use]
+    pub const fn new(service: Service) -> Self {
+        Self { service }
+    }
+
+    pub fn seq_kv(server: Arc<Mutex<dyn Server + Send + Sync + 'static>>) -> Self {
+        Self::new(Service::new(server, SEQ_KV))
+    }
+
+    pub fn lin_kv(server: Arc<Mutex<dyn Server + Send + Sync + 'static>>) -> Self {
+        Self::new(Service::new(server, LIN_KV))
+    }
+
+    pub fn lww_kv(server: Arc<Mutex<dyn Server + Send + Sync + 'static>>) -> Self {
+        Self::new(Service::new(server, LWW_KV))
+    }
+
+    #[must_use]
+    pub const fn service(&self) -> &Service {
+        &self.service
+    }
+
+    async fn call(&self, body: Value) -> Result<Value, KvError> {
+        let reply = self.service.call(body).await?;
+        Ok(reply.body)
+    }
+
+    /// # Errors
+    /// - [`KvError::KeyDoesNotExist`] if `key` was never written
+    /// - [`KvError::Malformed`] if the value isn't a `V`
+    /// - [`KvError::Rpc`] for anything else
+    pub async fn read<V: DeserializeOwned>(&self, key: impl Into<Value>) -> Result<V, KvError> {
+        let body = self
+            .call(json!({"type": "read", "key": key.into()}))
+            .await?;
+        serde_json::from_value(body["value"].clone()).map_err(KvError::Malformed)
+    }
+
+    /// # Errors
+    /// - [`KvError::Rpc`] if the write didn't go through
+    pub async fn write(
+        &self,
+        key: impl Into<Value>,
+        value: impl Into<Value>,
+    ) -> Result<(), KvError> {
+        let body = json!({"type": "write", "key": key.into(), "value": value.into()});
+        self.call(body).await.map(drop)
+    }
+
+    /// Sets `key` to `to` if it holds `from`. With `create_if_not_exists`, a key that was never
+    /// written is set to `to` as well.
+    ///
+    /// # Errors
+    /// - [`KvError::PreconditionFailed`] if `key` holds something else
+    /// - [`KvError::KeyDoesNotExist`] if `key` was never written and may not be created
+    /// - [`KvError::Rpc`] for anything else
+    pub async fn cas(
+        &self,
+        key: impl Into<Value>,
+        from: impl Into<Value>,
+        to: impl Into<Value>,
+        create_if_not_exists: bool,
+    ) -> Result<(), KvError> {
+        let body = json!({
+            "type": "cas",
+            "key": key.into(),
+            "from": from.into(),
+            "to": to.into(),
+            "create_if_not_exists": create_if_not_exists,
+        });
+        self.call(body).await.map(drop)
+    }
+}
+
+#[cfg(all(test, feature = "sim"))]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use serde_json::json;
+    use tokio::time::Duration;
+
+    use super::{KvClient, KvError};
+    use crate::service::{LIN_KV, Retry, Service};
+    use crate::sim::{Consistency, KvStore, Sim, SimConfig};
+    use crate::{Node, TsoClient, build_default_handlers};
+
+    #[test]
+    fn clients_talk_to_the_services() {
+        let mut handlers = build_default_handlers();
+        handlers.insert(
+            "run",
+            Arc::new(|srv, msg| {
+                Box::pin(async move {
+                    let retry = Retry {
+                        attempts: 20,
+                        ..Retry::default()
+                    };
+                    let kv = KvClient::new(Service::new(srv.clone(), LIN_KV).with_retry(retry));
+                    assert!(matches!(
+                        kv.read::<u64>("k").await,
+                        Err(KvError::KeyDoesNotExist)
+                    ));
+                    kv.cas("k", 0, 1, true).await?;
+                    assert!(matches!(
+                        kv.cas("k", 0, 2, false).await,
+                        Err(KvError::PreconditionFailed)
+                    ));
+                    kv.write("k", 5).await?;
+                    assert_eq!(kv.read::<u64>("k").await?, 5);
+
+                    let tso = TsoClient::lin_tso(srv.clone());
+                    let first = tso.ts().await?;
+                    assert!(tso.ts().await? > first);
+
+                    let reply = srv.lock().unwrap().build_reply("run_ok", &msg, json!({}));
+                    if let Some(reply) = reply {
+                        srv.lock().unwrap().send(&reply)?;
+                    }
+                    Ok(())
+                })
+            }),
+        );
+        let flaky = KvStore::new(Consistency::Linearizable).with_unavailable_rate(0.5);
+
+        Sim::new(SimConfig::default())
+            .service(LIN_KV, flaky)
+            .nodes(1, move || {
+                (Arc::new(Mutex::new(Node::default())), handlers.clone())
+            })
+            .run(|cluster| async move {
+                let mut client = cluster.client();
+                client
+                    .request("n0", json!({"type": "run"}), Duration::from_secs(5))
+                    .await
+                    .unwrap();
+                assert!(cluster.stats().service_msgs > 12);
+            });
+    }
+}
@@ -1,21 +1,57 @@
-use std::collections::HashMap;
-use std::sync::Mutex;
-
-use once_cell::sync::Lazy;
-
 // Module declarations
+pub mod calvin;
+pub mod checker;
+pub mod consensus;
+pub mod crdt;
+pub mod error;
 pub mod handlers;
+pub mod kafka;
+pub mod kv;
 pub mod messaging;
+pub mod outbox;
+pub mod partition;
+pub mod paxos;
+pub mod payload;
+pub mod raft;
+pub mod rpc;
 pub mod server;
+pub mod service;
+pub mod shutdown;
+#[cfg(feature = "sim")]
+pub mod sim;
+pub mod topology;
+pub mod transport;
+pub mod tso;
+pub mod txn;
 pub mod types;
 
-pub use handlers::{FnHandler, HandlersMap, build_default_handlers};
-pub use messaging::{handle_msg, listen, send_synchronous, serve};
+pub use calvin::Sequencer;
+pub use consensus::{Replica, StateMachine};
+pub use crdt::{Crdt, Gossip};
+pub use error::{Error, ErrorCode};
+pub use handlers::{FnHandler, HandlersMap, ORPHAN_REPLY, TypedHandlers, build_default_handlers};
+pub use kv::{KvClient, KvError};
+pub use messaging::{handle_msg, listen, send_synchronous, serve, serve_with};
+pub use outbox::{FlushPolicy, Outbox, OutboxMetrics};
+pub use partition::{Partitioner, relay};
+pub use paxos::{MultiPaxos, Registers};
+pub use payload::{Body, Payload};
+pub use raft::Raft;
+pub use rpc::{PendingRpc, RpcError, rpc};
 pub use server::Server;
-pub use types::{Message, Node, SequentialKV};
+pub use service::{LIN_KV, LIN_TSO, LWW_KV, Retry, SEQ_KV, Service};
+pub use shutdown::{Shutdown, TaskGuard};
+pub use topology::{Overlay, Topology, TopologyStats};
+pub use transport::{Channel, Incoming, Outgoing, Stdio, Tcp, Transport};
+pub use tso::TsoClient;
+pub use txn::list_append::{ListOp, Lists};
+pub use txn::{Isolation, MicroOp, TxnExecutor};
+pub use types::{Message, Node, PendingReplies, SequentialKV};
 
-// Global callback store
-// ammar: not sure about the global variable thing, maybe bring it back into the types ?
-type CallbackStore = Lazy<Mutex<HashMap<u64, Box<dyn FnOnce() + Send + Sync>>>>;
+// re-exported for `payload!`
+pub use serde;
 
-pub static CALLBACKS: CallbackStore = Lazy::new(|| Mutex::new(HashMap::new()));
+#[doc(hidden)]
+pub mod __private {
+    pub use serde as __node_serde;
+}
@@ -1,76 +1,146 @@
 use std::io;
 use std::sync::{Arc, Mutex};
 
-use tokio::task;
+use serde_json::json;
 
-use crate::CALLBACKS;
-use crate::handlers::HandlersMap;
+use crate::error::{Error, ErrorCode};
+use crate::handlers::{HandlersMap, ORPHAN_REPLY};
+use crate::rpc::{self, PendingRpc, RpcError};
 use crate::server::Server;
+use crate::transport::{Incoming, Stdio, TCP_ADDR_VAR, Tcp, Transport};
 use crate::types::Message;
 
+/// Reads messages from `incoming` and forwards them to `tx` until the other side is gone.
+///
 /// # Errors
-pub async fn listen(tx: tokio::sync::mpsc::Sender<Message>) -> io::Result<()> {
+/// - forwards `io` errors
+pub async fn listen<I: Incoming>(
+    mut incoming: I,
+    tx: tokio::sync::mpsc::Sender<Message>,
+) -> io::Result<()> {
     log::info!("starting listener loop");
-    loop {
-        let mut input = String::new();
-        io::stdin().read_line(&mut input)?;
-        log::info!("message: {input}");
-        match serde_json::from_str::<Message>(&input) {
-            Ok(msg) => {
-                if let Err(e) = tx.send(msg).await {
-                    log::error!("{e}");
-                }
-            }
-            Err(e) => {
-                log::error!("{e}");
-            }
+    while let Some(msg) = incoming.recv().await? {
+        if tx.send(msg).await.is_err() {
+            log::error!("message thread is gone, stopping listener loop");
+            return Ok(());
         }
     }
+    log::info!("input closed, stopping listener loop");
+    Ok(())
 }
 
+/// Dispatches `msg` to its handler, replying with a Maelstrom error when that fails.
+///
+/// Requests without a `type` get a `malformed-request` error and requests no handler is
+/// registered for get a `not-supported` one.
+///
+/// Replies to pending requests are handed to whoever is waiting on them, other replies go to the
+/// [`ORPHAN_REPLY`] hook and are silently dropped when it isn't registered.
 /// # Errors
-/// - forwards `serde_json` errors
-/// - returns an error if the message type is not found in the handlers map
+/// - returns an error describing why the message couldn't be handled
 /// # Panics
-/// - Panics if `msg["body"]["type"]` is not a string
 /// - panics if the mutex on `node_arc` is poisoned
 pub async fn handle_msg(
     server: Arc<Mutex<dyn Server + Send + Sync + 'static>>,
     handlers_map: &HandlersMap<dyn Server + Send + Sync + 'static>,
     msg: Message,
 ) -> Result<(), String> {
-    {
-        if let Some(callback) = msg.body["id"]
-            .as_u64()
-            .and_then(|id| CALLBACKS.lock().unwrap().remove(&id))
-        {
-            callback();
+    let Some(msg) = rpc::deliver_reply(&server, msg) else {
+        return Ok(());
+    };
+
+    // replies are never dispatched by type, nobody asked for this one
+    let msg_type = if msg.body.get("in_reply_to").is_some() {
+        ORPHAN_REPLY
+    } else if let Some(msg_type) = msg.body["type"].as_str() {
+        msg_type
+    } else {
+        let error = Error::malformed("missing `.body.type` field");
+        reply_error(&server, &msg, &error);
+        return Err(format!("{error} in {msg:?}"));
+    };
+
+    let Some(handler) = handlers_map.get(msg_type) else {
+        if msg_type == ORPHAN_REPLY {
+            return Ok(());
         }
+        let error = Error::new(ErrorCode::NotSupported, format!("unknown type {msg_type}"));
+        reply_error(&server, &msg, &error);
+        return Err(format!("handler {msg_type} not found"));
+    };
+
+    // the handler consumes the message, keep what's needed to reply with an error
+    let request = Message {
+        src: msg.src.clone(),
+        dest: msg.dest.clone(),
+        body: json!({ "msg_id": msg.body["msg_id"] }),
+    };
+    let msg_type = msg_type.to_string();
+    if let Err(error) = handler(server.clone(), msg).await {
+        reply_error(&server, &request, &error);
+        return Err(format!("handler {msg_type} failed: {error}"));
     }
-
-    let msg_type = msg.body["type"].as_str().unwrap();
-    _ = handlers_map
-        .get(msg_type)
-        .ok_or(format!("handler {msg_type} not found"))?(server, msg)
-    .await;
     Ok(())
 }
 
+fn reply_error(
+    server: &Arc<Mutex<dyn Server + Send + Sync + 'static>>,
+    msg: &Message,
+    error: &Error,
+) {
+    let sent = server.lock().unwrap().reply_error(msg, error);
+    if let Err(e) = sent {
+        log::error!("failed to send error reply: {e}");
+    }
+}
+
+/// Handles messages over Maelstrom's stdin/stdout, or over TCP when [`TCP_ADDR_VAR`] is set.
+///
 /// # Errors
 /// - forwards `io` errors
 pub async fn serve(
     node: Arc<Mutex<dyn Server + Send + Sync + 'static>>,
     handlers: HandlersMap<dyn Server + Send + Sync + 'static>,
 ) -> io::Result<()> {
+    match std::env::var(TCP_ADDR_VAR) {
+        Ok(addr) => {
+            log::info!("serving over tcp, connecting to {addr}");
+            serve_with(node, handlers, Tcp::connect(addr).await?).await
+        }
+        Err(_) => serve_with(node, handlers, Stdio).await,
+    }
+}
+
+/// Handles messages from `transport` until its input is closed.
+///
+/// Once the input is closed, the server's [`Shutdown`](crate::Shutdown) is triggered and this
+/// waits for in-flight handlers and retry loops to finish, then for the outbox to be written out,
+/// before returning.
+/// # Errors
+/// - forwards `io` errors
+/// # Panics
+/// Panics if the mutex on `node` is poisoned.
+pub async fn serve_with<T: Transport>(
+    node: Arc<Mutex<dyn Server + Send + Sync + 'static>>,
+    handlers: HandlersMap<dyn Server + Send + Sync + 'static>,
+    transport: T,
+) -> io::Result<()> {
+    let (incoming, outgoing) = transport.split()?;
     // 10 is an arbitrary value, the size doesn't actually matter (wink, wink)
     let (tx, mut rx) = tokio::sync::mpsc::channel(10);
-
-    tokio::spawn(async move {
+    let (shutdown, outbox) = {
+        let srv = node.lock().unwrap();
+        (srv.get_shutdown(), srv.get_outbox().clone())
+    };
+    let writer = outbox.spawn_writer(outgoing)?;
+
+    let dispatch_shutdown = shutdown.clone();
+    let dispatcher = tokio::spawn(async move {
         log::info!("starting message thread");
         while let Some(msg) = rx.recv().await {
             let node_mut = node.clone();
             let h = handlers.clone();
-            tokio::spawn(async move {
+            dispatch_shutdown.spawn(async move {
                 if let Err(e) = handle_msg(node_mut, &h, msg).await {
                     log::error!("failed to handle message: {e}");
                 }
@@ -78,9 +148,25 @@ pub async fn serve(
         }
     });
 
-    listen(tx).await
+    let listened = listen(incoming, tx).await;
+
+    log::info!("shutting down, waiting for in-flight tasks");
+    shutdown.trigger();
+    if let Err(e) = dispatcher.await {
+        log::error!("message thread failed: {e}");
+    }
+    shutdown.drain().await;
+
+    outbox.close();
+    let written = writer.await.map_err(io::Error::other)?;
+    log::info!("outbox: {:?}", outbox.metrics());
+    listened.and(written)
 }
 
+/// Sends `msg` and keeps resending it every `message_timout` until it is acknowledged.
+///
+/// Once the server shuts down, the message is given up on after its current attempt.
+///
 /// # Errors
 /// - forwards `serde_json` errors
 /// - forwards `io` errors
@@ -88,57 +174,45 @@ pub async fn serve(
 /// This function will panic if the mutex on `node_arc` is poisoned.
 pub fn send_synchronous(
     server_mut: &Arc<Mutex<dyn Server + Send + Sync + 'static>>,
-    mut msg: Message,
+    msg: Message,
     message_timout: tokio::time::Duration,
 ) -> io::Result<()> {
-    let mut server = server_mut.lock().unwrap();
-    let mut msg_count = server.get_msg_count();
-    msg_count += 1;
-    server.set_msg_count(msg_count);
-
-    msg.body["id"] = msg_count.into();
+    let (server_id, dest) = (msg.src.clone(), msg.dest.clone());
+    let shutdown = server_mut.lock().unwrap().get_shutdown();
+    let mut pending = PendingRpc::start(server_mut, msg)?;
 
     log::info!(
-        "{server_id}: using message number {msg_count} for destination {dest}",
-        dest = msg.dest,
-        server_id = server.get_id()
+        "{server_id}: using message number {msg_id} for destination {dest}",
+        msg_id = pending.msg_id(),
     );
 
-    let (tx, mut rx) = tokio::sync::oneshot::channel::<()>();
-
-    // register the callback
-    {
-        CALLBACKS.lock().unwrap().insert(
-            msg_count,
-            Box::new(move || {
-                _ = tx.send(());
-            }),
-        );
-    }
-
-    // initial send
-    server.send(&msg)?;
-
-    let server_mut_copy = server_mut.clone();
-    task::spawn(async move {
+    shutdown.clone().spawn(async move {
         loop {
-            tokio::select! {
-                _ = &mut rx => {
-                    break;
-                },
-
-                () = tokio::time::sleep(message_timout) => {
-                    let srv = server_mut_copy.lock().unwrap();
-                    log::info!("node {}: receiving a response from {} timed out, sending again", srv.get_id(), msg.dest);
-                    if let Err(e) = srv.send(&msg) {
-                        log::error!("failed to send echo_ok: {e}");
+            match pending.wait(message_timout).await {
+                // an error reply still means the message got there
+                Ok(_) | Err(RpcError::Remote(_)) => break,
+                Err(RpcError::Timeout) if shutdown.is_triggered() => {
+                    log::warn!("giving up on message {}: shutting down", pending.msg_id());
+                    return;
+                }
+                Err(RpcError::Timeout) => {
+                    log::info!(
+                        "receiving a response to message {} timed out, sending again",
+                        pending.msg_id()
+                    );
+                    if let Err(e) = pending.resend() {
+                        log::error!("failed to resend message {}: {e}", pending.msg_id());
                     }
-                },
+                }
+                Err(e) => {
+                    log::error!("giving up on message {}: {e}", pending.msg_id());
+                    return;
+                }
             }
         }
 
         // ammar: add payload identification for different message types
-        log::info!("node {} received message", msg.dest);
+        log::info!("message {} was acknowledged", pending.msg_id());
     });
     Ok(())
 }
@@ -0,0 +1,313 @@
+use std::io;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+use crate::transport::Outgoing;
+use crate::types::Message;
+
+/// When the writer task flushes what it wrote.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlushPolicy {
+    /// Write and flush every message on its own.
+    EachMessage,
+    /// Write whatever is queued, up to `max_messages`, and flush once.
+    Batch { max_messages: usize },
+}
+
+impl Default for FlushPolicy {
+    fn default() -> Self {
+        Self::Batch { max_messages: 64 }
+    }
+}
+
+/// A snapshot of the outbox counters.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OutboxMetrics {
+    pub enqueued: u64,
+    pub written: u64,
+    /// Zero for transports that don't serialize.
+    pub bytes_written: u64,
+    pub flushes: u64,
+    /// Messages enqueued but not written yet.
+    pub queue_depth: usize,
+    /// Deepest the queue has been, a sign the writer can't keep up.
+    pub max_queue_depth: usize,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    enqueued: AtomicU64,
+    written: AtomicU64,
+    bytes_written: AtomicU64,
+    flushes: AtomicU64,
+    queue_depth: AtomicUsize,
+    max_queue_depth: AtomicUsize,
+}
+
+enum Queued {
+    Message(Message),
+    Close,
+}
+
+/// Outgoing messages of a server, written out one line each by a single writer task.
+///
+/// Sending only enqueues, so it is cheap to do while holding the server's mutex.
+#[derive(Clone)]
+pub struct Outbox {
+    tx: mpsc::UnboundedSender<Queued>,
+    rx: Arc<Mutex<Option<mpsc::UnboundedReceiver<Queued>>>>,
+    closed: Arc<AtomicBool>,
+    policy: FlushPolicy,
+    counters: Arc<Counters>,
+}
+
+impl std::fmt::Debug for Outbox {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Outbox")
+            .field("policy", &self.policy)
+            .field("metrics", &self.metrics())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Default for Outbox {
+    fn default() -> Self {
+        Self::with_policy(FlushPolicy::default())
+    }
+}
+
+impl Outbox {
+    #[must_use]
+    pub fn with_policy(policy: FlushPolicy) -> Self {
+        let (tx, rx) = mpsc::unbounded_channel();
+        Self {
+            tx,
+            rx: Arc::new(Mutex::new(Some(rx))),
+            closed: Arc::new(AtomicBool::new(false)),
+            policy,
+            counters: Arc::default(),
+        }
+    }
+
+    /// Queues `msg` for the writer task.
+    ///
+    /// # Errors
+    /// - returns [`io::ErrorKind::BrokenPipe`] once the outbox is closed
+    pub fn send(&self, msg: Message) -> io::Result<()> {
+        if self.closed.load(Ordering::SeqCst) {
+            return Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "outbox is closed",
+            ));
+        }
+        // counted before the writer can take it off the queue, or its decrement could come first
+        self.counters.enqueued.fetch_add(1, Ordering::Relaxed);
+        let depth = self.counters.queue_depth.fetch_add(1, Ordering::Relaxed) + 1;
+        self.counters
+            .max_queue_depth
+            .fetch_max(depth, Ordering::Relaxed);
+        self.tx.send(Queued::Message(msg)).map_err(|_| {
+            self.counters.enqueued.fetch_sub(1, Ordering::Relaxed);
+            self.counters.queue_depth.fetch_sub(1, Ordering::Relaxed);
+            io::Error::new(io::ErrorKind::BrokenPipe, "writer task is gone")
+        })?;
+        Ok(())
+    }
+
+    #[must_use]
+    pub fn metrics(&self) -> OutboxMetrics {
+        let c = &self.counters;
+        OutboxMetrics {
+            enqueued: c.enqueued.load(Ordering::Relaxed),
+            written: c.written.load(Ordering::Relaxed),
+            bytes_written: c.bytes_written.load(Ordering::Relaxed),
+            flushes: c.flushes.load(Ordering::Relaxed),
+            queue_depth: c.queue_depth.load(Ordering::Relaxed),
+            max_queue_depth: c.max_queue_depth.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Stops accepting messages, the writer task exits once everything queued is written.
+    pub fn close(&self) {
+        if !self.closed.swap(true, Ordering::SeqCst) {
+            _ = self.tx.send(Queued::Close);
+        }
+    }
+
+    /// Starts the writer task draining this outbox into `outgoing`.
+    ///
+    /// # Errors
+    /// - returns an error if a writer task was already started for this outbox
+    /// # Panics
+    /// Panics if the mutex guarding the receiving end is poisoned.
+    pub fn spawn_writer<O: Outgoing>(&self, outgoing: O) -> io::Result<JoinHandle<io::Result<()>>> {
+        let rx = self.rx.lock().unwrap().take().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::AlreadyExists, "writer task already started")
+        })?;
+        Ok(tokio::spawn(write_loop(
+            rx,
+            outgoing,
+            self.policy,
+            self.counters.clone(),
+        )))
+    }
+}
+
+async fn write_loop<O: Outgoing>(
+    mut rx: mpsc::UnboundedReceiver<Queued>,
+    mut outgoing: O,
+    policy: FlushPolicy,
+    counters: Arc<Counters>,
+) -> io::Result<()> {
+    let max_messages = match policy {
+        FlushPolicy::EachMessage => 1,
+        FlushPolicy::Batch { max_messages } => max_messages.max(1),
+    };
+
+    let mut closing = false;
+    while !closing {
+        let Some(first) = rx.recv().await else {
+            break;
+        };
+
+        let mut queued = vec![first];
+        while queued.len() < max_messages {
+            match rx.try_recv() {
+                Ok(next) => queued.push(next),
+                Err(_) => break,
+            }
+        }
+
+        let mut batch = Vec::with_capacity(queued.len());
+        for next in queued {
+            match next {
+                Queued::Message(msg) => batch.push(msg),
+                Queued::Close => closing = true,
+            }
+        }
+        if batch.is_empty() {
+            continue;
+        }
+
+        let count = batch.len();
+        counters.queue_depth.fetch_sub(count, Ordering::Relaxed);
+        let bytes = outgoing.send_batch(batch).await?;
+        counters.written.fetch_add(count as u64, Ordering::Relaxed);
+        counters.bytes_written.fetch_add(bytes, Ordering::Relaxed);
+        counters.flushes.fetch_add(1, Ordering::Relaxed);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::{FlushPolicy, Outbox};
+    use crate::transport::{Channel, Transport};
+    use crate::types::Message;
+
+    /// Queues five messages before starting the writer, then closes the outbox and returns it
+    /// once everything is written, along with what the other end received.
+    async fn write_five(policy: FlushPolicy) -> (Outbox, Vec<Message>) {
+        let message = |i: u64| Message {
+            src: "n1".to_string(),
+            dest: "n2".to_string(),
+            body: json!({"type": "broadcast", "message": i}),
+        };
+        let outbox = Outbox::with_policy(policy);
+        for i in 0..5 {
+            outbox.send(message(i)).unwrap();
+        }
+        outbox.close();
+        assert!(outbox.send(message(5)).is_err());
+
+        let (ours, mut theirs) = Channel::pair();
+        let (_, outgoing) = ours.split().unwrap();
+        outbox
+            .spawn_writer(outgoing)
+            .unwrap()
+            .await
+            .unwrap()
+            .unwrap();
+        let mut received = Vec::new();
+        while let Some(msg) = theirs.recv().await {
+            received.push(msg);
+        }
+        (outbox, received)
+    }
+
+    #[tokio::test]
+    async fn batches_flush_whatever_is_queued_at_once() {
+        let (outbox, received) = write_five(FlushPolicy::Batch { max_messages: 3 }).await;
+        let sent: Vec<_> = received.iter().map(|m| m.body["message"].clone()).collect();
+        assert_eq!(sent, [0, 1, 2, 3, 4]);
+        let metrics = outbox.metrics();
+        assert_eq!((metrics.enqueued, metrics.written), (5, 5));
+        assert_eq!(metrics.flushes, 2);
+        assert_eq!((metrics.queue_depth, metrics.max_queue_depth), (0, 5));
+
+        let (outbox, received) = write_five(FlushPolicy::EachMessage).await;
+        assert_eq!(received.len(), 5);
+        assert_eq!(outbox.metrics().flushes, 5);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+    async fn metrics_stay_sane_while_the_writer_drains_concurrently() {
+        // one message a write, so the writer races every send
+        let outbox = Outbox::with_policy(FlushPolicy::EachMessage);
+        let (ours, mut theirs) = Channel::pair();
+        let (_, outgoing) = ours.split().unwrap();
+        let writer = outbox.spawn_writer(outgoing).unwrap();
+        // a depth counted after the writer took the message off the queue wraps, for a moment
+        let watching = outbox.clone();
+        let watcher = tokio::spawn(async move {
+            loop {
+                let metrics = watching.metrics();
+                assert!(metrics.queue_depth <= 40_000, "{metrics:?}");
+                if metrics.written == 40_000 {
+                    return;
+                }
+                tokio::task::yield_now().await;
+            }
+        });
+
+        let senders: Vec<_> = (0..4_u64)
+            .map(|s| {
+                let outbox = outbox.clone();
+                tokio::spawn(async move {
+                    for i in 0..10_000 {
+                        let body = json!({"type": "broadcast", "message": s * 10_000 + i});
+                        outbox
+                            .send(Message {
+                                src: "n1".to_string(),
+                                dest: "n2".to_string(),
+                                body,
+                            })
+                            .unwrap();
+                        tokio::task::yield_now().await;
+                    }
+                })
+            })
+            .collect();
+        for sender in senders {
+            sender.await.unwrap();
+        }
+        outbox.close();
+        writer.await.unwrap().unwrap();
+        watcher.await.unwrap();
+
+        let mut received = 0;
+        while theirs.recv().await.is_some() {
+            received += 1;
+        }
+        assert_eq!(received, 40_000);
+        let metrics = outbox.metrics();
+        assert_eq!((metrics.enqueued, metrics.written), (40_000, 40_000));
+        assert_eq!(metrics.queue_depth, 0);
+        assert!(metrics.max_queue_depth <= 40_000, "{metrics:?}");
+    }
+}
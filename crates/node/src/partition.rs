@@ -0,0 +1,117 @@
+//! Spreading keys over servers, and relaying requests to the server owning their key.
+
+use std::sync::{Arc, Mutex};
+
+use serde::Serialize;
+use serde_json::json;
+use tokio::time::Duration;
+
+use crate::error::{Error, ErrorCode};
+use crate::payload::Body;
+use crate::rpc::{RpcError, rpc};
+use crate::server::Server;
+use crate::types::Message;
+
+/// Assigns every key to one of a fixed set of servers by hashing it.
+///
+/// The hash doesn't depend on the process, so every server agrees on the owners as long as they
+/// agree on the members.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Partitioner {
+    nodes: Vec<String>,
+}
+
+impl Partitioner {
+    /// # Panics
+    /// Panics if `nodes` is empty.
+    #[must_use]
+    pub fn new(mut nodes: Vec<String>) -> Self {
+        assert!(!nodes.is_empty(), "keys need at least one node to go to");
+        nodes.sort();
+        nodes.dedup();
+        Self { nodes }
+    }
+
+    #[must_use]
+    pub fn owner(&self, key: &str) -> &str {
+        // FNV-1a, unlike `DefaultHasher` it is stable across builds
+        let hash = key.bytes().fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+        });
+        let index = usize::try_from(hash % self.nodes.len() as u64).unwrap_or_default();
+        &self.nodes[index]
+    }
+
+    #[must_use]
+    pub fn nodes(&self) -> &[String] {
+        &self.nodes
+    }
+}
+
+/// Forwards the request `msg` to `owner` and relays its reply back to whoever sent `msg`.
+///
+/// # Errors
+/// - the error `owner` replied with, which the handler then relays
+/// - [`ErrorCode::Timeout`] if `owner` doesn't reply within `timeout`
+/// - fails if the request or the relayed reply can't be sent
+/// # Panics
+/// Panics if the mutex on `server` is poisoned.
+pub async fn relay<P: Serialize + Sync>(
+    server: &Arc<Mutex<dyn Server + Send + Sync>>,
+    msg: &Message<Body<P>>,
+    owner: &str,
+    timeout: Duration,
+) -> Result<(), Error> {
+    let body = json!(Body::new(&msg.body.payload));
+    let mut reply = match rpc(server, owner, body, timeout).await {
+        Ok(reply) => reply.body,
+        Err(RpcError::Remote(e)) => return Err(e),
+        Err(RpcError::Timeout) => {
+            return Err(Error::new(
+                ErrorCode::Timeout,
+                format!("{owner} didn't reply"),
+            ));
+        }
+        Err(e) => return Err(Error::crash(format!("relaying to {owner} failed: {e}"))),
+    };
+    if let Some(reply) = reply.as_object_mut() {
+        reply.remove("msg_id");
+    }
+    reply["in_reply_to"] = json!(msg.body.msg_id);
+
+    let relayed = Message {
+        src: msg.dest.clone(),
+        dest: msg.src.clone(),
+        body: reply,
+    };
+    let sent = server.lock().unwrap().send(&relayed);
+    Ok(sent?)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::Partitioner;
+
+    #[test]
+    fn keys_spread_over_every_node() {
+        let nodes: Vec<String> = (0..5).map(|i| format!("n{i}")).collect();
+        let partitioner = Partitioner::new(nodes.iter().rev().cloned().collect());
+        let mut owned: HashMap<&str, usize> = HashMap::new();
+        for key in 0..1000 {
+            *owned
+                .entry(partitioner.owner(&key.to_string()))
+                .or_default() += 1;
+        }
+        assert_eq!(owned.len(), 5);
+        assert!(owned.values().all(|&n| n > 150), "{owned:?}");
+
+        // the order the members come in doesn't matter
+        let other = Partitioner::new(nodes);
+        assert!((0..100).all(|key| {
+            let key = key.to_string();
+            partitioner.owner(&key) == other.owner(&key)
+        }));
+    }
+}
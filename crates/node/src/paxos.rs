@@ -0,0 +1,23 @@
+//! Paxos, to weigh against [`Raft`](crate::Raft): single-decree registers agreeing on one value
+//! per key at a time, and a Multi-Paxos log driving a [`StateMachine`](crate::StateMachine).
+//!
+//! Both order their proposals by [`Ballot`]s, and need a majority of acceptors to promise or
+//! accept one, so the side of a partition without a majority makes no progress.
+
+pub mod multi;
+pub mod register;
+
+pub use multi::MultiPaxos;
+pub use register::Registers;
+
+/// A proposal number: a round, then the id of the proposer so no two proposers share one.
+pub type Ballot = (u64, String);
+
+/// The ballot no proposal has, below every other.
+const fn zero() -> Ballot {
+    (0, String::new())
+}
+
+const fn majority(nodes: usize) -> usize {
+    nodes / 2 + 1
+}
@@ -0,0 +1,512 @@
+//! Multi-Paxos: a log of slots, each decided by its own instance of Paxos, sharing ballots.
+//!
+//! A server whose leader went quiet prepares a higher ballot for every slot from the first it
+//! hasn't seen chosen. Once a majority promises, it re-proposes what they accepted there, fills
+//! the gaps with no-ops, and from then on proposes each client command in one round of accepts,
+//! until a higher ballot preempts it. Like [`Raft`](crate::Raft), followers forward client
+//! requests to the leader they know of and reads go through the log.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use tokio::sync::oneshot;
+use tokio::time::{Duration, Instant};
+
+use super::{Ballot, majority, zero};
+use crate::consensus::{Applied, Engine, Outgoing, Replica, Role, StateMachine};
+use crate::error::{Error, ErrorCode};
+use crate::types::Message;
+
+/// Type of the messages a candidate prepares its ballot with, fire-and-forget like the others.
+pub const PREPARE: &str = "paxos_prepare";
+pub const PROMISE: &str = "paxos_promise";
+/// Type of the messages a leader proposes commands, ships chosen ones and heartbeats with.
+pub const ACCEPT: &str = "paxos_accept";
+pub const ACCEPTED: &str = "paxos_accepted";
+
+const HEARTBEAT_INTERVAL: Duration = Duration::from_millis(50);
+/// Bounds of the randomized time a follower waits for the leader before preparing a ballot.
+const ELECTION_TIMEOUT: (Duration, Duration) =
+    (Duration::from_millis(150), Duration::from_millis(300));
+/// Proposals, and chosen entries, shipped in one message at most.
+const MAX_ENTRIES: usize = 64;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Entry {
+    /// The ballot that first proposed the command, which tells its client whether the slot
+    /// went to it.
+    ballot: Ballot,
+    /// `null` for the no-ops a new leader fills gaps with.
+    command: Value,
+}
+
+/// A client waiting on the entry at some slot, as long as it still is the one its ballot
+/// proposed.
+type Waiter = (Ballot, oneshot::Sender<Result<Value, Error>>);
+
+/// The Multi-Paxos state of one server, which a [`MultiPaxos`] runs.
+#[derive(Debug)]
+pub struct Core<S> {
+    machine: S,
+    id: String,
+    peers: Vec<String>,
+    role: Role,
+    leader: Option<String>,
+    /// The highest ballot this server promised, its own while it's a candidate or the leader.
+    promised: Ballot,
+    /// What this server accepted in each slot, and under which ballot.
+    accepted: BTreeMap<u64, (Ballot, Entry)>,
+    chosen: BTreeMap<u64, Entry>,
+    /// Slots start at 1, every one up to this one is chosen and applied.
+    last_applied: u64,
+    /// What the servers that promised the candidate's ballot accepted from the first slot it
+    /// hasn't seen chosen.
+    promises: HashMap<String, Vec<(u64, Ballot, Entry)>>,
+    /// The leader's proposals that aren't chosen yet, and who accepted them.
+    proposals: BTreeMap<u64, (Entry, BTreeSet<String>)>,
+    next_slot: u64,
+    /// How far each peer applied, so the leader knows which chosen entries to ship it.
+    learned: HashMap<String, u64>,
+    election_deadline: Instant,
+    heartbeat_due: Instant,
+    waiting: HashMap<u64, Waiter>,
+}
+
+impl<S: StateMachine> Core<S> {
+    fn new(machine: S) -> Self {
+        Self {
+            machine,
+            id: String::new(),
+            peers: Vec::new(),
+            role: Role::Follower,
+            leader: None,
+            promised: zero(),
+            accepted: BTreeMap::new(),
+            chosen: BTreeMap::new(),
+            last_applied: 0,
+            promises: HashMap::new(),
+            proposals: BTreeMap::new(),
+            next_slot: 1,
+            learned: HashMap::new(),
+            election_deadline: Instant::now(),
+            heartbeat_due: Instant::now(),
+            waiting: HashMap::new(),
+        }
+    }
+
+    const fn majority(&self) -> usize {
+        majority(self.peers.len() + 1)
+    }
+
+    fn reset_election_timer(&mut self) {
+        let (min, max) = ELECTION_TIMEOUT;
+        self.election_deadline = Instant::now() + rand::rng().random_range(min..max);
+    }
+
+    /// Catches up with a higher `ballot` seen in a message, as a follower.
+    fn observe(&mut self, ballot: &Ballot) {
+        if *ballot > self.promised {
+            self.promised = ballot.clone();
+            self.leader = None;
+            self.role = Role::Follower;
+            self.promises.clear();
+            self.proposals.clear();
+        }
+    }
+
+    fn accepted_from(&self, slot: u64) -> Vec<(u64, Ballot, Entry)> {
+        self.accepted
+            .range(slot..)
+            .map(|(&slot, (ballot, entry))| (slot, ballot.clone(), entry.clone()))
+            .collect()
+    }
+
+    fn become_leader(&mut self) -> Outgoing {
+        log::info!("{} leads with ballot {:?}", self.id, self.promised);
+        self.role = Role::Leader;
+        self.leader = Some(self.id.clone());
+
+        // the value of a slot chosen before is the one accepted under the highest ballot
+        let mut recovered: BTreeMap<u64, (Ballot, Entry)> = BTreeMap::new();
+        for (slot, ballot, entry) in self.promises.drain().flat_map(|(_, accepted)| accepted) {
+            if recovered
+                .get(&slot)
+                .is_none_or(|(highest, _)| ballot > *highest)
+            {
+                recovered.insert(slot, (ballot, entry));
+            }
+        }
+        let last = [recovered.keys().last(), self.chosen.keys().last()]
+            .into_iter()
+            .flatten()
+            .copied()
+            .max()
+            .unwrap_or_default()
+            .max(self.last_applied);
+        for slot in self.last_applied + 1..=last {
+            if self.chosen.contains_key(&slot) {
+                continue;
+            }
+            let entry = recovered.remove(&slot).map_or_else(
+                || Entry {
+                    ballot: self.promised.clone(),
+                    command: Value::Null,
+                },
+                |(_, entry)| entry,
+            );
+            self.propose(slot, entry);
+        }
+        self.next_slot = last + 1;
+        self.learned = self.peers.iter().map(|peer| (peer.clone(), 0)).collect();
+        self.heartbeat_due = Instant::now() + HEARTBEAT_INTERVAL;
+        self.peers.iter().map(|peer| self.proposal(peer)).collect()
+    }
+
+    fn prepare(&mut self, ballot: &Ballot, body: &Value) -> Value {
+        self.observe(ballot);
+        let from_slot = body["from_slot"].as_u64().unwrap_or_default();
+        let accepted = if *ballot == self.promised {
+            self.reset_election_timer();
+            self.accepted_from(from_slot)
+        } else {
+            Vec::new()
+        };
+        json!({"type": PROMISE, "ballot": self.promised, "accepted": accepted})
+    }
+
+    /// Counts a promise, replying with the promised ballot, or a refusal with a higher one.
+    fn promise(&mut self, from: &str, ballot: &Ballot, body: &Value) -> Result<Outgoing, Error> {
+        self.observe(ballot);
+        if self.role != Role::Candidate || *ballot != self.promised {
+            return Ok(Vec::new());
+        }
+        let accepted = serde_json::from_value(body["accepted"].clone())
+            .map_err(|e| Error::malformed(e.to_string()))?;
+        self.promises.insert(from.to_string(), accepted);
+        if self.promises.len() >= self.majority() {
+            return Ok(self.become_leader());
+        }
+        Ok(Vec::new())
+    }
+
+    /// Has the leader's own acceptor accept `entry` in `slot`, then waits for the others.
+    fn propose(&mut self, slot: u64, entry: Entry) {
+        self.accepted
+            .insert(slot, (self.promised.clone(), entry.clone()));
+        self.proposals
+            .insert(slot, (entry, BTreeSet::from([self.id.clone()])));
+        self.tally(slot);
+    }
+
+    /// What `peer` hasn't accepted of the proposals and hasn't learned of the chosen entries,
+    /// which makes a heartbeat if nothing.
+    fn proposal(&self, peer: &str) -> (String, Value) {
+        let entries: Vec<(u64, &Entry)> = self
+            .proposals
+            .iter()
+            .filter(|(_, (_, acceptors))| !acceptors.contains(peer))
+            .map(|(&slot, (entry, _))| (slot, entry))
+            .take(MAX_ENTRIES)
+            .collect();
+        let learned = self.learned.get(peer).copied().unwrap_or_default();
+        let decided: Vec<(u64, &Entry)> = self
+            .chosen
+            .range(learned + 1..)
+            .map(|(&slot, entry)| (slot, entry))
+            .take(MAX_ENTRIES)
+            .collect();
+        let body = json!({
+            "type": ACCEPT,
+            "ballot": self.promised,
+            "entries": entries,
+            "decided": decided,
+        });
+        (peer.to_string(), body)
+    }
+
+    fn accept(&mut self, from: &str, ballot: &Ballot, body: &Value) -> Result<Value, Error> {
+        // chosen entries are chosen whoever ships them
+        let decided: Vec<(u64, Entry)> = serde_json::from_value(body["decided"].clone())
+            .map_err(|e| Error::malformed(e.to_string()))?;
+        for (slot, entry) in decided {
+            if slot > self.last_applied {
+                self.chosen.insert(slot, entry);
+            }
+        }
+        self.apply_chosen();
+
+        self.observe(ballot);
+        let mut slots = Vec::new();
+        if *ballot == self.promised {
+            self.role = Role::Follower;
+            self.leader = Some(from.to_string());
+            self.reset_election_timer();
+            let entries: Vec<(u64, Entry)> = serde_json::from_value(body["entries"].clone())
+                .map_err(|e| Error::malformed(e.to_string()))?;
+            for (slot, entry) in entries {
+                self.accepted.insert(slot, (ballot.clone(), entry));
+                slots.push(slot);
+            }
+        }
+        Ok(json!({
+            "type": ACCEPTED,
+            "ballot": self.promised,
+            "slots": slots,
+            "learned": self.last_applied,
+        }))
+    }
+
+    fn accepted_by(&mut self, from: &str, ballot: &Ballot, body: &Value) {
+        self.observe(ballot);
+        if self.role != Role::Leader || *ballot != self.promised {
+            return;
+        }
+        let learned = body["learned"].as_u64().unwrap_or_default();
+        self.learned.insert(from.to_string(), learned);
+        for slot in body["slots"].as_array().into_iter().flatten() {
+            let Some(slot) = slot.as_u64() else {
+                continue;
+            };
+            if let Some((_, acceptors)) = self.proposals.get_mut(&slot) {
+                acceptors.insert(from.to_string());
+                self.tally(slot);
+            }
+        }
+    }
+
+    /// Chooses the proposal in `slot` once a majority accepted it.
+    fn tally(&mut self, slot: u64) {
+        let majority = self.majority();
+        if self
+            .proposals
+            .get(&slot)
+            .is_some_and(|(_, acceptors)| acceptors.len() >= majority)
+            && let Some((entry, _)) = self.proposals.remove(&slot)
+        {
+            self.chosen.insert(slot, entry);
+            self.apply_chosen();
+        }
+    }
+
+    fn apply_chosen(&mut self) {
+        while let Some(entry) = self.chosen.get(&(self.last_applied + 1)) {
+            self.last_applied += 1;
+            let result = (!entry.command.is_null()).then(|| self.machine.apply(&entry.command));
+            let Some((ballot, waiter)) = self.waiting.remove(&self.last_applied) else {
+                continue;
+            };
+            let result = if ballot == entry.ballot {
+                result.unwrap_or_else(|| Err(Error::crash("applied a no-op for a client")))
+            } else {
+                Err(Error::new(
+                    ErrorCode::TemporarilyUnavailable,
+                    "another ballot took the slot of the command",
+                ))
+            };
+            let _ = waiter.send(result);
+        }
+    }
+}
+
+impl<S: StateMachine> Engine for Core<S> {
+    type Machine = S;
+    const NAME: &'static str = "multi-paxos";
+    const MESSAGES: &'static [&'static str] = &[PREPARE, PROMISE, ACCEPT, ACCEPTED];
+
+    fn start(&mut self, id: String, nodes: Vec<String>) {
+        self.peers = nodes.into_iter().filter(|node| *node != id).collect();
+        self.id = id;
+        self.reset_election_timer();
+    }
+
+    /// Handles one of the Multi-Paxos messages, returning the messages to send in response.
+    fn handle(&mut self, msg: &Message) -> Result<Outgoing, Error> {
+        let from = msg.src.clone();
+        let ballot: Ballot = serde_json::from_value(msg.body["ballot"].clone())
+            .map_err(|e| Error::malformed(e.to_string()))?;
+        match msg.body["type"].as_str() {
+            Some(PREPARE) => Ok(vec![(from, self.prepare(&ballot, &msg.body))]),
+            Some(PROMISE) => self.promise(&from, &ballot, &msg.body),
+            Some(ACCEPT) => Ok(vec![(
+                from.clone(),
+                self.accept(&from, &ballot, &msg.body)?,
+            )]),
+            Some(ACCEPTED) => {
+                self.accepted_by(&from, &ballot, &msg.body);
+                Ok(Vec::new())
+            }
+            _ => Err(Error::new(ErrorCode::NotSupported, "not a paxos message")),
+        }
+    }
+
+    fn tick(&mut self) -> Outgoing {
+        let now = Instant::now();
+        if self.role == Role::Leader {
+            if now < self.heartbeat_due {
+                return Vec::new();
+            }
+            self.heartbeat_due = now + HEARTBEAT_INTERVAL;
+            return self.peers.iter().map(|peer| self.proposal(peer)).collect();
+        }
+        if now < self.election_deadline {
+            return Vec::new();
+        }
+
+        self.promised = (self.promised.0 + 1, self.id.clone());
+        self.role = Role::Candidate;
+        self.leader = None;
+        let from_slot = self.last_applied + 1;
+        self.promises = HashMap::from([(self.id.clone(), self.accepted_from(from_slot))]);
+        self.reset_election_timer();
+        log::debug!("{} prepares ballot {:?}", self.id, self.promised);
+        if self.promises.len() >= self.majority() {
+            return self.become_leader();
+        }
+        let body = json!({"type": PREPARE, "ballot": self.promised, "from_slot": from_slot});
+        self.peers
+            .iter()
+            .map(|peer| (peer.clone(), body.clone()))
+            .collect()
+    }
+
+    /// Proposes `command` in the next slot if this server leads, or says which server does.
+    fn submit(&mut self, command: Value) -> Result<Applied, Option<String>> {
+        if self.role != Role::Leader {
+            return Err(self.leader.clone());
+        }
+        let slot = self.next_slot;
+        self.next_slot += 1;
+        let (tx, rx) = oneshot::channel();
+        self.waiting.insert(slot, (self.promised.clone(), tx));
+        let entry = Entry {
+            ballot: self.promised.clone(),
+            command,
+        };
+        self.propose(slot, entry);
+        // ship it with the next tick rather than wait for the heartbeat
+        self.heartbeat_due = Instant::now();
+        Ok(rx)
+    }
+
+    fn role(&self) -> Role {
+        self.role
+    }
+
+    fn leader(&self) -> Option<String> {
+        self.leader.clone()
+    }
+
+    fn machine(&self) -> &S {
+        &self.machine
+    }
+}
+
+/// A server's Multi-Paxos replica of `S`, running once [`Replica::install`]ed.
+pub type MultiPaxos<S> = Replica<Core<S>>;
+
+impl<S: StateMachine> MultiPaxos<S> {
+    pub fn new(machine: S) -> Self {
+        Self::from_engine(Core::new(machine))
+    }
+
+    /// The highest ballot this server promised.
+    ///
+    /// # Panics
+    /// Panics if the mutex on the replica is poisoned.
+    #[must_use]
+    pub fn ballot(&self) -> Ballot {
+        self.lock().promised.clone()
+    }
+
+    /// The slot up to which every command is chosen and applied here.
+    ///
+    /// # Panics
+    /// Panics if the mutex on the replica is poisoned.
+    #[must_use]
+    pub fn last_applied(&self) -> u64 {
+        self.lock().last_applied
+    }
+}
+
+#[cfg(all(test, feature = "sim"))]
+mod tests {
+
+    use serde_json::{Value, json};
+    use tokio::time::Duration;
+
+    use super::MultiPaxos;
+    use crate::consensus::kv::LinKv;
+    use crate::consensus::{Role, sim_replicas};
+    use crate::sim::{Client, Fault};
+    use crate::types::Message;
+
+    /// Sends `body` to `dest` until it's answered, which is fine for idempotent requests.
+    async fn until_answered(client: &mut Client, dest: &str, body: Value) -> Message {
+        loop {
+            if let Ok(reply) = client
+                .request(dest, body.clone(), Duration::from_secs(1))
+                .await
+            {
+                return reply;
+            }
+        }
+    }
+
+    #[test]
+    fn a_higher_ballot_takes_over_and_keeps_the_chosen_commands() {
+        sim_replicas(
+            30,
+            || MultiPaxos::new(LinKv::new()),
+            async |cluster, replicas| {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                let leaders: Vec<_> = replicas
+                    .iter()
+                    .filter(|paxos| paxos.role() == Role::Leader)
+                    .collect();
+                assert_eq!(leaders.len(), 1);
+                let (leader, ballot) = (leaders[0].leader().unwrap(), leaders[0].ballot());
+
+                let mut client = cluster.client();
+                for i in 0..5_u64 {
+                    let body = json!({"type": "write", "key": i, "value": i});
+                    let reply = until_answered(&mut client, &leader, body).await;
+                    assert_eq!(reply.body["type"], "write_ok");
+                }
+
+                let others: Vec<String> = cluster
+                    .node_ids()
+                    .iter()
+                    .filter(|id| **id != leader)
+                    .cloned()
+                    .collect();
+                cluster.apply(&Fault::Components(vec![
+                    vec![leader.clone()],
+                    others.clone(),
+                ]));
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                let successor = replicas
+                    .iter()
+                    .find(|paxos| paxos.role() == Role::Leader && paxos.ballot() > ballot)
+                    .expect("no new leader");
+                let successor = successor.leader().unwrap();
+                assert_ne!(successor, leader);
+                for i in 0..5_u64 {
+                    let body = json!({"type": "read", "key": i});
+                    let reply = until_answered(&mut client, &others[0], body).await;
+                    assert_eq!(reply.body["value"], i);
+                }
+
+                // the old leader steps down and learns what it missed once it hears of the ballot
+                cluster.apply(&Fault::Heal);
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                let leaders = replicas.iter().filter(|paxos| paxos.role() == Role::Leader);
+                assert_eq!(leaders.count(), 1);
+                let applied = replicas[0].last_applied();
+                assert!(applied >= 10);
+                assert!(replicas.iter().all(|paxos| paxos.last_applied() == applied));
+            },
+        );
+    }
+}
@@ -0,0 +1,378 @@
+//! Single-decree Paxos registers, one per key: every change runs both phases anew, proposing
+//! what a function makes of the value a majority last accepted (as in `CASPaxos`).
+//!
+//! There is no leader, any server can change any register, and a value is chosen once a
+//! majority accepts it. Proposers racing on a key preempt each other with higher ballots and
+//! back off at random.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use rand::Rng;
+use serde_json::{Value, json};
+use tokio::sync::mpsc;
+use tokio::time::{self, Duration, Instant};
+
+use super::{Ballot, majority};
+use crate::error::{Error, ErrorCode};
+use crate::handlers::HandlersMap;
+use crate::rpc::rpc;
+use crate::server::Server;
+
+/// Type of the requests a proposer prepares its ballot on a key with.
+pub const PREPARE: &str = "paxos_register_prepare";
+pub const PREPARE_OK: &str = "paxos_register_prepare_ok";
+/// Type of the requests a proposer has its value accepted with.
+pub const ACCEPT: &str = "paxos_register_accept";
+pub const ACCEPT_OK: &str = "paxos_register_accept_ok";
+
+/// How long one round of requests waits for the acceptors.
+const ROUND_TIMEOUT: Duration = Duration::from_millis(200);
+/// How long a change keeps retrying before giving up.
+const CHANGE_TIMEOUT: Duration = Duration::from_secs(1);
+/// Upper bound of the random pause before retrying a preempted or unanswered round.
+const MAX_BACKOFF: Duration = Duration::from_millis(30);
+
+type Srv = Arc<Mutex<dyn Server + Send + Sync>>;
+
+/// What a server's acceptor knows of one register. `None` values are registers nobody created.
+#[derive(Debug, Clone, Default)]
+struct Acceptor {
+    promised: Ballot,
+    accepted: (Ballot, Option<Value>),
+}
+
+impl Acceptor {
+    /// Promises `ballot` unless it promised a higher one, replying with its promise and what
+    /// it accepted.
+    fn prepare(&mut self, ballot: &Ballot) -> Value {
+        if *ballot > self.promised {
+            self.promised = ballot.clone();
+        }
+        json!({"promised": self.promised, "accepted": self.accepted})
+    }
+
+    /// Accepts `value` under `ballot` unless it promised a higher one, replying with its
+    /// promise.
+    fn accept(&mut self, ballot: &Ballot, value: Option<Value>) -> Value {
+        if *ballot >= self.promised {
+            self.promised = ballot.clone();
+            self.accepted = (ballot.clone(), value);
+        }
+        json!({"promised": self.promised})
+    }
+}
+
+/// A server's acceptors of every register, and its proposer, serving once
+/// [`Registers::install`]ed.
+///
+/// Clones share the acceptors.
+#[derive(Debug, Clone, Default)]
+pub struct Registers {
+    acceptors: Arc<Mutex<HashMap<String, Acceptor>>>,
+    /// The highest round seen, so the next ballot beats it.
+    round: Arc<Mutex<u64>>,
+}
+
+impl Registers {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers the handlers of the acceptors' requests.
+    ///
+    /// # Panics
+    /// Panics if the mutex on the server is poisoned.
+    pub fn install(&self, handlers: &mut HandlersMap<dyn Server + Send + Sync>) {
+        for (r#type, reply_type) in [(PREPARE, PREPARE_OK), (ACCEPT, ACCEPT_OK)] {
+            let registers = self.clone();
+            handlers.insert(
+                r#type,
+                Arc::new(move |srv_mutex, msg| {
+                    let registers = registers.clone();
+                    Box::pin(async move {
+                        let body = registers.acceptor_reply(&msg.body)?;
+                        let srv = srv_mutex.lock().unwrap();
+                        let Some(reply) = srv.build_reply(reply_type, &msg, body) else {
+                            return Ok(());
+                        };
+                        Ok(srv.send(&reply)?)
+                    })
+                }),
+            );
+        }
+    }
+
+    /// Has this server's acceptor of the key answer a prepare or accept request.
+    fn acceptor_reply(&self, body: &Value) -> Result<Value, Error> {
+        let key = body["key"]
+            .as_str()
+            .ok_or_else(|| Error::malformed("missing key"))?;
+        let ballot: Ballot = serde_json::from_value(body["ballot"].clone())
+            .map_err(|e| Error::malformed(e.to_string()))?;
+        let mut acceptors = self.acceptors.lock().unwrap();
+        let acceptor = acceptors.entry(key.to_string()).or_default();
+        let reply = match body["type"].as_str() {
+            Some(PREPARE) => acceptor.prepare(&ballot),
+            Some(ACCEPT) => {
+                let value = &body["value"];
+                acceptor.accept(&ballot, (!value.is_null()).then(|| value.clone()))
+            }
+            _ => {
+                return Err(Error::new(
+                    ErrorCode::NotSupported,
+                    "not a register request",
+                ));
+            }
+        };
+        drop(acceptors);
+        Ok(reply)
+    }
+
+    /// Changes the register `key` to what `f` makes of its current value, `None` if nobody
+    /// created it, returning the value it changed to. If `f` fails, the current value is
+    /// written back as it is, so the failure is as linearizable as a change.
+    ///
+    /// # Errors
+    /// - what `f` failed with
+    /// - `temporarily-unavailable` if no majority of acceptors ever promised a ballot
+    /// - `timeout` if no majority accepted in time, the change may still be chosen later
+    ///
+    /// # Panics
+    /// Panics if the mutex on the server is poisoned.
+    pub async fn change(
+        &self,
+        srv_mutex: &Srv,
+        key: &str,
+        f: impl Fn(Option<&Value>) -> Result<Option<Value>, Error>,
+    ) -> Result<Option<Value>, Error> {
+        let (id, nodes) = {
+            let srv = srv_mutex.lock().unwrap();
+            (srv.get_id(), srv.get_topology())
+        };
+        let peers: Vec<String> = nodes.into_iter().filter(|peer| *peer != id).collect();
+        let majority = majority(peers.len() + 1);
+        let deadline = Instant::now() + CHANGE_TIMEOUT;
+        // a proposal whose accepts went unanswered, which may still be chosen
+        let mut pending: Option<(Ballot, Result<Option<Value>, Error>)> = None;
+
+        while Instant::now() < deadline {
+            let ballot = {
+                let mut round = self.round.lock().unwrap();
+                *round += 1;
+                (*round, id.clone())
+            };
+
+            let body = json!({"type": PREPARE, "key": key, "ballot": ballot});
+            let promises = self
+                .round_trip(srv_mutex, &peers, body, &ballot, majority)
+                .await;
+            if promises.len() >= majority {
+                // the value chosen last, if any, is the one accepted under the highest ballot
+                let (latest, current) = promises
+                    .into_iter()
+                    .filter_map(|promise| {
+                        serde_json::from_value::<(Ballot, Option<Value>)>(
+                            promise["accepted"].clone(),
+                        )
+                        .ok()
+                    })
+                    .max_by(|(a, _), (b, _)| a.cmp(b))
+                    .unwrap_or_default();
+                let changed = match pending.take() {
+                    // the pending proposal is what was last accepted, finish it rather than
+                    // apply `f` on top of it
+                    Some((proposed, changed)) if proposed == latest => changed,
+                    Some((proposed, _)) if proposed < latest => {
+                        return Err(Error::new(
+                            ErrorCode::Timeout,
+                            "preempted after proposing, the change may have been chosen",
+                        ));
+                    }
+                    // a majority promised without accepting it, so it can't be chosen anymore
+                    _ => f(current.as_ref()),
+                };
+                let value = changed.as_ref().map_or(current, Clone::clone);
+
+                let body = json!({"type": ACCEPT, "key": key, "ballot": ballot, "value": value});
+                let accepts = self
+                    .round_trip(srv_mutex, &peers, body, &ballot, majority)
+                    .await;
+                if accepts.len() >= majority {
+                    return changed;
+                }
+                pending = Some((ballot, changed));
+            }
+            let backoff = rand::rng().random_range(Duration::ZERO..MAX_BACKOFF);
+            time::sleep(backoff).await;
+        }
+
+        if pending.is_some() {
+            Err(Error::new(
+                ErrorCode::Timeout,
+                "no majority accepted in time",
+            ))
+        } else {
+            Err(Error::new(
+                ErrorCode::TemporarilyUnavailable,
+                "no majority promised a ballot",
+            ))
+        }
+    }
+
+    /// Sends `body` to this server's acceptor and to every peer, returning the replies of those
+    /// that promised `ballot` as soon as a `majority` did, or of every one that did in time.
+    async fn round_trip(
+        &self,
+        srv_mutex: &Srv,
+        peers: &[String],
+        body: Value,
+        ballot: &Ballot,
+        majority: usize,
+    ) -> Vec<Value> {
+        let shutdown = srv_mutex.lock().unwrap().get_shutdown();
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        for peer in peers {
+            let (tx, srv_mutex, peer, body) =
+                (tx.clone(), srv_mutex.clone(), peer.clone(), body.clone());
+            shutdown.spawn(async move {
+                match rpc(&srv_mutex, &peer, body, ROUND_TIMEOUT).await {
+                    Ok(reply) => {
+                        let _ = tx.send(reply.body);
+                    }
+                    Err(e) => log::debug!("{peer} didn't answer: {e}"),
+                }
+            });
+        }
+        drop(tx);
+
+        let mut local = Some(self.acceptor_reply(&body).expect("a well-formed request"));
+        let mut promised = Vec::new();
+        while promised.len() < majority {
+            let reply = match local.take() {
+                Some(reply) => reply,
+                None => match rx.recv().await {
+                    Some(reply) => reply,
+                    None => break,
+                },
+            };
+            let Ok(seen) = serde_json::from_value::<Ballot>(reply["promised"].clone()) else {
+                continue;
+            };
+            if seen == *ballot {
+                promised.push(reply);
+            } else {
+                // preempted, the next ballot has to beat this one
+                let mut round = self.round.lock().unwrap();
+                *round = (*round).max(seen.0);
+            }
+        }
+        promised
+    }
+}
+
+#[cfg(all(test, feature = "sim"))]
+mod tests {
+    use std::collections::BTreeSet;
+    use std::sync::{Arc, Mutex};
+
+    use serde_json::{Value, json};
+    use tokio::time::Duration;
+
+    use super::Registers;
+    use crate::error::Error;
+    use crate::sim::{Fault, Nemesis, Sim, SimConfig};
+    use crate::{Node, build_default_handlers};
+
+    /// Serves `add`, which increments a counter register and replies with its new value.
+    fn counter() -> crate::HandlersMap<dyn crate::Server + Send + Sync> {
+        let registers = Registers::new();
+        let mut handlers = build_default_handlers();
+        registers.install(&mut handlers);
+        handlers.insert(
+            "add",
+            Arc::new(move |srv_mutex, msg| {
+                let registers = registers.clone();
+                Box::pin(async move {
+                    let value = registers
+                        .change(&srv_mutex, "counter", |value| {
+                            let value = value.and_then(Value::as_u64).unwrap_or_default();
+                            Ok::<_, Error>(Some(json!(value + 1)))
+                        })
+                        .await?;
+                    let srv = srv_mutex.lock().unwrap();
+                    let Some(reply) = srv.build_reply("add_ok", &msg, json!({"value": value}))
+                    else {
+                        return Ok(());
+                    };
+                    Ok(srv.send(&reply)?)
+                })
+            }),
+        );
+        handlers
+    }
+
+    #[test]
+    fn racing_proposers_never_choose_the_same_increment_twice() {
+        let nemesis = Nemesis::new().flapping(
+            &Fault::MajorityMinority,
+            Duration::from_millis(300),
+            Duration::from_secs(3),
+            Duration::from_millis(500),
+        );
+        Sim::new(SimConfig {
+            seed: 32,
+            drop_rate: 0.05,
+            ..SimConfig::default()
+        })
+        .nodes(5, || (Arc::new(Mutex::new(Node::default())), counter()))
+        .nemesis(nemesis)
+        .run(|cluster| async move {
+            let clients: Vec<_> = (0..5_usize)
+                .map(|c| {
+                    let cluster = cluster.clone();
+                    tokio::spawn(async move {
+                        let mut client = cluster.client();
+                        let mut seen = Vec::new();
+                        for _ in 0..20 {
+                            let dest = &cluster.node_ids()[c];
+                            let body = json!({"type": "add"});
+                            if let Ok(reply) =
+                                client.request(dest, body, Duration::from_secs(2)).await
+                                && reply.body["type"] == "add_ok"
+                            {
+                                seen.push(reply.body["value"].as_u64().unwrap());
+                            }
+                            tokio::time::sleep(Duration::from_millis(20)).await;
+                        }
+                        seen
+                    })
+                })
+                .collect();
+            let mut values = Vec::new();
+            for client in clients {
+                values.extend(client.await.unwrap());
+            }
+            assert!(cluster.stats().partitioned > 0);
+
+            let distinct: BTreeSet<u64> = values.iter().copied().collect();
+            assert_eq!(distinct.len(), values.len(), "{values:?}");
+            assert!(values.len() > 50, "{values:?}");
+
+            // increments that timed out may still have been chosen, never more than were asked
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            let mut client = cluster.client();
+            let reply = client
+                .request(
+                    &cluster.node_ids()[0],
+                    json!({"type": "add"}),
+                    Duration::from_secs(2),
+                )
+                .await
+                .unwrap();
+            let last = reply.body["value"].as_u64().unwrap();
+            assert!(last > *distinct.last().unwrap() && last <= 101, "{last}");
+        });
+    }
+}
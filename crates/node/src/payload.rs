@@ -0,0 +1,146 @@
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::json;
+
+use crate::types::Message;
+
+/// A workload's message payloads, a `#[serde(tag = "type")]` enum.
+///
+/// Declare them with [`payload!`](crate::payload) rather than implementing this by hand.
+pub trait Payload: Serialize + DeserializeOwned + Send + 'static {
+    /// The `type` of every variant.
+    const TYPES: &'static [&'static str];
+}
+
+/// A message body: a payload plus the Maelstrom request/reply bookkeeping.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Body<P> {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub msg_id: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub in_reply_to: Option<u64>,
+    #[serde(flatten)]
+    pub payload: P,
+}
+
+impl<P> Body<P> {
+    /// A body without `msg_id`, which gets stamped when the message is sent as a request.
+    pub const fn new(payload: P) -> Self {
+        Self {
+            msg_id: None,
+            in_reply_to: None,
+            payload,
+        }
+    }
+}
+
+impl<P> Message<Body<P>> {
+    /// Builds the reply to this message, sent back from its destination to its source.
+    #[must_use]
+    pub fn reply<R: Payload>(&self, payload: R) -> Message {
+        Message {
+            src: self.dest.clone(),
+            dest: self.src.clone(),
+            body: json!(Body {
+                msg_id: None,
+                in_reply_to: self.body.msg_id,
+                payload,
+            }),
+        }
+    }
+}
+
+impl Message {
+    /// Parses the untyped body into `P`.
+    ///
+    /// # Errors
+    /// - forwards `serde_json` errors when the body doesn't match any variant of `P`
+    pub fn parse<P: Payload>(self) -> serde_json::Result<Message<Body<P>>> {
+        Ok(Message {
+            src: self.src,
+            dest: self.dest,
+            body: serde_json::from_value(self.body)?,
+        })
+    }
+}
+
+/// Declares a [`Payload`] enum, each variant preceded by its `type` on the wire.
+///
+/// ```ignore
+/// node::payload! {
+///     pub enum Request {
+///         "echo" => Echo { echo: String },
+///         "read" => Read,
+///     }
+/// }
+/// ```
+#[macro_export]
+macro_rules! payload {
+    (
+        $(#[$meta:meta])*
+        $vis:vis enum $name:ident {
+            $(
+                $(#[$variant_meta:meta])*
+                $type:literal => $variant:ident $({ $($fields:tt)* })?
+            ),* $(,)?
+        }
+    ) => {
+        // `serde(crate)` takes a path as a string, which `$crate` can't be spelled in, so
+        // serde is brought into scope under a name nothing else uses. Globs importing the same
+        // item don't clash, leaving room for more than one payload per module.
+        #[allow(unused_imports)]
+        use $crate::__private::*;
+
+        $(#[$meta])*
+        // fields may be floats, so `Eq` is left to whoever declares the payload
+        #[allow(clippy::derive_partial_eq_without_eq)]
+        #[derive($crate::serde::Serialize, $crate::serde::Deserialize, Debug, Clone, PartialEq)]
+        #[serde(crate = "__node_serde", tag = "type")]
+        $vis enum $name {
+            $(
+                $(#[$variant_meta])*
+                #[serde(rename = $type)]
+                $variant $({ $($fields)* })?
+            ),*
+        }
+
+        impl $crate::Payload for $name {
+            const TYPES: &'static [&'static str] = &[$($type),*];
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::Payload;
+
+    crate::payload! {
+        enum Request {
+            "echo" => Echo { echo: String },
+            "read" => Read,
+        }
+    }
+
+    crate::payload! {
+        enum Reply {
+            "echo_ok" => EchoOk { echo: String },
+        }
+    }
+
+    #[test]
+    fn payloads_declared_side_by_side_inside_the_crate_round_trip() {
+        let echo = Request::Echo {
+            echo: "hi".to_string(),
+        };
+        assert_eq!(
+            serde_json::to_value(&echo).unwrap(),
+            json!({"type": "echo", "echo": "hi"})
+        );
+        let read: Request = serde_json::from_value(json!({"type": "read"})).unwrap();
+        assert_eq!(read, Request::Read);
+        assert_eq!(Request::TYPES, ["echo", "read"]);
+        assert_eq!(Reply::TYPES, ["echo_ok"]);
+    }
+}
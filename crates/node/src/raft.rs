@@ -0,0 +1,465 @@
+//! Raft consensus: the servers elect a leader that orders client commands in a replicated log,
+//! and every server applies the committed commands in order to its copy of a [`StateMachine`].
+//!
+//! Followers forward client requests to the leader they know of, a server knowing no leader
+//! turns them down with `temporarily-unavailable`. Reads go through the log like any other
+//! command, which makes them linearizable without leases.
+
+use std::collections::{BTreeSet, HashMap};
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use tokio::sync::oneshot;
+use tokio::time::{Duration, Instant};
+
+pub use crate::consensus::Role;
+pub use crate::consensus::kv::{self, KV_TYPES, LinKv};
+
+use crate::consensus::{Applied, Engine, Outgoing, Replica, StateMachine};
+use crate::error::{Error, ErrorCode};
+use crate::types::Message;
+
+/// Type of the messages a candidate asks for votes with, fire-and-forget like the others.
+pub const REQUEST_VOTE: &str = "raft_request_vote";
+pub const VOTE: &str = "raft_vote";
+/// Type of the messages a leader replicates its log and heartbeats with.
+pub const APPEND_ENTRIES: &str = "raft_append_entries";
+pub const APPEND_RESULT: &str = "raft_append_result";
+
+const HEARTBEAT_INTERVAL: Duration = Duration::from_millis(50);
+/// Bounds of the randomized time a follower waits for the leader before standing for election.
+const ELECTION_TIMEOUT: (Duration, Duration) =
+    (Duration::from_millis(150), Duration::from_millis(300));
+/// Entries shipped in one message at most.
+const MAX_ENTRIES: usize = 64;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Entry {
+    term: u64,
+    /// `null` for the entry a new leader starts its term with.
+    command: Value,
+}
+
+/// A client waiting on the entry at some index, as long as it still is from its term.
+type Waiter = (u64, oneshot::Sender<Result<Value, Error>>);
+
+/// The Raft state of one server, which a [`Raft`] runs.
+#[derive(Debug)]
+pub struct Core<S> {
+    machine: S,
+    id: String,
+    peers: Vec<String>,
+    role: Role,
+    term: u64,
+    voted_for: Option<String>,
+    leader: Option<String>,
+    /// The entry at index `i` is `log[i - 1]`, index 0 being the empty log.
+    log: Vec<Entry>,
+    commit_index: u64,
+    last_applied: u64,
+    votes: BTreeSet<String>,
+    next_index: HashMap<String, u64>,
+    match_index: HashMap<String, u64>,
+    election_deadline: Instant,
+    heartbeat_due: Instant,
+    waiting: HashMap<u64, Waiter>,
+}
+
+impl<S: StateMachine> Core<S> {
+    fn new(machine: S) -> Self {
+        Self {
+            machine,
+            id: String::new(),
+            peers: Vec::new(),
+            role: Role::Follower,
+            term: 0,
+            voted_for: None,
+            leader: None,
+            log: Vec::new(),
+            commit_index: 0,
+            last_applied: 0,
+            votes: BTreeSet::new(),
+            next_index: HashMap::new(),
+            match_index: HashMap::new(),
+            election_deadline: Instant::now(),
+            heartbeat_due: Instant::now(),
+            waiting: HashMap::new(),
+        }
+    }
+
+    const fn last_index(&self) -> u64 {
+        self.log.len() as u64
+    }
+
+    fn term_at(&self, index: u64) -> u64 {
+        index
+            .checked_sub(1)
+            .and_then(|i| self.log.get(usize::try_from(i).ok()?))
+            .map_or(0, |entry| entry.term)
+    }
+
+    const fn majority(&self) -> usize {
+        let nodes = self.peers.len() + 1;
+        nodes / 2 + 1
+    }
+
+    fn reset_election_timer(&mut self) {
+        let (min, max) = ELECTION_TIMEOUT;
+        self.election_deadline = Instant::now() + rand::rng().random_range(min..max);
+    }
+
+    /// Catches up with a newer `term` seen in a message, as a follower.
+    fn observe(&mut self, term: u64) {
+        if term > self.term {
+            self.term = term;
+            self.voted_for = None;
+            self.leader = None;
+            self.role = Role::Follower;
+        }
+    }
+
+    fn become_leader(&mut self) -> Outgoing {
+        log::info!("{} leads term {}", self.id, self.term);
+        self.role = Role::Leader;
+        self.leader = Some(self.id.clone());
+        // committing an entry of its own term commits the ones before it
+        self.log.push(Entry {
+            term: self.term,
+            command: Value::Null,
+        });
+        self.next_index = self
+            .peers
+            .iter()
+            .map(|peer| (peer.clone(), self.last_index()))
+            .collect();
+        self.match_index = self.peers.iter().map(|peer| (peer.clone(), 0)).collect();
+        self.heartbeat_due = Instant::now() + HEARTBEAT_INTERVAL;
+        self.advance_commit();
+        self.peers
+            .iter()
+            .map(|peer| self.append_entries(peer))
+            .collect()
+    }
+
+    fn request_vote(&mut self, body: &Value) -> Value {
+        let term = body["term"].as_u64().unwrap_or_default();
+        self.observe(term);
+        let candidate = body["candidate"].as_str().unwrap_or_default();
+        let last_log = (
+            body["last_log_term"].as_u64().unwrap_or_default(),
+            body["last_log_index"].as_u64().unwrap_or_default(),
+        );
+        let up_to_date = last_log >= (self.term_at(self.last_index()), self.last_index());
+        let granted = term == self.term
+            && self
+                .voted_for
+                .as_deref()
+                .is_none_or(|voted| voted == candidate)
+            && up_to_date;
+        if granted {
+            self.voted_for = Some(candidate.to_string());
+            self.reset_election_timer();
+        }
+        json!({"type": VOTE, "term": self.term, "granted": granted})
+    }
+
+    fn vote(&mut self, from: &str, body: &Value) -> Outgoing {
+        let term = body["term"].as_u64().unwrap_or_default();
+        self.observe(term);
+        if self.role != Role::Candidate || term != self.term || body["granted"] != true {
+            return Vec::new();
+        }
+        self.votes.insert(from.to_string());
+        if self.votes.len() >= self.majority() {
+            return self.become_leader();
+        }
+        Vec::new()
+    }
+
+    /// What `peer` is missing of the log, or a heartbeat if nothing.
+    fn append_entries(&self, peer: &str) -> (String, Value) {
+        let next = self.next_index.get(peer).copied().unwrap_or(1).max(1);
+        let prev = next - 1;
+        let entries: Vec<&Entry> = self
+            .log
+            .iter()
+            .skip(usize::try_from(prev).unwrap_or(usize::MAX))
+            .take(MAX_ENTRIES)
+            .collect();
+        let body = json!({
+            "type": APPEND_ENTRIES,
+            "term": self.term,
+            "prev_log_index": prev,
+            "prev_log_term": self.term_at(prev),
+            "entries": entries,
+            "leader_commit": self.commit_index,
+        });
+        (peer.to_string(), body)
+    }
+
+    fn append(&mut self, from: &str, body: &Value) -> Result<Value, Error> {
+        let term = body["term"].as_u64().unwrap_or_default();
+        self.observe(term);
+        let current = self.term;
+        let reply = |success: bool, index: u64| json!({"type": APPEND_RESULT, "term": current, "success": success, "index": index});
+        if term < self.term {
+            return Ok(reply(false, 0));
+        }
+        self.role = Role::Follower;
+        self.leader = Some(from.to_string());
+        self.reset_election_timer();
+
+        let prev = body["prev_log_index"].as_u64().unwrap_or_default();
+        if prev > self.last_index() {
+            return Ok(reply(false, self.last_index() + 1));
+        }
+        if self.term_at(prev) != body["prev_log_term"].as_u64().unwrap_or_default() {
+            return Ok(reply(false, prev));
+        }
+        let entries: Vec<Entry> = serde_json::from_value(body["entries"].clone())
+            .map_err(|e| Error::malformed(e.to_string()))?;
+        let mut index = prev;
+        for entry in entries {
+            index += 1;
+            if index <= self.last_index() {
+                if self.term_at(index) == entry.term {
+                    continue;
+                }
+                self.log
+                    .truncate(usize::try_from(index - 1).unwrap_or(usize::MAX));
+            }
+            self.log.push(entry);
+        }
+        let leader_commit = body["leader_commit"].as_u64().unwrap_or_default();
+        if leader_commit > self.commit_index {
+            self.commit_index = leader_commit.min(index);
+            self.apply_committed();
+        }
+        Ok(reply(true, index))
+    }
+
+    fn append_result(&mut self, from: &str, body: &Value) -> Outgoing {
+        let term = body["term"].as_u64().unwrap_or_default();
+        self.observe(term);
+        if self.role != Role::Leader || term != self.term {
+            return Vec::new();
+        }
+        let index = body["index"].as_u64().unwrap_or_default();
+        if body["success"] == true {
+            let matched = self.match_index.entry(from.to_string()).or_default();
+            *matched = index.max(*matched);
+            self.next_index.insert(from.to_string(), *matched + 1);
+            self.advance_commit();
+            if index >= self.last_index() {
+                return Vec::new();
+            }
+        } else {
+            // the follower says where its log stops matching
+            self.next_index.insert(from.to_string(), index.max(1));
+        }
+        vec![self.append_entries(from)]
+    }
+
+    /// Commits the entries of this term a majority has, and the ones before them.
+    fn advance_commit(&mut self) {
+        let mut matched: Vec<u64> = self.match_index.values().copied().collect();
+        matched.push(self.last_index());
+        matched.sort_unstable_by(|a, b| b.cmp(a));
+        let replicated = matched[self.majority() - 1];
+        if replicated > self.commit_index && self.term_at(replicated) == self.term {
+            self.commit_index = replicated;
+            self.apply_committed();
+        }
+    }
+
+    fn apply_committed(&mut self) {
+        while self.last_applied < self.commit_index {
+            self.last_applied += 1;
+            let entry = &self.log[usize::try_from(self.last_applied - 1).unwrap_or(usize::MAX)];
+            let result = (!entry.command.is_null()).then(|| self.machine.apply(&entry.command));
+            let Some((term, waiter)) = self.waiting.remove(&self.last_applied) else {
+                continue;
+            };
+            let result = if term == entry.term {
+                result.unwrap_or_else(|| Err(Error::crash("applied a no-op for a client")))
+            } else {
+                Err(Error::new(
+                    ErrorCode::TemporarilyUnavailable,
+                    "leadership changed before the command committed",
+                ))
+            };
+            let _ = waiter.send(result);
+        }
+    }
+}
+
+impl<S: StateMachine> Engine for Core<S> {
+    type Machine = S;
+    const NAME: &'static str = "raft";
+    const MESSAGES: &'static [&'static str] = &[REQUEST_VOTE, VOTE, APPEND_ENTRIES, APPEND_RESULT];
+
+    fn start(&mut self, id: String, nodes: Vec<String>) {
+        self.peers = nodes.into_iter().filter(|node| *node != id).collect();
+        self.id = id;
+        self.reset_election_timer();
+    }
+
+    /// Handles one of the Raft messages, returning the messages to send in response.
+    fn handle(&mut self, msg: &Message) -> Result<Outgoing, Error> {
+        let from = msg.src.clone();
+        match msg.body["type"].as_str() {
+            Some(REQUEST_VOTE) => Ok(vec![(from, self.request_vote(&msg.body))]),
+            Some(VOTE) => Ok(self.vote(&from, &msg.body)),
+            Some(APPEND_ENTRIES) => Ok(vec![(from.clone(), self.append(&from, &msg.body)?)]),
+            Some(APPEND_RESULT) => Ok(self.append_result(&from, &msg.body)),
+            _ => Err(Error::new(ErrorCode::NotSupported, "not a raft message")),
+        }
+    }
+
+    fn tick(&mut self) -> Outgoing {
+        let now = Instant::now();
+        if self.role == Role::Leader {
+            if now < self.heartbeat_due {
+                return Vec::new();
+            }
+            self.heartbeat_due = now + HEARTBEAT_INTERVAL;
+            return self
+                .peers
+                .iter()
+                .map(|peer| self.append_entries(peer))
+                .collect();
+        }
+        if now < self.election_deadline {
+            return Vec::new();
+        }
+
+        self.term += 1;
+        self.role = Role::Candidate;
+        self.voted_for = Some(self.id.clone());
+        self.leader = None;
+        self.votes = BTreeSet::from([self.id.clone()]);
+        self.reset_election_timer();
+        log::debug!("{} stands for election in term {}", self.id, self.term);
+        if self.votes.len() >= self.majority() {
+            return self.become_leader();
+        }
+        let body = json!({
+            "type": REQUEST_VOTE,
+            "term": self.term,
+            "candidate": self.id,
+            "last_log_index": self.last_index(),
+            "last_log_term": self.term_at(self.last_index()),
+        });
+        self.peers
+            .iter()
+            .map(|peer| (peer.clone(), body.clone()))
+            .collect()
+    }
+
+    /// Appends `command` to the log if this server leads, or says which server does.
+    fn submit(&mut self, command: Value) -> Result<Applied, Option<String>> {
+        if self.role != Role::Leader {
+            return Err(self.leader.clone());
+        }
+        self.log.push(Entry {
+            term: self.term,
+            command,
+        });
+        let (tx, rx) = oneshot::channel();
+        self.waiting.insert(self.last_index(), (self.term, tx));
+        // ship it with the next tick rather than wait for the heartbeat
+        self.heartbeat_due = Instant::now();
+        self.advance_commit();
+        Ok(rx)
+    }
+
+    fn role(&self) -> Role {
+        self.role
+    }
+
+    fn leader(&self) -> Option<String> {
+        self.leader.clone()
+    }
+
+    fn machine(&self) -> &S {
+        &self.machine
+    }
+}
+
+/// A server's Raft replica of `S`, running once [`Replica::install`]ed.
+pub type Raft<S> = Replica<Core<S>>;
+
+impl<S: StateMachine> Raft<S> {
+    pub fn new(machine: S) -> Self {
+        Self::from_engine(Core::new(machine))
+    }
+
+    /// # Panics
+    /// Panics if the mutex on the replica is poisoned.
+    #[must_use]
+    pub fn term(&self) -> u64 {
+        self.lock().term
+    }
+
+    /// # Panics
+    /// Panics if the mutex on the replica is poisoned.
+    #[must_use]
+    pub fn commit_index(&self) -> u64 {
+        self.lock().commit_index
+    }
+}
+
+#[cfg(all(test, feature = "sim"))]
+mod tests {
+    use tokio::time::Duration;
+
+    use super::{LinKv, Raft, Role};
+    use crate::consensus::sim_replicas;
+    use crate::sim::Fault;
+
+    #[test]
+    fn a_new_leader_takes_over_from_an_isolated_one() {
+        sim_replicas(
+            20,
+            || Raft::new(LinKv::new()),
+            async |cluster, replicas| {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                let leaders: Vec<_> = replicas
+                    .iter()
+                    .filter(|raft| raft.role() == Role::Leader)
+                    .collect();
+                assert_eq!(leaders.len(), 1);
+                let (leader, term) = (leaders[0].leader().unwrap(), leaders[0].term());
+                assert!(
+                    replicas
+                        .iter()
+                        .all(|raft| raft.leader() == Some(leader.clone()))
+                );
+
+                let others: Vec<String> = cluster
+                    .node_ids()
+                    .iter()
+                    .filter(|id| **id != leader)
+                    .cloned()
+                    .collect();
+                cluster.apply(&Fault::Components(vec![vec![leader.clone()], others]));
+                tokio::time::sleep(Duration::from_secs(1)).await;
+
+                let successor = replicas
+                    .iter()
+                    .find(|raft| raft.role() == Role::Leader && raft.term() > term)
+                    .expect("no new leader");
+                assert_ne!(successor.leader(), Some(leader));
+
+                // the old leader steps down once it hears of the new term
+                cluster.apply(&Fault::Heal);
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                let leaders = replicas.iter().filter(|raft| raft.role() == Role::Leader);
+                assert_eq!(leaders.count(), 1);
+                let commit = replicas[0].commit_index();
+                assert!(replicas.iter().all(|raft| raft.commit_index() == commit));
+            },
+        );
+    }
+}
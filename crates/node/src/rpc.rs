@@ -0,0 +1,284 @@
+use std::error;
+use std::fmt;
+use std::io;
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::oneshot;
+use tokio::time::Duration;
+
+use crate::error::Error;
+use crate::server::Server;
+use crate::types::Message;
+
+#[derive(Debug)]
+pub enum RpcError {
+    /// No reply arrived before the deadline.
+    Timeout,
+    /// The node dropped the request before a reply arrived.
+    Cancelled,
+    /// The request couldn't be written out.
+    Io(io::Error),
+    /// The destination replied with an `error` message.
+    Remote(Error),
+}
+
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Timeout => write!(f, "timed out waiting for a reply"),
+            Self::Cancelled => write!(f, "request was cancelled"),
+            Self::Io(e) => write!(f, "failed to send request: {e}"),
+            Self::Remote(e) => write!(f, "request failed: {e}"),
+        }
+    }
+}
+
+impl error::Error for RpcError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            Self::Remote(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for RpcError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+/// A request that has been sent and registered on the server, waiting for its reply.
+///
+/// Dropping it unregisters the request, so a late reply is no longer delivered.
+pub struct PendingRpc {
+    server: Arc<Mutex<dyn Server + Send + Sync + 'static>>,
+    msg: Message,
+    msg_id: u64,
+    rx: oneshot::Receiver<Message>,
+}
+
+impl fmt::Debug for PendingRpc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PendingRpc")
+            .field("msg", &self.msg)
+            .field("msg_id", &self.msg_id)
+            .finish_non_exhaustive()
+    }
+}
+
+impl PendingRpc {
+    /// Stamps `msg` with a fresh `msg_id`, registers it and sends it.
+    ///
+    /// # Errors
+    /// - forwards `io` errors from the initial send
+    /// # Panics
+    /// Panics if the mutex on `server_mut` is poisoned.
+    pub fn start(
+        server_mut: &Arc<Mutex<dyn Server + Send + Sync + 'static>>,
+        mut msg: Message,
+    ) -> io::Result<Self> {
+        let (tx, rx) = oneshot::channel();
+
+        let mut server = server_mut.lock().unwrap();
+        let msg_id = server.next_msg_id();
+        msg.body["msg_id"] = msg_id.into();
+        server.get_pending_replies().insert(msg_id, tx);
+
+        if let Err(e) = server.send(&msg) {
+            server.get_pending_replies().remove(&msg_id);
+            return Err(e);
+        }
+        drop(server);
+
+        Ok(Self {
+            server: server_mut.clone(),
+            msg,
+            msg_id,
+            rx,
+        })
+    }
+
+    #[must_use]
+    pub const fn msg_id(&self) -> u64 {
+        self.msg_id
+    }
+
+    /// Sends the request again under the same `msg_id`.
+    ///
+    /// # Errors
+    /// - forwards `io` errors
+    /// # Panics
+    /// Panics if the mutex on the server is poisoned.
+    pub fn resend(&self) -> io::Result<()> {
+        self.server.lock().unwrap().send(&self.msg)
+    }
+
+    /// Waits up to `timeout` for the reply.
+    ///
+    /// The request stays registered after a timeout, so it can be resent and waited on again.
+    ///
+    /// # Errors
+    /// - [`RpcError::Timeout`] if nothing arrived in time
+    /// - [`RpcError::Cancelled`] if the server dropped the request
+    /// - [`RpcError::Remote`] if the reply is an `error` message
+    pub async fn wait(&mut self, timeout: Duration) -> Result<Message, RpcError> {
+        match tokio::time::timeout(timeout, &mut self.rx).await {
+            Ok(Ok(reply)) => {
+                Error::from_body(&reply.body).map_or(Ok(reply), |e| Err(RpcError::Remote(e)))
+            }
+            Ok(Err(_)) => Err(RpcError::Cancelled),
+            Err(_) => Err(RpcError::Timeout),
+        }
+    }
+}
+
+impl Drop for PendingRpc {
+    fn drop(&mut self) {
+        if let Ok(mut server) = self.server.lock() {
+            server.get_pending_replies().remove(&self.msg_id);
+        }
+    }
+}
+
+/// Sends `body` to `dest` and waits for the reply carrying the matching `in_reply_to`.
+///
+/// Dropping the returned future cancels the request: it is unregistered and a late reply is
+/// ignored.
+///
+/// # Errors
+/// - [`RpcError::Io`] if the request couldn't be sent
+/// - [`RpcError::Timeout`] if no reply arrived within `timeout`
+/// - [`RpcError::Cancelled`] if the server dropped the request
+/// - [`RpcError::Remote`] if the reply is an `error` message
+/// # Panics
+/// Panics if the mutex on `server_mut` is poisoned.
+pub async fn rpc(
+    server_mut: &Arc<Mutex<dyn Server + Send + Sync + 'static>>,
+    dest: &str,
+    body: serde_json::Value,
+    timeout: Duration,
+) -> Result<Message, RpcError> {
+    let src = server_mut.lock().unwrap().get_id();
+    let msg = Message {
+        src,
+        dest: dest.to_string(),
+        body,
+    };
+    PendingRpc::start(server_mut, msg)?.wait(timeout).await
+}
+
+/// Hands `msg` to the request waiting on it, if any.
+///
+/// Returns the message back when it isn't a reply to a pending request.
+pub(crate) fn deliver_reply(
+    server_mut: &Arc<Mutex<dyn Server + Send + Sync + 'static>>,
+    msg: Message,
+) -> Option<Message> {
+    let Some(in_reply_to) = msg.body["in_reply_to"].as_u64() else {
+        return Some(msg);
+    };
+
+    let Some(tx) = server_mut
+        .lock()
+        .unwrap()
+        .get_pending_replies()
+        .remove(&in_reply_to)
+    else {
+        return Some(msg);
+    };
+
+    // the receiver may have given up already, which is fine
+    _ = tx.send(msg);
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use serde_json::json;
+    use tokio::time::Duration;
+
+    use super::{PendingRpc, RpcError, deliver_reply};
+    use crate::error::ErrorCode;
+    use crate::server::Server;
+    use crate::transport::{Channel, Transport};
+    use crate::types::{Message, Node};
+
+    /// A server called `n1`, and the other end of its outbox.
+    fn server() -> (Arc<Mutex<dyn Server + Send + Sync>>, Channel) {
+        let node = Node {
+            id: "n1".to_string(),
+            ..Node::default()
+        };
+        let (ours, theirs) = Channel::pair();
+        let (_, outgoing) = ours.split().unwrap();
+        node.outbox.spawn_writer(outgoing).unwrap();
+        (Arc::new(Mutex::new(node)), theirs)
+    }
+
+    fn reply(to: &Message, body: serde_json::Value) -> Message {
+        let mut body = body;
+        body["in_reply_to"] = to.body["msg_id"].clone();
+        Message {
+            src: to.dest.clone(),
+            dest: to.src.clone(),
+            body,
+        }
+    }
+
+    #[tokio::test]
+    async fn a_timed_out_request_is_resent_under_the_same_msg_id() {
+        let (srv, mut theirs) = server();
+        let request = Message {
+            src: "n1".to_string(),
+            dest: "n2".to_string(),
+            body: json!({"type": "read"}),
+        };
+        let mut pending = PendingRpc::start(&srv, request).unwrap();
+        let sent = theirs.recv().await.unwrap();
+        assert_eq!(sent.body["msg_id"], pending.msg_id());
+
+        let timeout = Duration::from_millis(20);
+        assert!(matches!(
+            pending.wait(timeout).await,
+            Err(RpcError::Timeout)
+        ));
+        pending.resend().unwrap();
+        let resent = theirs.recv().await.unwrap();
+        assert_eq!(resent.body, sent.body);
+
+        // a late reply to the first attempt answers the resent one
+        assert!(deliver_reply(&srv, reply(&sent, json!({"type": "read_ok"}))).is_none());
+        let answer = pending.wait(timeout).await.unwrap();
+        assert_eq!(answer.body["type"], "read_ok");
+        // nobody waits on it anymore, it's handed back to the caller
+        assert!(deliver_reply(&srv, reply(&sent, json!({"type": "read_ok"}))).is_some());
+    }
+
+    #[tokio::test]
+    async fn error_replies_and_dropped_requests_are_told_apart() {
+        let (srv, mut theirs) = server();
+        let request = || Message {
+            src: "n1".to_string(),
+            dest: "n2".to_string(),
+            body: json!({"type": "read"}),
+        };
+
+        let mut pending = PendingRpc::start(&srv, request()).unwrap();
+        let sent = theirs.recv().await.unwrap();
+        let error = json!({"type": "error", "code": 20, "text": "not found"});
+        deliver_reply(&srv, reply(&sent, error));
+        match pending.wait(Duration::from_millis(20)).await {
+            Err(RpcError::Remote(e)) => assert_eq!(e.code, ErrorCode::KeyDoesNotExist),
+            other => panic!("expected an error reply, got {other:?}"),
+        }
+
+        let mut pending = PendingRpc::start(&srv, request()).unwrap();
+        srv.lock().unwrap().get_pending_replies().clear();
+        let dropped = pending.wait(Duration::from_millis(20)).await;
+        assert!(matches!(dropped, Err(RpcError::Cancelled)));
+    }
+}
@@ -1,8 +1,12 @@
 use std::any::Any;
 use std::collections::HashSet;
-use std::io::{self, Write};
+use std::io;
 
-use crate::types::Message;
+use crate::error::Error;
+use crate::outbox::Outbox;
+use crate::shutdown::Shutdown;
+use crate::topology::Topology;
+use crate::types::{Message, PendingReplies};
 
 pub trait Server {
     fn get_id(&self) -> String;
@@ -17,19 +21,47 @@ pub trait Server {
 
     fn set_msg_count(&mut self, count: u64);
 
+    /// Who talks to whom, empty until a `topology` message or an overlay sets it.
+    fn get_graph(&self) -> &Topology;
+
+    fn set_graph(&mut self, graph: Topology);
+
+    /// This server's neighbors in the graph, every other server while the graph doesn't list it.
+    fn get_neighbors(&self) -> Vec<String> {
+        let id = self.get_id();
+        if self.get_graph().contains(&id) {
+            return self.get_graph().neighbors(&id).cloned().collect();
+        }
+        let mut all = self.get_topology();
+        all.retain(|node| *node != id);
+        all
+    }
+
+    /// Requests sent by this server that are still waiting for a reply.
+    fn get_pending_replies(&mut self) -> &mut PendingReplies;
+
+    /// Handle used to wind the server down once its input is closed.
+    fn get_shutdown(&self) -> Shutdown;
+
+    fn get_outbox(&self) -> &Outbox;
+
     fn as_any(&self) -> &dyn Any;
 
     fn as_any_mut(&mut self) -> &mut dyn Any;
 
+    /// Allocates a fresh `msg_id` for an outgoing request.
+    fn next_msg_id(&mut self) -> u64 {
+        let msg_id = self.get_msg_count() + 1;
+        self.set_msg_count(msg_id);
+        msg_id
+    }
+
+    /// Queues `msg` on the outbox, the writer task does the actual writing.
+    ///
     /// # Errors
-    /// - forwards `serde_json` errors
-    /// - forwards `io` errors
+    /// - returns an error once the outbox is closed
     fn send(&self, msg: &Message) -> io::Result<()> {
-        let json_str = serde_json::to_string(msg)?;
-        let mut writer = std::io::stdout();
-        writer.write_all(json_str.as_bytes())?;
-        writer.write_all(b"\n")?;
-        Ok(())
+        self.get_outbox().send(msg.clone())
     }
 
     fn build_reply(
@@ -52,6 +84,30 @@ pub trait Server {
             body,
         })
     }
+
+    /// Replies to `msg` with a Maelstrom `error` message.
+    ///
+    /// Messages without a `msg_id` don't expect a reply, so nothing is sent for them.
+    /// # Errors
+    /// - forwards `serde_json` errors
+    /// - forwards `io` errors
+    fn reply_error(&self, msg: &Message, error: &Error) -> io::Result<()> {
+        let Some(msg_id) = msg.body["msg_id"].as_u64() else {
+            log::debug!("not replying to `{msg:?}` with `{error}`: missing `.body.msg_id` field");
+            return Ok(());
+        };
+
+        self.send(&Message {
+            src: self.get_id(),
+            dest: msg.src.clone(),
+            body: serde_json::json!({
+                "type": "error",
+                "in_reply_to": msg_id,
+                "code": error.code,
+                "text": error.text,
+            }),
+        })
+    }
 }
 
 impl dyn Server {
@@ -92,6 +148,26 @@ macro_rules! impl_server {
                 self.msg_count = count;
             }
 
+            fn get_graph(&self) -> &Topology {
+                &self.graph
+            }
+
+            fn set_graph(&mut self, graph: Topology) {
+                self.graph = graph;
+            }
+
+            fn get_pending_replies(&mut self) -> &mut PendingReplies {
+                &mut self.pending
+            }
+
+            fn get_shutdown(&self) -> Shutdown {
+                self.shutdown.clone()
+            }
+
+            fn get_outbox(&self) -> &Outbox {
+                &self.outbox
+            }
+
             fn as_any(&self) -> &dyn Any {
                 self
             }
@@ -0,0 +1,107 @@
+//! Maelstrom's built-in services, nodes of the network that aren't servers of the workload.
+
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use serde_json::Value;
+use tokio::time::Duration;
+
+use crate::error::ErrorCode;
+use crate::rpc::{RpcError, rpc};
+use crate::server::Server;
+use crate::types::Message;
+
+/// Sequentially consistent key-value store.
+pub const SEQ_KV: &str = "seq-kv";
+/// Linearizable key-value store.
+pub const LIN_KV: &str = "lin-kv";
+/// Eventually consistent key-value store, concurrent writes resolved by last-write-wins.
+pub const LWW_KV: &str = "lww-kv";
+/// Linearizable timestamp oracle.
+pub const LIN_TSO: &str = "lin-tso";
+
+/// How often a request that got `temporarily-unavailable` is tried again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Retry {
+    /// Tries in total, including the first one.
+    pub attempts: u32,
+    /// Pause before the first retry, doubling on every following one.
+    pub backoff: Duration,
+}
+
+impl Default for Retry {
+    fn default() -> Self {
+        Self {
+            attempts: 5,
+            backoff: Duration::from_millis(10),
+        }
+    }
+}
+
+/// A service running as `name` on the network, called on behalf of a server.
+#[derive(Clone)]
+pub struct Service {
+    server: Arc<Mutex<dyn Server + Send + Sync + 'static>>,
+    name: String,
+    timeout: Duration,
+    retry: Retry,
+}
+
+impl fmt::Debug for Service {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Service")
+            .field("name", &self.name)
+            .field("timeout", &self.timeout)
+            .field("retry", &self.retry)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Service {
+    pub fn new(server: Arc<Mutex<dyn Server + Send + Sync + 'static>>, name: &str) -> Self {
+        Self {
+            server,
+            name: name.to_string(),
+            timeout: Duration::from_secs(1),
+            retry: Retry::default(),
+        }
+    }
+
+    /// How long to wait for each reply, a second by default.
+    #[must_use]
+    pub const fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    #[must_use]
+    pub const fn with_retry(mut self, retry: Retry) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    #[must_use]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Sends `body` to the service and waits for the reply, trying again while the service is
+    /// temporarily unavailable.
+    ///
+    /// # Errors
+    /// - same as [`rpc`], `temporarily-unavailable` only once the retries are used up
+    pub async fn call(&self, body: Value) -> Result<Message, RpcError> {
+        let mut backoff = self.retry.backoff;
+        for _ in 1..self.retry.attempts {
+            match rpc(&self.server, &self.name, body.clone(), self.timeout).await {
+                Err(RpcError::Remote(e)) if e.code == ErrorCode::TemporarilyUnavailable => {
+                    log::debug!("{} is unavailable, retrying in {backoff:?}", self.name);
+                    tokio::time::sleep(backoff).await;
+                    backoff *= 2;
+                }
+                result => return result,
+            }
+        }
+        rpc(&self.server, &self.name, body, self.timeout).await
+    }
+}
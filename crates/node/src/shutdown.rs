@@ -0,0 +1,169 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use tokio::sync::Notify;
+
+#[derive(Debug, Default)]
+struct Inner {
+    triggered: AtomicBool,
+    on_trigger: Notify,
+    in_flight: AtomicUsize,
+    on_idle: Notify,
+}
+
+/// Coordinates a graceful shutdown of a server.
+///
+/// Background work is spawned through [`Shutdown::spawn`] so [`Shutdown::drain`] can wait for it,
+/// and long running loops check [`Shutdown::is_triggered`] to wind down.
+#[derive(Debug, Clone, Default)]
+pub struct Shutdown(Arc<Inner>);
+
+impl Shutdown {
+    /// Tells every task the server is going away. Idempotent.
+    pub fn trigger(&self) {
+        self.0.triggered.store(true, Ordering::SeqCst);
+        self.0.on_trigger.notify_waiters();
+    }
+
+    #[must_use]
+    pub fn is_triggered(&self) -> bool {
+        self.0.triggered.load(Ordering::SeqCst)
+    }
+
+    /// Resolves once [`Shutdown::trigger`] has been called.
+    pub async fn triggered(&self) {
+        loop {
+            let notified = self.0.on_trigger.notified();
+            if self.is_triggered() {
+                return;
+            }
+            notified.await;
+        }
+    }
+
+    /// Marks a task as in flight until the returned guard is dropped.
+    #[must_use]
+    pub fn track(&self) -> TaskGuard {
+        self.0.in_flight.fetch_add(1, Ordering::SeqCst);
+        TaskGuard(self.clone())
+    }
+
+    /// Spawns `fut` as a tracked task.
+    pub fn spawn<F>(&self, fut: F) -> tokio::task::JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let guard = self.track();
+        tokio::spawn(async move {
+            let output = fut.await;
+            drop(guard);
+            output
+        })
+    }
+
+    /// Waits until no tracked task is left.
+    pub async fn drain(&self) {
+        loop {
+            let notified = self.0.on_idle.notified();
+            if self.0.in_flight.load(Ordering::SeqCst) == 0 {
+                return;
+            }
+            notified.await;
+        }
+    }
+}
+
+/// Keeps its task counted as in flight, see [`Shutdown::track`].
+#[derive(Debug)]
+pub struct TaskGuard(Shutdown);
+
+impl Drop for TaskGuard {
+    fn drop(&mut self) {
+        if self.0.0.in_flight.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.0.0.on_idle.notify_waiters();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use serde_json::json;
+    use tokio::sync::mpsc;
+    use tokio::time::Duration;
+
+    use super::Shutdown;
+    use crate::error::Error;
+    use crate::transport::Channel;
+    use crate::types::{Message, Node};
+
+    #[tokio::test]
+    async fn drain_waits_for_every_spawned_task() {
+        let shutdown = Shutdown::default();
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        for i in 0..3_u64 {
+            let (shutdown, tx) = (shutdown.clone(), tx.clone());
+            shutdown.clone().spawn(async move {
+                shutdown.triggered().await;
+                tokio::time::sleep(Duration::from_millis(10 * i)).await;
+                tx.send(i).unwrap();
+            });
+        }
+        drop(tx);
+
+        shutdown.trigger();
+        shutdown.drain().await;
+        let mut done = Vec::new();
+        while let Ok(i) = rx.try_recv() {
+            done.push(i);
+        }
+        assert_eq!(done, [0, 1, 2]);
+    }
+
+    #[tokio::test]
+    async fn input_closing_still_answers_the_requests_in_flight() {
+        let mut handlers = crate::build_default_handlers();
+        handlers.insert(
+            "slow",
+            Arc::new(|srv_mutex, msg| {
+                Box::pin(async move {
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                    let srv = srv_mutex.lock().unwrap();
+                    let reply = srv
+                        .build_reply("slow_ok", &msg, json!({}))
+                        .ok_or_else(|| Error::malformed("a request without a `msg_id`"))?;
+                    Ok(srv.send(&reply)?)
+                })
+            }),
+        );
+        let (in_tx, in_rx) = mpsc::unbounded_channel();
+        let (out_tx, mut out_rx) = mpsc::unbounded_channel();
+        let request = |body| Message {
+            src: "c1".to_string(),
+            dest: "n1".to_string(),
+            body,
+        };
+        in_tx
+            .send(request(
+                json!({"type": "init", "msg_id": 1, "node_id": "n1"}),
+            ))
+            .unwrap();
+        in_tx
+            .send(request(json!({"type": "slow", "msg_id": 2})))
+            .unwrap();
+        // the input is closed before the slow handler is done
+        drop(in_tx);
+
+        let node = Arc::new(Mutex::new(Node::default()));
+        crate::serve_with(node, handlers, Channel::new(in_rx, out_tx))
+            .await
+            .unwrap();
+        let mut replies = Vec::new();
+        while let Ok(reply) = out_rx.try_recv() {
+            replies.push(reply.body["type"].clone());
+        }
+        assert_eq!(replies, ["init_ok", "slow_ok"]);
+    }
+}
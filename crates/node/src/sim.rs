@@ -0,0 +1,835 @@
+//! In-process cluster simulator.
+//!
+//! Servers run with their [`HandlersMap`] over [`Channel`] transports, every message goes
+//! through a simulated network with seeded latency, drops, duplication and reordering, and
+//! simulated clients drive the workload. The runtime is single threaded with a paused clock, so
+//! a run is reproducible from its seed and sleeps cost no wall-clock time.
+//!
+//! Network faults only apply between servers, clients and services always reach them (with
+//! latency), like in Maelstrom. Partitions come from a [`Nemesis`] timeline or from [`Cluster::apply`].
+//!
+//! Stand-ins for Maelstrom's `seq-kv`, `lin-kv`, `lww-kv` and `lin-tso` [services] run next
+//! to the servers.
+//!
+//! Requests of the scenario's clients are recorded in a [`History`] for the
+//! [checkers](crate::checker).
+
+pub mod nemesis;
+pub mod services;
+
+use std::cmp::Ordering;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::sync::{Arc, Mutex};
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde_json::{Value, json};
+use tokio::sync::mpsc;
+use tokio::time::{Duration, Instant};
+
+use crate::checker::{History, OpType};
+use crate::error::Error;
+use crate::handlers::HandlersMap;
+use crate::messaging::serve_with;
+use crate::rpc::RpcError;
+use crate::server::Server;
+use crate::service::{LIN_KV, LIN_TSO, LWW_KV, SEQ_KV};
+use crate::shutdown::Shutdown;
+use crate::transport::Channel;
+use crate::types::Message;
+
+pub use nemesis::{Fault, Grudge, Nemesis};
+pub use services::{Consistency, KvStore, SimService, Tso};
+
+pub type SimServer = Arc<Mutex<dyn Server + Send + Sync + 'static>>;
+
+pub type SimHandlers = HandlersMap<dyn Server + Send + Sync + 'static>;
+
+/// Builds a fresh server and its handlers for every simulated node.
+pub type NodeFactory = Arc<dyn Fn() -> (SimServer, SimHandlers) + Send + Sync>;
+
+#[derive(Debug, Clone)]
+pub struct SimConfig {
+    pub seed: u64,
+    pub min_latency: Duration,
+    pub max_latency: Duration,
+    /// Probability for a message between servers to be lost.
+    pub drop_rate: f64,
+    /// Probability for a message between servers to be delivered twice.
+    pub duplicate_rate: f64,
+    /// Probability for a message between servers to be held back by up to `reorder_delay`.
+    pub reorder_rate: f64,
+    pub reorder_delay: Duration,
+}
+
+impl Default for SimConfig {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            min_latency: Duration::from_millis(1),
+            max_latency: Duration::from_millis(10),
+            drop_rate: 0.0,
+            duplicate_rate: 0.0,
+            reorder_rate: 0.0,
+            reorder_delay: Duration::from_millis(100),
+        }
+    }
+}
+
+/// Counters of the simulated network.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NetStats {
+    /// Messages sent between servers.
+    pub server_msgs: u64,
+    /// Messages sent between clients and servers.
+    pub client_msgs: u64,
+    /// Messages sent between servers and services.
+    pub service_msgs: u64,
+    pub delivered: u64,
+    pub dropped: u64,
+    pub duplicated: u64,
+    /// Messages between servers lost to a partition.
+    pub partitioned: u64,
+}
+
+/// A message on its way, ordered by delivery time then by submission order.
+#[derive(Debug)]
+struct InFlight {
+    at: Instant,
+    seq: u64,
+    msg: Message,
+}
+
+impl PartialEq for InFlight {
+    fn eq(&self, other: &Self) -> bool {
+        (self.at, self.seq) == (other.at, other.seq)
+    }
+}
+
+impl Eq for InFlight {}
+
+impl PartialOrd for InFlight {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for InFlight {
+    // reversed, `BinaryHeap` is a max-heap
+    fn cmp(&self, other: &Self) -> Ordering {
+        (other.at, other.seq).cmp(&(self.at, self.seq))
+    }
+}
+
+type Inboxes = Arc<Mutex<HashMap<String, mpsc::UnboundedSender<Message>>>>;
+
+fn is_client(id: &str) -> bool {
+    id.starts_with('c')
+}
+
+struct Network {
+    config: SimConfig,
+    servers: HashSet<String>,
+    // one rng per link, so the fate of a message doesn't depend on how sends on other links
+    // interleave with it
+    links: HashMap<(String, String), StdRng>,
+    queue: BinaryHeap<InFlight>,
+    seq: u64,
+    inboxes: Inboxes,
+    grudge: Arc<Mutex<Grudge>>,
+    stats: Arc<Mutex<NetStats>>,
+}
+
+impl Network {
+    fn link_rng(&mut self, src: &str, dest: &str) -> &mut StdRng {
+        let seed = self.config.seed;
+        self.links
+            .entry((src.to_string(), dest.to_string()))
+            .or_insert_with(|| {
+                let mut hasher = DefaultHasher::new();
+                (seed, src, dest).hash(&mut hasher);
+                StdRng::seed_from_u64(hasher.finish())
+            })
+    }
+
+    fn schedule(&mut self, msg: Message, delay: Duration) {
+        self.seq += 1;
+        self.queue.push(InFlight {
+            at: Instant::now() + delay,
+            seq: self.seq,
+            msg,
+        });
+    }
+
+    fn submit(&mut self, msg: Message) {
+        let faulty = self.servers.contains(&msg.src) && self.servers.contains(&msg.dest);
+        let to_client = is_client(&msg.src) || is_client(&msg.dest);
+        let config = self.config.clone();
+        let rng = self.link_rng(&msg.src, &msg.dest);
+
+        let latency = if config.max_latency > config.min_latency {
+            rng.random_range(config.min_latency..config.max_latency)
+        } else {
+            config.min_latency
+        };
+        let (dropped, duplicated, held_back) = if faulty {
+            (
+                rng.random_bool(config.drop_rate),
+                rng.random_bool(config.duplicate_rate),
+                rng.random_bool(config.reorder_rate),
+            )
+        } else {
+            (false, false, false)
+        };
+        let extra = if held_back && !config.reorder_delay.is_zero() {
+            rng.random_range(Duration::ZERO..config.reorder_delay)
+        } else {
+            Duration::ZERO
+        };
+        let copy_latency = if duplicated {
+            rng.random_range(config.min_latency..=config.max_latency)
+        } else {
+            Duration::ZERO
+        };
+
+        {
+            let mut stats = self.stats.lock().unwrap();
+            if faulty {
+                stats.server_msgs += 1;
+            } else if to_client {
+                stats.client_msgs += 1;
+            } else {
+                stats.service_msgs += 1;
+            }
+            stats.dropped += u64::from(dropped);
+            stats.duplicated += u64::from(duplicated && !dropped);
+        }
+        if dropped {
+            return;
+        }
+        if duplicated {
+            self.schedule(msg.clone(), copy_latency);
+        }
+        self.schedule(msg, latency + extra);
+    }
+
+    fn next_delivery(&self) -> Option<Instant> {
+        self.queue.peek().map(|in_flight| in_flight.at)
+    }
+
+    fn deliver_due(&mut self) {
+        let now = Instant::now();
+        while self
+            .queue
+            .peek()
+            .is_some_and(|in_flight| in_flight.at <= now)
+        {
+            let Some(InFlight { msg, .. }) = self.queue.pop() else {
+                break;
+            };
+            // partitions are checked on arrival, so messages already on the wire when one
+            // starts are lost too
+            let cut = self
+                .grudge
+                .lock()
+                .unwrap()
+                .get(&msg.dest)
+                .is_some_and(|ignored| ignored.contains(&msg.src));
+            if cut {
+                self.stats.lock().unwrap().partitioned += 1;
+                continue;
+            }
+            let inbox = self.inboxes.lock().unwrap().get(&msg.dest).cloned();
+            match inbox {
+                Some(inbox) if inbox.send(msg).is_ok() => {
+                    self.stats.lock().unwrap().delivered += 1;
+                }
+                _ => log::debug!("sim: nobody to deliver to, dropping message"),
+            }
+        }
+    }
+
+    async fn run(mut self, mut wire: mpsc::UnboundedReceiver<Message>, stop: Shutdown) {
+        loop {
+            let next = self.next_delivery();
+            tokio::select! {
+                biased;
+                () = stop.triggered() => break,
+                msg = wire.recv() => match msg {
+                    Some(msg) => self.submit(msg),
+                    None => break,
+                },
+                () = tokio::time::sleep_until(next.unwrap_or_else(Instant::now)), if next.is_some() => {
+                    self.deliver_due();
+                }
+            }
+        }
+        // closes every server's input so they shut down
+        self.inboxes.lock().unwrap().clear();
+    }
+}
+
+/// Where a client records its operations, with the time they count from.
+#[derive(Debug, Clone)]
+struct Recorder {
+    history: Arc<Mutex<History>>,
+    start: Instant,
+}
+
+impl Recorder {
+    fn now(&self) -> u64 {
+        u64::try_from(self.start.elapsed().as_nanos()).unwrap_or(u64::MAX)
+    }
+}
+
+async fn run_service(
+    id: String,
+    mut service: Box<dyn SimService>,
+    mut rng: StdRng,
+    mut inbox: mpsc::UnboundedReceiver<Message>,
+    wire: mpsc::UnboundedSender<Message>,
+) {
+    while let Some(msg) = inbox.recv().await {
+        let mut body = service.handle(&msg, &mut rng);
+        body["in_reply_to"] = msg.body["msg_id"].clone();
+        let reply = Message {
+            src: id.clone(),
+            dest: msg.src,
+            body,
+        };
+        if wire.send(reply).is_err() {
+            break;
+        }
+    }
+}
+
+/// A simulated client, sending requests to servers and waiting for their replies.
+#[derive(Debug)]
+pub struct Client {
+    id: String,
+    process: u64,
+    msg_count: u64,
+    inbox: mpsc::UnboundedReceiver<Message>,
+    wire: mpsc::UnboundedSender<Message>,
+    recorder: Option<Recorder>,
+}
+
+impl Client {
+    #[must_use]
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Sends `body` to `dest` and waits up to `timeout` for the reply, recording the operation
+    /// in the cluster's history as `ok` on a reply, `fail` on a definite error and `info`
+    /// otherwise.
+    ///
+    /// # Errors
+    /// - [`RpcError::Timeout`] if no reply arrived in time
+    /// - [`RpcError::Remote`] if the server replied with an error
+    /// - [`RpcError::Io`] if the simulation is over
+    ///
+    /// # Panics
+    /// Panics if the history mutex is poisoned.
+    pub async fn request(
+        &mut self,
+        dest: &str,
+        body: serde_json::Value,
+        timeout: Duration,
+    ) -> Result<Message, RpcError> {
+        let Some(recorder) = self.recorder.clone() else {
+            return self.send_and_wait(dest, body, timeout).await;
+        };
+        let f = body["type"].as_str().unwrap_or_default().to_string();
+        recorder.history.lock().unwrap().invoke(
+            self.process,
+            &f,
+            body.clone(),
+            Some(dest),
+            recorder.now(),
+        );
+
+        let result = self.send_and_wait(dest, body, timeout).await;
+        let (kind, value) = match &result {
+            Ok(reply) => (OpType::Ok, reply.body.clone()),
+            Err(RpcError::Remote(e)) if e.code.is_definite() => {
+                (OpType::Fail, json!({"code": e.code, "text": e.text}))
+            }
+            Err(_) => (OpType::Info, serde_json::Value::Null),
+        };
+        recorder
+            .history
+            .lock()
+            .unwrap()
+            .complete(self.process, kind, value, recorder.now());
+        result
+    }
+
+    async fn send_and_wait(
+        &mut self,
+        dest: &str,
+        mut body: serde_json::Value,
+        timeout: Duration,
+    ) -> Result<Message, RpcError> {
+        self.msg_count += 1;
+        let msg_id = self.msg_count;
+        body["msg_id"] = msg_id.into();
+        self.wire
+            .send(Message {
+                src: self.id.clone(),
+                dest: dest.to_string(),
+                body,
+            })
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "simulation is over"))?;
+
+        let deadline = Instant::now() + timeout;
+        loop {
+            let reply = match tokio::time::timeout_at(deadline, self.inbox.recv()).await {
+                Ok(Some(reply)) => reply,
+                Ok(None) => return Err(RpcError::Cancelled),
+                Err(_) => return Err(RpcError::Timeout),
+            };
+            // late replies to requests that timed out
+            if reply.body["in_reply_to"].as_u64() != Some(msg_id) {
+                continue;
+            }
+            return Error::from_body(&reply.body).map_or(Ok(reply), |e| Err(RpcError::Remote(e)));
+        }
+    }
+}
+
+/// Handle on a running simulation, given to the scenario.
+#[derive(Clone)]
+pub struct Cluster {
+    node_ids: Vec<String>,
+    servers: HashMap<String, SimServer>,
+    inboxes: Inboxes,
+    wire: mpsc::UnboundedSender<Message>,
+    grudge: Arc<Mutex<Grudge>>,
+    nemesis_rng: Arc<Mutex<StdRng>>,
+    stats: Arc<Mutex<NetStats>>,
+    clients: Arc<Mutex<u64>>,
+    recorder: Recorder,
+}
+
+impl std::fmt::Debug for Cluster {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Cluster")
+            .field("node_ids", &self.node_ids)
+            .field("stats", &self.stats())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Cluster {
+    #[must_use]
+    pub fn node_ids(&self) -> &[String] {
+        &self.node_ids
+    }
+
+    /// The server running as `id`, to inspect its state.
+    #[must_use]
+    pub fn server(&self, id: &str) -> Option<SimServer> {
+        self.servers.get(id).cloned()
+    }
+
+    /// Connects a new client to the network.
+    ///
+    /// # Panics
+    /// Panics if a mutex of the simulation is poisoned.
+    #[must_use]
+    pub fn client(&self) -> Client {
+        let mut client = self.unrecorded_client();
+        client.recorder = Some(self.recorder.clone());
+        client
+    }
+
+    fn unrecorded_client(&self) -> Client {
+        let process = {
+            let mut clients = self.clients.lock().unwrap();
+            *clients += 1;
+            *clients
+        };
+        let id = format!("c{process}");
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.inboxes.lock().unwrap().insert(id.clone(), tx);
+        Client {
+            id,
+            process,
+            msg_count: 0,
+            inbox: rx,
+            wire: self.wire.clone(),
+            recorder: None,
+        }
+    }
+
+    /// # Panics
+    /// Panics if the stats mutex is poisoned.
+    #[must_use]
+    pub fn stats(&self) -> NetStats {
+        *self.stats.lock().unwrap()
+    }
+
+    /// Applies `fault` to the network right away, replacing the current partition unless it's a
+    /// [`Fault::OneWay`].
+    ///
+    /// # Panics
+    /// Panics if a mutex of the simulation is poisoned.
+    pub fn apply(&self, fault: &Fault) {
+        log::debug!("sim: nemesis {fault:?}");
+        let mut rng = self.nemesis_rng.lock().unwrap();
+        nemesis::apply(
+            &mut self.grudge.lock().unwrap(),
+            fault,
+            &self.node_ids,
+            &mut rng,
+        );
+    }
+
+    /// Operations of the clients so far.
+    ///
+    /// # Panics
+    /// Panics if the history mutex is poisoned.
+    #[must_use]
+    pub fn history(&self) -> History {
+        self.recorder.history.lock().unwrap().clone()
+    }
+
+    /// Has `clients` clients each send `requests` requests to servers picked at random, pausing
+    /// `pause` after each. `request` makes the body of request `i` of client `c` with the
+    /// client's rng, seeded with `seed + c`. Errors and timeouts are part of the history, so
+    /// they're left to the checkers.
+    ///
+    /// # Panics
+    /// Panics if a client panics.
+    pub async fn random_requests(
+        &self,
+        seed: u64,
+        clients: u64,
+        requests: u64,
+        pause: Duration,
+        request: impl Fn(&mut StdRng, u64, u64) -> Value + Clone + Send + 'static,
+    ) {
+        let clients: Vec<_> = (0..clients)
+            .map(|c| {
+                let (cluster, request) = (self.clone(), request.clone());
+                tokio::spawn(async move {
+                    let mut rng = StdRng::seed_from_u64(seed + c);
+                    let mut client = cluster.client();
+                    let nodes = cluster.node_ids().len();
+                    for i in 0..requests {
+                        let dest = &cluster.node_ids()[rng.random_range(0..nodes)];
+                        let body = request(&mut rng, c, i);
+                        _ = client.request(dest, body, Duration::from_secs(2)).await;
+                        tokio::time::sleep(pause).await;
+                    }
+                })
+            })
+            .collect();
+        for client in clients {
+            client.await.unwrap();
+        }
+    }
+
+    /// Sources each server currently drops messages from.
+    ///
+    /// # Panics
+    /// Panics if the grudge mutex is poisoned.
+    #[must_use]
+    pub fn grudge(&self) -> Grudge {
+        self.grudge.lock().unwrap().clone()
+    }
+}
+
+/// A simulation to run, see the [module docs](self).
+pub struct Sim {
+    config: SimConfig,
+    nodes: Vec<(String, NodeFactory)>,
+    services: Vec<(String, Box<dyn SimService>)>,
+    nemesis: Nemesis,
+}
+
+impl std::fmt::Debug for Sim {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Sim")
+            .field("config", &self.config)
+            .field(
+                "nodes",
+                &self.nodes.iter().map(|(id, _)| id).collect::<Vec<_>>(),
+            )
+            .field(
+                "services",
+                &self.services.iter().map(|(id, _)| id).collect::<Vec<_>>(),
+            )
+            .field("nemesis", &self.nemesis)
+            .finish()
+    }
+}
+
+impl Sim {
+    /// A simulation with Maelstrom's services and no servers yet.
+    #[must_use]
+    pub fn new(config: SimConfig) -> Self {
+        Self {
+            config,
+            nodes: Vec::new(),
+            services: Vec::new(),
+            nemesis: Nemesis::new(),
+        }
+        .service(SEQ_KV, KvStore::new(Consistency::Sequential))
+        .service(LIN_KV, KvStore::new(Consistency::Linearizable))
+        .service(LWW_KV, KvStore::new(Consistency::LastWriteWins))
+        .service(LIN_TSO, Tso::default())
+    }
+
+    /// Adds `count` servers named `n0`, `n1`, ... built by `factory`.
+    #[must_use]
+    pub fn nodes(
+        mut self,
+        count: usize,
+        factory: impl Fn() -> (SimServer, SimHandlers) + Send + Sync + 'static,
+    ) -> Self {
+        let factory: NodeFactory = Arc::new(factory);
+        let first = self.nodes.len();
+        for i in first..first + count {
+            self.nodes.push((format!("n{i}"), factory.clone()));
+        }
+        self
+    }
+
+    /// Runs `service` as `name`, in place of the service running under that name if any.
+    #[must_use]
+    pub fn service(mut self, name: &str, service: impl SimService) -> Self {
+        self.services.retain(|(id, _)| id != name);
+        self.services.push((name.to_string(), Box::new(service)));
+        self
+    }
+
+    /// Runs `nemesis` alongside the scenario, its offsets counting from when the scenario starts.
+    #[must_use]
+    pub fn nemesis(mut self, nemesis: Nemesis) -> Self {
+        self.nemesis = nemesis;
+        self
+    }
+
+    /// Boots every server, sends them `init` and runs `scenario` against the cluster.
+    ///
+    /// Once the scenario returns, servers have their input closed and are left to shut down.
+    ///
+    /// # Panics
+    /// - panics if the runtime can't be built
+    /// - panics if a server doesn't answer its `init`
+    pub fn run<F, Fut, T>(self, scenario: F) -> T
+    where
+        F: FnOnce(Cluster) -> Fut,
+        Fut: Future<Output = T>,
+    {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .start_paused(true)
+            .build()
+            .expect("failed to build the simulation runtime");
+
+        runtime.block_on(async move {
+            let (wire_tx, wire_rx) = mpsc::unbounded_channel();
+            let inboxes = Inboxes::default();
+            let stats = Arc::new(Mutex::new(NetStats::default()));
+            let grudge = Arc::new(Mutex::new(Grudge::new()));
+            let stop = Shutdown::default();
+
+            let node_ids: Vec<String> = self.nodes.iter().map(|(id, _)| id.clone()).collect();
+            let mut servers = HashMap::new();
+            let mut tasks = Vec::new();
+            for (id, factory) in &self.nodes {
+                let (server, handlers) = factory();
+                let (tx, rx) = mpsc::unbounded_channel();
+                inboxes.lock().unwrap().insert(id.clone(), tx);
+                servers.insert(id.clone(), server.clone());
+                let transport = Channel::new(rx, wire_tx.clone());
+                tasks.push(tokio::spawn(serve_with(server, handlers, transport)));
+            }
+
+            // a stream of its own, so faults don't shift with the traffic
+            let nemesis_rng = StdRng::seed_from_u64(self.config.seed.rotate_left(32));
+            for (id, service) in self.services {
+                let (tx, rx) = mpsc::unbounded_channel();
+                inboxes.lock().unwrap().insert(id.clone(), tx);
+                let mut hasher = DefaultHasher::new();
+                (self.config.seed, &id).hash(&mut hasher);
+                let rng = StdRng::seed_from_u64(hasher.finish());
+                tokio::spawn(run_service(id, service, rng, rx, wire_tx.clone()));
+            }
+
+            let network = Network {
+                config: self.config,
+                servers: node_ids.iter().cloned().collect(),
+                links: HashMap::new(),
+                queue: BinaryHeap::new(),
+                seq: 0,
+                inboxes: inboxes.clone(),
+                grudge: grudge.clone(),
+                stats: stats.clone(),
+            };
+            let router = tokio::spawn(network.run(wire_rx, stop.clone()));
+
+            let mut cluster = Cluster {
+                node_ids: node_ids.clone(),
+                servers,
+                inboxes,
+                wire: wire_tx,
+                grudge,
+                nemesis_rng: Arc::new(Mutex::new(nemesis_rng)),
+                stats,
+                clients: Arc::default(),
+                recorder: Recorder {
+                    history: Arc::default(),
+                    start: Instant::now(),
+                },
+            };
+
+            let mut init = cluster.unrecorded_client();
+            for id in &node_ids {
+                let body = json!({"type": "init", "node_id": id, "node_ids": node_ids});
+                if let Err(e) = init.request(id, body, Duration::from_secs(5)).await {
+                    panic!("{id} didn't answer init: {e}");
+                }
+            }
+
+            let mut timeline = self.nemesis.timeline;
+            timeline.sort_by_key(|(at, _)| *at);
+            let start = Instant::now();
+            cluster.recorder.start = start;
+            let nemesis = tokio::spawn({
+                let cluster = cluster.clone();
+                async move {
+                    for (at, fault) in timeline {
+                        tokio::time::sleep_until(start + at).await;
+                        cluster.apply(&fault);
+                    }
+                }
+            });
+
+            let output = scenario(cluster).await;
+
+            nemesis.abort();
+            stop.trigger();
+            _ = router.await;
+            for task in tasks {
+                if let Ok(Err(e)) = task.await {
+                    log::error!("sim: server failed: {e}");
+                }
+            }
+            output
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use serde_json::json;
+    use tokio::time::Duration;
+
+    use super::{Fault, Nemesis, NetStats, Sim, SimConfig};
+    use crate::{Message, Node, build_default_handlers, send_synchronous};
+
+    /// Every `gossip` is acked and forwarded once to every other node.
+    fn gossip_node() -> (super::SimServer, super::SimHandlers) {
+        let mut handlers = build_default_handlers();
+        handlers.insert(
+            "gossip",
+            Arc::new(|srv_mutex, msg| {
+                Box::pin(async move {
+                    let (reply, peers) = {
+                        let srv = srv_mutex.lock().unwrap();
+                        let reply = srv.build_reply("gossip_ok", &msg, json!({}));
+                        (reply, srv.get_topology())
+                    };
+                    if let Some(reply) = reply {
+                        srv_mutex.lock().unwrap().send(&reply)?;
+                    }
+                    if msg.src.starts_with('c') {
+                        let src = srv_mutex.lock().unwrap().get_id();
+                        for dest in peers.into_iter().filter(|peer| *peer != src) {
+                            let body = json!({"type": "gossip"});
+                            let forward = Message {
+                                src: src.clone(),
+                                dest,
+                                body,
+                            };
+                            send_synchronous(&srv_mutex, forward, Duration::from_millis(100))?;
+                        }
+                    }
+                    Ok(())
+                })
+            }),
+        );
+        (Arc::new(Mutex::new(Node::default())), handlers)
+    }
+
+    fn run(seed: u64) -> NetStats {
+        let config = SimConfig {
+            seed,
+            drop_rate: 0.3,
+            duplicate_rate: 0.2,
+            reorder_rate: 0.2,
+            ..SimConfig::default()
+        };
+        Sim::new(config)
+            .nodes(4, gossip_node)
+            .run(|cluster| async move {
+                let mut client = cluster.client();
+                for i in 0..20 {
+                    let dest = &cluster.node_ids()[i % 4];
+                    let body = json!({"type": "gossip"});
+                    client
+                        .request(dest, body, Duration::from_secs(1))
+                        .await
+                        .unwrap();
+                }
+                tokio::time::sleep(Duration::from_secs(2)).await;
+                cluster.stats()
+            })
+    }
+
+    #[test]
+    fn runs_replay_from_their_seed() {
+        let stats = run(42);
+        assert!(stats.dropped > 0 && stats.duplicated > 0);
+        assert_eq!(run(42), stats);
+        assert_ne!(run(43), stats);
+    }
+
+    #[test]
+    fn partitions_hold_until_healed() {
+        let nemesis = Nemesis::new()
+            .at(Duration::ZERO, Fault::Components(vec![vec!["n0".into()]]))
+            .at(Duration::from_secs(1), Fault::Heal);
+        Sim::new(SimConfig::default())
+            .nodes(3, gossip_node)
+            .nemesis(nemesis)
+            .run(|cluster| async move {
+                let mut client = cluster.client();
+                let body = json!({"type": "gossip"});
+                client
+                    .request("n0", body, Duration::from_secs(1))
+                    .await
+                    .unwrap();
+                tokio::time::sleep(Duration::from_millis(500)).await;
+                let during = cluster.stats();
+                assert!(during.partitioned > 0);
+
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                assert!(cluster.grudge().is_empty());
+                // the retries got through once healed, nothing's dropped anymore
+                let after = cluster.stats();
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                assert_eq!(cluster.stats().partitioned, after.partitioned);
+                assert_eq!(cluster.stats().server_msgs, after.server_msgs);
+            });
+    }
+}
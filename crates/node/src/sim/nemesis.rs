@@ -0,0 +1,224 @@
+//! Network partitions for the simulator, modelled after Jepsen's partition nemeses.
+
+use std::collections::{HashMap, HashSet};
+
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use tokio::time::Duration;
+
+/// Messages each server drops, keyed by destination, valued by the sources it ignores.
+pub type Grudge = HashMap<String, HashSet<String>>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Fault {
+    /// Cuts a random minority of the servers off from the majority.
+    MajorityMinority,
+    /// Arranges servers in a random ring where each one only sees the majority around it, so
+    /// every server sees a majority but no two of them see the same one.
+    MajoritiesRing,
+    /// Splits servers in two random halves, except for one that sees both.
+    Bridge,
+    /// Splits servers in two random halves.
+    RandomHalves,
+    /// Splits servers in the given components, servers left out are isolated on their own.
+    Components(Vec<Vec<String>>),
+    /// Drops messages from `from` to `to` but not the other way around. Adds up with the
+    /// faults already in place.
+    OneWay { from: String, to: String },
+    /// Removes every fault.
+    Heal,
+}
+
+/// Faults to apply over the course of a simulation, at offsets from the start of the scenario.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Nemesis {
+    pub timeline: Vec<(Duration, Fault)>,
+}
+
+impl Nemesis {
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            timeline: Vec::new(),
+        }
+    }
+
+    /// Applies `fault` at `at`.
+    #[must_use]
+    pub fn at(mut self, at: Duration, fault: Fault) -> Self {
+        self.timeline.push((at, fault));
+        self
+    }
+
+    /// Alternates `fault` for `period` and healed for `period`, from `start` to `end`.
+    #[must_use]
+    pub fn flapping(
+        mut self,
+        fault: &Fault,
+        start: Duration,
+        end: Duration,
+        period: Duration,
+    ) -> Self {
+        let mut at = start;
+        while at < end {
+            self.timeline.push((at, fault.clone()));
+            self.timeline.push(((at + period).min(end), Fault::Heal));
+            at += period * 2;
+        }
+        self
+    }
+}
+
+/// Every server drops what comes from outside of its component.
+fn components_grudge(nodes: &[String], components: &[Vec<String>]) -> Grudge {
+    let mut grudge = Grudge::new();
+    for node in nodes {
+        let visible: HashSet<&String> = components
+            .iter()
+            .filter(|component| component.contains(node))
+            .flatten()
+            .collect();
+        let dropped = nodes
+            .iter()
+            .filter(|other| *other != node && !visible.contains(other))
+            .cloned()
+            .collect();
+        grudge.insert(node.clone(), dropped);
+    }
+    grudge
+}
+
+/// Updates `grudge` with `fault` applied over `nodes`.
+pub(crate) fn apply(grudge: &mut Grudge, fault: &Fault, nodes: &[String], rng: &mut StdRng) {
+    let mut shuffled = nodes.to_vec();
+    shuffled.shuffle(rng);
+    let half = shuffled.len() / 2;
+
+    *grudge = match fault {
+        Fault::Heal => Grudge::new(),
+        Fault::OneWay { from, to } => {
+            grudge.entry(to.clone()).or_default().insert(from.clone());
+            return;
+        }
+        Fault::MajorityMinority => {
+            let minority = (shuffled.len().saturating_sub(1)) / 2;
+            let (minority, majority) = shuffled.split_at(minority);
+            components_grudge(nodes, &[minority.to_vec(), majority.to_vec()])
+        }
+        Fault::RandomHalves => {
+            let (a, b) = shuffled.split_at(half);
+            components_grudge(nodes, &[a.to_vec(), b.to_vec()])
+        }
+        Fault::Bridge => {
+            let (a, b) = shuffled.split_at(half);
+            let mut a = a.to_vec();
+            if let Some(bridge) = b.first() {
+                a.push(bridge.clone());
+            }
+            components_grudge(nodes, &[a, b.to_vec()])
+        }
+        Fault::MajoritiesRing => {
+            let n = shuffled.len();
+            // a window of `2 * reach + 1` servers around each one is a majority, and windows are
+            // symmetric so two servers see each other or neither does
+            let reach = (n / 2).div_ceil(2);
+            let mut grudge = Grudge::new();
+            for (i, node) in shuffled.iter().enumerate() {
+                let visible: HashSet<&String> = (0..=2 * reach)
+                    .map(|offset| &shuffled[(i + n + offset - reach) % n])
+                    .collect();
+                let dropped = nodes
+                    .iter()
+                    .filter(|other| !visible.contains(other))
+                    .cloned()
+                    .collect();
+                grudge.insert(node.clone(), dropped);
+            }
+            grudge
+        }
+        Fault::Components(components) => components_grudge(nodes, components),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    use super::{Fault, Grudge, apply};
+
+    fn nodes(n: usize) -> Vec<String> {
+        (0..n).map(|i| format!("n{i}")).collect()
+    }
+
+    fn sees(grudge: &Grudge, node: &str, nodes: &[String]) -> HashSet<String> {
+        nodes
+            .iter()
+            .filter(|other| {
+                !grudge
+                    .get(node)
+                    .is_some_and(|ignored| ignored.contains(*other))
+            })
+            .cloned()
+            .collect()
+    }
+
+    #[test]
+    fn partitions_have_their_shape() {
+        let nodes = nodes(5);
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut grudge = Grudge::new();
+
+        apply(&mut grudge, &Fault::MajorityMinority, &nodes, &mut rng);
+        let mut sizes: Vec<usize> = nodes
+            .iter()
+            .map(|n| sees(&grudge, n, &nodes).len())
+            .collect();
+        sizes.sort_unstable();
+        assert_eq!(sizes, [2, 2, 3, 3, 3]);
+
+        apply(&mut grudge, &Fault::Bridge, &nodes, &mut rng);
+        let bridges = nodes
+            .iter()
+            .filter(|n| sees(&grudge, n, &nodes).len() == 5)
+            .count();
+        assert_eq!(bridges, 1);
+
+        apply(&mut grudge, &Fault::MajoritiesRing, &nodes, &mut rng);
+        let majorities: HashSet<Vec<String>> = nodes
+            .iter()
+            .map(|n| {
+                let seen = sees(&grudge, n, &nodes);
+                assert_eq!(seen.len(), 3, "{n} doesn't see a majority");
+                let mut seen: Vec<String> = seen.into_iter().collect();
+                seen.sort();
+                seen
+            })
+            .collect();
+        assert_eq!(majorities.len(), 5);
+
+        apply(&mut grudge, &Fault::Heal, &nodes, &mut rng);
+        assert!(grudge.is_empty());
+    }
+
+    #[test]
+    fn one_way_drops_add_up() {
+        let nodes = nodes(3);
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut grudge = Grudge::new();
+        let one_way = |from: &str, to: &str| Fault::OneWay {
+            from: from.to_string(),
+            to: to.to_string(),
+        };
+
+        apply(&mut grudge, &one_way("n0", "n1"), &nodes, &mut rng);
+        apply(&mut grudge, &one_way("n2", "n1"), &nodes, &mut rng);
+        assert_eq!(
+            sees(&grudge, "n1", &nodes),
+            HashSet::from(["n1".to_string()])
+        );
+        assert_eq!(sees(&grudge, "n0", &nodes).len(), 3);
+    }
+}
@@ -0,0 +1,283 @@
+//! Stand-ins for Maelstrom's services, so solutions relying on them run in the simulator.
+
+use std::collections::HashMap;
+
+use rand::Rng;
+use rand::rngs::StdRng;
+use serde_json::{Value, json};
+use tokio::time::{Duration, Instant};
+
+use crate::error::{Error, ErrorCode};
+use crate::types::Message;
+
+/// A service answering requests on the simulated network.
+pub trait SimService: Send + 'static {
+    /// The body of the reply to `msg`, `in_reply_to` is filled in by the simulator.
+    fn handle(&mut self, msg: &Message, rng: &mut StdRng) -> Value;
+}
+
+fn error(code: ErrorCode, text: &str) -> Value {
+    json!({"type": "error", "code": code, "text": text})
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Consistency {
+    /// Every operation sees every operation that completed before it, like `lin-kv`.
+    Linearizable,
+    /// Reads may be stale, but never older than what the same client saw or wrote before,
+    /// like `seq-kv`.
+    Sequential,
+    /// Reads and `cas` may be stale in any way and the last write wins, like `lww-kv`.
+    LastWriteWins,
+}
+
+/// In-memory key-value store speaking Maelstrom's `read`, `write` and `cas`.
+///
+/// Writes go to one log shared by every key. Stale reads look at an earlier point of that log,
+/// but only at states that were current less than `staleness` ago, so every client catches up
+/// once writes quiet down.
+#[derive(Debug)]
+pub struct KvStore {
+    consistency: Consistency,
+    staleness: Duration,
+    unavailable_rate: f64,
+    /// When every write happened, the state before the first one being position 0.
+    log: Vec<Instant>,
+    /// Per key, the log position of every write with the value it wrote.
+    versions: HashMap<String, Vec<(usize, Value)>>,
+    /// Per client, the earliest position it may read from.
+    floors: HashMap<String, usize>,
+}
+
+impl KvStore {
+    #[must_use]
+    pub fn new(consistency: Consistency) -> Self {
+        Self {
+            consistency,
+            staleness: Duration::from_millis(100),
+            unavailable_rate: 0.0,
+            log: Vec::new(),
+            versions: HashMap::new(),
+            floors: HashMap::new(),
+        }
+    }
+
+    #[must_use]
+    pub const fn with_staleness(mut self, staleness: Duration) -> Self {
+        self.staleness = staleness;
+        self
+    }
+
+    /// Probability for a request to be turned down with `temporarily-unavailable`.
+    #[must_use]
+    pub const fn with_unavailable_rate(mut self, rate: f64) -> Self {
+        self.unavailable_rate = rate;
+        self
+    }
+
+    const fn latest(&self) -> usize {
+        self.log.len()
+    }
+
+    /// The position `client` reads from.
+    fn view(&mut self, client: &str, rng: &mut StdRng) -> usize {
+        let latest = self.latest();
+        let floor = match self.consistency {
+            Consistency::Linearizable => return latest,
+            Consistency::Sequential => self.floors.get(client).copied().unwrap_or_default(),
+            Consistency::LastWriteWins => 0,
+        };
+        // the state at position `p` was current until write `p` (0-based) happened
+        let cutoff = Instant::now().checked_sub(self.staleness);
+        let oldest = self
+            .log
+            .iter()
+            .position(|&at| cutoff.is_none_or(|cutoff| at >= cutoff))
+            .unwrap_or(latest);
+        let position = rng.random_range(floor.max(oldest).min(latest)..=latest);
+        if self.consistency == Consistency::Sequential {
+            self.floors.insert(client.to_string(), position);
+        }
+        position
+    }
+
+    fn value_at(&self, key: &str, position: usize) -> Option<&Value> {
+        self.versions
+            .get(key)?
+            .iter()
+            .rev()
+            .find(|(at, _)| *at <= position)
+            .map(|(_, value)| value)
+    }
+
+    fn write(&mut self, client: &str, key: String, value: Value) {
+        self.log.push(Instant::now());
+        let position = self.latest();
+        self.versions
+            .entry(key)
+            .or_default()
+            .push((position, value));
+        if self.consistency == Consistency::Sequential {
+            self.floors.insert(client.to_string(), position);
+        }
+    }
+
+    fn apply(&mut self, msg: &Message, rng: &mut StdRng) -> Result<Value, Error> {
+        let body = &msg.body;
+        let key = body["key"].to_string();
+        match body["type"].as_str() {
+            Some("read") => {
+                let position = self.view(&msg.src, rng);
+                let value = self
+                    .value_at(&key, position)
+                    .ok_or_else(|| Error::new(ErrorCode::KeyDoesNotExist, "key does not exist"))?;
+                Ok(json!({"type": "read_ok", "value": value}))
+            }
+            Some("write") => {
+                self.write(&msg.src, key, body["value"].clone());
+                Ok(json!({"type": "write_ok"}))
+            }
+            Some("cas") => {
+                // `seq-kv` orders cas with the writes, only `lww-kv` may compare to stale data
+                let position = match self.consistency {
+                    Consistency::LastWriteWins => self.view(&msg.src, rng),
+                    _ => self.latest(),
+                };
+                match self.value_at(&key, position) {
+                    None if body["create_if_not_exists"].as_bool() == Some(true) => {}
+                    None => {
+                        return Err(Error::new(ErrorCode::KeyDoesNotExist, "key does not exist"));
+                    }
+                    Some(current) if *current != body["from"] => {
+                        return Err(Error::new(
+                            ErrorCode::PreconditionFailed,
+                            format!("expected {}, found {current}", body["from"]),
+                        ));
+                    }
+                    Some(_) => {}
+                }
+                self.write(&msg.src, key, body["to"].clone());
+                Ok(json!({"type": "cas_ok"}))
+            }
+            _ => Err(Error::new(ErrorCode::NotSupported, "unknown request type")),
+        }
+    }
+}
+
+impl SimService for KvStore {
+    fn handle(&mut self, msg: &Message, rng: &mut StdRng) -> Value {
+        if rng.random_bool(self.unavailable_rate) {
+            return error(ErrorCode::TemporarilyUnavailable, "try again later");
+        }
+        self.apply(msg, rng)
+            .unwrap_or_else(|e| error(e.code, &e.text))
+    }
+}
+
+/// Timestamp oracle speaking `lin-tso`'s `ts`.
+#[derive(Debug, Default)]
+pub struct Tso {
+    next: u64,
+}
+
+impl SimService for Tso {
+    fn handle(&mut self, msg: &Message, _rng: &mut StdRng) -> Value {
+        if msg.body["type"] != "ts" {
+            return error(ErrorCode::NotSupported, "unknown request type");
+        }
+        let ts = self.next;
+        self.next += 1;
+        json!({"type": "ts_ok", "ts": ts})
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+    use serde_json::{Value, json};
+    use tokio::time::Duration;
+
+    use super::{Consistency, KvStore, SimService};
+    use crate::types::Message;
+
+    fn request(store: &mut KvStore, rng: &mut StdRng, src: &str, body: Value) -> Value {
+        let msg = Message {
+            src: src.to_string(),
+            dest: "kv".to_string(),
+            body,
+        };
+        store.handle(&msg, rng)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn sequential_reads_never_go_back_in_time() {
+        let mut store = KvStore::new(Consistency::Sequential);
+        let mut rng = StdRng::seed_from_u64(0);
+        let body = json!({"type": "write", "key": "k", "value": 0});
+        request(&mut store, &mut rng, "n0", body);
+        // too old to be read anymore
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        for value in 1..20 {
+            let body = json!({"type": "write", "key": "k", "value": value});
+            request(&mut store, &mut rng, "n0", body);
+        }
+
+        let mut last = -1;
+        let mut stale = false;
+        for _ in 0..20 {
+            let reply = request(
+                &mut store,
+                &mut rng,
+                "n1",
+                json!({"type": "read", "key": "k"}),
+            );
+            let value = reply["value"].as_i64().unwrap();
+            assert!(value >= last, "read {value} after {last}");
+            stale |= value < 19;
+            last = value;
+        }
+        assert!(stale);
+
+        // the writer always sees its own writes
+        let reply = request(
+            &mut store,
+            &mut rng,
+            "n0",
+            json!({"type": "read", "key": "k"}),
+        );
+        assert_eq!(reply["value"], 19);
+
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        let reply = request(
+            &mut store,
+            &mut rng,
+            "n2",
+            json!({"type": "read", "key": "k"}),
+        );
+        assert_eq!(reply["value"], 19);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn cas_checks_and_creates() {
+        let mut store = KvStore::new(Consistency::Linearizable);
+        let mut rng = StdRng::seed_from_u64(0);
+        let cas = |from: i64, to: i64, create: bool| json!({"type": "cas", "key": 1, "from": from, "to": to, "create_if_not_exists": create});
+
+        let reply = request(&mut store, &mut rng, "n0", cas(0, 1, false));
+        assert_eq!(reply["code"], 20);
+        let reply = request(&mut store, &mut rng, "n0", cas(0, 1, true));
+        assert_eq!(reply["type"], "cas_ok");
+        let reply = request(&mut store, &mut rng, "n0", cas(0, 2, false));
+        assert_eq!(reply["code"], 22);
+        let reply = request(&mut store, &mut rng, "n0", cas(1, 2, false));
+        assert_eq!(reply["type"], "cas_ok");
+        let reply = request(
+            &mut store,
+            &mut rng,
+            "n1",
+            json!({"type": "read", "key": 1}),
+        );
+        assert_eq!(reply["value"], 2);
+    }
+}
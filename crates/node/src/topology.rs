@@ -0,0 +1,414 @@
+//! Neighbor graphs between servers: the one a `topology` message gives, or overlays computed from
+//! the node ids to trade latency against message count.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::fmt;
+use std::iter;
+use std::str::FromStr;
+
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+
+/// Who each node talks to. Edges built here go both ways, the ones a `topology` message gives are
+/// taken as they are.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Topology {
+    neighbors: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl From<HashMap<String, Vec<String>>> for Topology {
+    fn from(adjacency: HashMap<String, Vec<String>>) -> Self {
+        let neighbors = adjacency
+            .into_iter()
+            .map(|(node, neighbors)| (node, neighbors.into_iter().collect()))
+            .collect();
+        Self { neighbors }
+    }
+}
+
+impl Topology {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds an edge between `a` and `b`, both ways.
+    pub fn connect(&mut self, a: &str, b: &str) {
+        if a == b {
+            return;
+        }
+        self.neighbors
+            .entry(a.to_string())
+            .or_default()
+            .insert(b.to_string());
+        self.neighbors
+            .entry(b.to_string())
+            .or_default()
+            .insert(a.to_string());
+    }
+
+    /// The neighbors of `node`, none if it isn't part of the graph.
+    pub fn neighbors(&self, node: &str) -> impl Iterator<Item = &String> {
+        self.neighbors.get(node).into_iter().flatten()
+    }
+
+    #[must_use]
+    pub fn contains(&self, node: &str) -> bool {
+        self.neighbors.contains_key(node)
+    }
+
+    pub fn nodes(&self) -> impl Iterator<Item = &String> {
+        self.neighbors.keys()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.neighbors.is_empty()
+    }
+
+    fn with_nodes(ids: &[String]) -> Self {
+        let neighbors = ids.iter().map(|id| (id.clone(), BTreeSet::new())).collect();
+        Self { neighbors }
+    }
+
+    /// Everyone is everyone's neighbor: one hop, the most messages.
+    #[must_use]
+    pub fn complete(ids: &[String]) -> Self {
+        let mut topology = Self::with_nodes(ids);
+        for (i, a) in ids.iter().enumerate() {
+            for b in &ids[i + 1..] {
+                topology.connect(a, b);
+            }
+        }
+        topology
+    }
+
+    /// Each node next to the one before and after it, in `ids` order.
+    #[must_use]
+    pub fn ring(ids: &[String]) -> Self {
+        let mut topology = Self::with_nodes(ids);
+        for (i, a) in ids.iter().enumerate() {
+            topology.connect(a, &ids[(i + 1) % ids.len()]);
+        }
+        topology
+    }
+
+    /// Rows of `ceil(sqrt(n))` nodes, each next to the ones left, right, above and below it.
+    #[must_use]
+    pub fn grid(ids: &[String]) -> Self {
+        let mut topology = Self::with_nodes(ids);
+        let width = ids.len().isqrt() + usize::from(ids.len().isqrt().pow(2) < ids.len());
+        for (i, a) in ids.iter().enumerate() {
+            if (i + 1) % width != 0
+                && let Some(right) = ids.get(i + 1)
+            {
+                topology.connect(a, right);
+            }
+            if let Some(below) = ids.get(i + width) {
+                topology.connect(a, below);
+            }
+        }
+        topology
+    }
+
+    /// A tree rooted at the first node, every node having up to `fanout` children.
+    ///
+    /// # Panics
+    /// Panics if `fanout` is 0.
+    #[must_use]
+    pub fn tree(ids: &[String], fanout: usize) -> Self {
+        assert!(fanout > 0, "a tree needs a fanout of at least 1");
+        let mut topology = Self::with_nodes(ids);
+        for (i, child) in ids.iter().enumerate().skip(1) {
+            topology.connect(&ids[(i - 1) / fanout], child);
+        }
+        topology
+    }
+
+    /// The breadth-first spanning tree of this graph rooted at `hub`, so every node is as few
+    /// hops from the hub as it was, over as few edges as can still reach everyone.
+    ///
+    /// Nodes `hub` can't reach are left out.
+    #[must_use]
+    pub fn spanning_tree(&self, hub: &str) -> Self {
+        let mut tree = Self::new();
+        if !self.contains(hub) {
+            return tree;
+        }
+        tree.neighbors.insert(hub.to_string(), BTreeSet::new());
+        let mut queue = VecDeque::from([hub]);
+        while let Some(node) = queue.pop_front() {
+            for neighbor in self.neighbors(node) {
+                if !tree.contains(neighbor) {
+                    tree.connect(node, neighbor);
+                    queue.push_back(neighbor);
+                }
+            }
+        }
+        tree
+    }
+
+    /// A random graph where every node has `k` neighbors, the same for every `seed`.
+    ///
+    /// Needs `k < n` and `k * n` even, `k` is lowered until it fits.
+    #[must_use]
+    pub fn random_regular(ids: &[String], k: usize, seed: u64) -> Self {
+        let n = ids.len();
+        let mut k = k.min(n.saturating_sub(1));
+        if k * n % 2 == 1 {
+            k -= 1;
+        }
+        let mut rng = StdRng::seed_from_u64(seed);
+
+        // pair up k stubs per node, starting over if that makes a loop or a double edge
+        for _ in 0..100 {
+            let mut stubs: Vec<usize> = (0..n).flat_map(|i| iter::repeat_n(i, k)).collect();
+            stubs.shuffle(&mut rng);
+            let mut topology = Self::with_nodes(ids);
+            let simple = stubs.chunks(2).all(|pair| {
+                let (a, b) = (&ids[pair[0]], &ids[pair[1]]);
+                let fresh = a != b && !topology.neighbors(a).any(|x| x == b);
+                topology.connect(a, b);
+                fresh
+            });
+            if simple {
+                return topology;
+            }
+        }
+
+        // unlucky, settle for a circulant graph over shuffled nodes
+        let mut shuffled = ids.to_vec();
+        shuffled.shuffle(&mut rng);
+        let mut topology = Self::with_nodes(ids);
+        for (i, a) in shuffled.iter().enumerate() {
+            for step in 1..=k / 2 {
+                topology.connect(a, &shuffled[(i + step) % n]);
+            }
+            if k % 2 == 1 {
+                topology.connect(a, &shuffled[(i + n / 2) % n]);
+            }
+        }
+        topology
+    }
+
+    /// Hops from `from` to every node it can reach.
+    fn distances<'a>(&'a self, from: &'a str) -> HashMap<&'a str, usize> {
+        let mut distances = HashMap::from([(from, 0)]);
+        let mut queue = VecDeque::from([from]);
+        while let Some(node) = queue.pop_front() {
+            let distance = distances[node];
+            for neighbor in self.neighbors(node) {
+                if !distances.contains_key(neighbor.as_str()) {
+                    distances.insert(neighbor, distance + 1);
+                    queue.push_back(neighbor);
+                }
+            }
+        }
+        distances
+    }
+
+    #[must_use]
+    pub fn stats(&self) -> TopologyStats {
+        let degrees: Vec<usize> = self.neighbors.values().map(BTreeSet::len).collect();
+        let mut diameter = Some(0);
+        for node in self.nodes() {
+            let distances = self.distances(node);
+            diameter = diameter
+                .filter(|_| distances.len() == self.neighbors.len())
+                .zip(distances.values().max())
+                .map(|(diameter, &furthest)| diameter.max(furthest));
+        }
+        TopologyStats {
+            nodes: self.neighbors.len(),
+            edges: degrees.iter().sum(),
+            min_degree: degrees.iter().copied().min().unwrap_or_default(),
+            max_degree: degrees.iter().copied().max().unwrap_or_default(),
+            diameter,
+        }
+    }
+}
+
+/// Shape of a [`Topology`]. The diameter bounds the hops a value takes to reach everyone, the
+/// edges how many messages it takes to get there.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TopologyStats {
+    pub nodes: usize,
+    /// Directed edges, twice the links when they all go both ways.
+    pub edges: usize,
+    pub min_degree: usize,
+    pub max_degree: usize,
+    /// Most hops between two nodes, `None` if some can't reach each other.
+    pub diameter: Option<usize>,
+}
+
+impl TopologyStats {
+    #[must_use]
+    pub fn mean_degree(&self) -> f64 {
+        if self.nodes == 0 {
+            return 0.0;
+        }
+        #[allow(clippy::cast_precision_loss)]
+        let mean = self.edges as f64 / self.nodes as f64;
+        mean
+    }
+}
+
+impl fmt::Display for TopologyStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} nodes, {} edges, degree {}..={} (mean {:.1}), diameter ",
+            self.nodes,
+            self.edges,
+            self.min_degree,
+            self.max_degree,
+            self.mean_degree(),
+        )?;
+        match self.diameter {
+            Some(diameter) => write!(f, "{diameter}"),
+            None => write!(f, "infinite"),
+        }
+    }
+}
+
+/// Which graph to gossip over.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Overlay {
+    /// The one the `topology` message gives.
+    #[default]
+    Given,
+    Complete,
+    Ring,
+    Grid,
+    Tree {
+        fanout: usize,
+    },
+    /// The spanning tree of the given graph, rooted at the first node.
+    SpanningTree,
+    RandomRegular {
+        k: usize,
+    },
+}
+
+impl Overlay {
+    /// The overlay over `ids`, sorted so every node builds the same one.
+    #[must_use]
+    pub fn build(self, ids: &[String], given: &Topology) -> Topology {
+        let mut ids = ids.to_vec();
+        ids.sort();
+        match self {
+            Self::Given => given.clone(),
+            Self::Complete => Topology::complete(&ids),
+            Self::Ring => Topology::ring(&ids),
+            Self::Grid => Topology::grid(&ids),
+            Self::Tree { fanout } => Topology::tree(&ids, fanout),
+            Self::SpanningTree => ids
+                .first()
+                .map(|hub| given.spanning_tree(hub))
+                .unwrap_or_default(),
+            Self::RandomRegular { k } => Topology::random_regular(&ids, k, 0),
+        }
+    }
+}
+
+/// Error parsing an [`Overlay`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseOverlayError(String);
+
+impl fmt::Display for ParseOverlayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "unknown overlay `{}`, expected `given`, `complete`, `ring`, `grid`, `tree:<fanout>`, \
+             `spanning-tree` or `random:<k>`",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for ParseOverlayError {}
+
+impl FromStr for Overlay {
+    type Err = ParseOverlayError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || ParseOverlayError(s.to_string());
+        let (name, arg) = s.split_once(':').unwrap_or((s, ""));
+        let number = || {
+            arg.parse::<usize>()
+                .ok()
+                .filter(|&n| n > 0)
+                .ok_or_else(error)
+        };
+        match (name, arg) {
+            ("given", "") => Ok(Self::Given),
+            ("complete", "") => Ok(Self::Complete),
+            ("ring", "") => Ok(Self::Ring),
+            ("grid", "") => Ok(Self::Grid),
+            ("tree", _) => Ok(Self::Tree { fanout: number()? }),
+            ("spanning-tree", "") => Ok(Self::SpanningTree),
+            ("random", _) => Ok(Self::RandomRegular { k: number()? }),
+            _ => Err(error()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Overlay, Topology};
+
+    fn ids(n: usize) -> Vec<String> {
+        (0..n).map(|i| format!("n{i}")).collect()
+    }
+
+    #[test]
+    fn overlays_have_their_shape() {
+        let ids = ids(25);
+
+        let stats = Topology::complete(&ids).stats();
+        assert_eq!((stats.min_degree, stats.diameter), (24, Some(1)));
+
+        let stats = Topology::ring(&ids).stats();
+        assert_eq!((stats.max_degree, stats.diameter), (2, Some(12)));
+
+        let stats = Topology::grid(&ids).stats();
+        assert_eq!((stats.min_degree, stats.max_degree), (2, 4));
+        assert_eq!(stats.diameter, Some(8));
+
+        let tree = Topology::tree(&ids, 4);
+        assert_eq!(tree.stats().edges, 2 * 24);
+        assert_eq!(tree.neighbors("n0").count(), 4);
+        assert_eq!(tree.stats().diameter, Some(5));
+
+        for k in [3, 4, 6] {
+            let stats = Topology::random_regular(&ids[1..], k, 7).stats();
+            assert_eq!((stats.min_degree, stats.max_degree), (k, k), "{k}-regular");
+            assert!(stats.diameter.is_some());
+        }
+    }
+
+    #[test]
+    fn spanning_trees_keep_distances_to_the_hub() {
+        let grid = Topology::grid(&ids(25));
+        let tree = grid.spanning_tree("n12");
+        let stats = tree.stats();
+        assert_eq!(stats.edges, 2 * 24);
+        assert_eq!(tree.distances("n12"), grid.distances("n12"));
+
+        let mut split = Topology::new();
+        split.connect("n0", "n1");
+        split.connect("n2", "n3");
+        assert_eq!(split.stats().diameter, None);
+        assert_eq!(split.spanning_tree("n0").nodes().count(), 2);
+    }
+
+    #[test]
+    fn overlays_parse() {
+        assert_eq!("tree:4".parse(), Ok(Overlay::Tree { fanout: 4 }));
+        assert_eq!("random:3".parse(), Ok(Overlay::RandomRegular { k: 3 }));
+        assert_eq!("spanning-tree".parse(), Ok(Overlay::SpanningTree));
+        assert!("tree".parse::<Overlay>().is_err());
+        assert!("grid:2".parse::<Overlay>().is_err());
+    }
+}
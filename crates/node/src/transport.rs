@@ -0,0 +1,352 @@
+use std::io;
+
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::{TcpStream, ToSocketAddrs, tcp};
+use tokio::sync::mpsc;
+
+use crate::types::Message;
+
+/// Longest line accepted by the line-delimited transports, longer ones are dropped.
+pub const MAX_LINE_LEN: usize = 1 << 20;
+
+/// Environment variable holding the address [`serve`](crate::serve) connects to over TCP instead
+/// of using stdin/stdout.
+pub const TCP_ADDR_VAR: &str = "NODE_TCP_ADDR";
+
+/// Where a server's messages come from and go to.
+pub trait Transport {
+    type Incoming: Incoming;
+    type Outgoing: Outgoing;
+
+    /// Splits the transport into its reading and writing halves.
+    ///
+    /// # Errors
+    /// - forwards `io` errors
+    fn split(self) -> io::Result<(Self::Incoming, Self::Outgoing)>;
+}
+
+/// The reading half of a [`Transport`].
+pub trait Incoming: Send + 'static {
+    /// Returns the next message, or `None` once the other side is gone.
+    ///
+    /// Garbage is logged and skipped rather than returned as an error.
+    fn recv(&mut self) -> impl Future<Output = io::Result<Option<Message>>> + Send;
+}
+
+/// The writing half of a [`Transport`].
+pub trait Outgoing: Send + 'static {
+    /// Sends `batch` in order, returning how many bytes that took on the wire.
+    fn send_batch(&mut self, batch: Vec<Message>) -> impl Future<Output = io::Result<u64>> + Send;
+}
+
+enum ReadLine {
+    Line,
+    TooLong,
+    Eof,
+}
+
+/// Reads a line into `line` without ever buffering more than `limit` bytes of it.
+async fn read_line_bounded<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    line: &mut Vec<u8>,
+    limit: usize,
+) -> io::Result<ReadLine> {
+    let mut too_long = false;
+    loop {
+        let available = reader.fill_buf().await?;
+        if available.is_empty() {
+            // a last line without a trailing newline still counts
+            return Ok(match (too_long, line.is_empty()) {
+                (true, _) => ReadLine::TooLong,
+                (false, true) => ReadLine::Eof,
+                (false, false) => ReadLine::Line,
+            });
+        }
+
+        let newline = available.iter().position(|&b| b == b'\n');
+        let chunk = newline.map_or(available, |i| &available[..=i]);
+        if line.len() + chunk.len() > limit {
+            too_long = true;
+            line.clear();
+        } else if !too_long {
+            line.extend_from_slice(chunk);
+        }
+
+        let consumed = chunk.len();
+        reader.consume(consumed);
+        if newline.is_some() {
+            return Ok(if too_long {
+                ReadLine::TooLong
+            } else {
+                ReadLine::Line
+            });
+        }
+    }
+}
+
+/// Reads one JSON message per line.
+#[derive(Debug)]
+pub struct LineIncoming<R> {
+    reader: R,
+    line: Vec<u8>,
+}
+
+impl<R: AsyncBufRead + Unpin> LineIncoming<R> {
+    pub const fn new(reader: R) -> Self {
+        Self {
+            reader,
+            line: Vec::new(),
+        }
+    }
+}
+
+impl<R: AsyncBufRead + Unpin + Send + 'static> Incoming for LineIncoming<R> {
+    async fn recv(&mut self) -> io::Result<Option<Message>> {
+        loop {
+            self.line.clear();
+            match read_line_bounded(&mut self.reader, &mut self.line, MAX_LINE_LEN).await? {
+                ReadLine::Eof => return Ok(None),
+                ReadLine::TooLong => {
+                    log::error!("dropping a message longer than {MAX_LINE_LEN} bytes");
+                    continue;
+                }
+                ReadLine::Line => {}
+            }
+            if self.line.trim_ascii().is_empty() {
+                continue;
+            }
+
+            log::info!(
+                "message: {}",
+                String::from_utf8_lossy(&self.line).trim_end()
+            );
+            match serde_json::from_slice::<Message>(&self.line) {
+                Ok(msg) => return Ok(Some(msg)),
+                Err(e) => log::error!("{e}"),
+            }
+        }
+    }
+}
+
+/// Writes one JSON message per line.
+#[derive(Debug)]
+pub struct LineOutgoing<W> {
+    writer: W,
+    buf: Vec<u8>,
+}
+
+impl<W: AsyncWrite + Unpin> LineOutgoing<W> {
+    pub const fn new(writer: W) -> Self {
+        Self {
+            writer,
+            buf: Vec::new(),
+        }
+    }
+}
+
+impl<W: AsyncWrite + Unpin + Send + 'static> Outgoing for LineOutgoing<W> {
+    async fn send_batch(&mut self, batch: Vec<Message>) -> io::Result<u64> {
+        self.buf.clear();
+        for msg in &batch {
+            // a message is serialized in one go, so lines never interleave
+            serde_json::to_writer(&mut self.buf, msg)?;
+            self.buf.push(b'\n');
+        }
+        self.writer.write_all(&self.buf).await?;
+        self.writer.flush().await?;
+        Ok(self.buf.len() as u64)
+    }
+}
+
+/// Maelstrom's transport: messages in on stdin, out on stdout.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Stdio;
+
+impl Transport for Stdio {
+    type Incoming = LineIncoming<BufReader<tokio::io::Stdin>>;
+    type Outgoing = LineOutgoing<tokio::io::Stdout>;
+
+    fn split(self) -> io::Result<(Self::Incoming, Self::Outgoing)> {
+        Ok((
+            LineIncoming::new(BufReader::new(tokio::io::stdin())),
+            LineOutgoing::new(tokio::io::stdout()),
+        ))
+    }
+}
+
+/// Line-delimited JSON over a TCP connection, e.g. to a router forwarding messages by `dest`.
+#[derive(Debug)]
+pub struct Tcp {
+    stream: TcpStream,
+}
+
+impl Tcp {
+    /// # Errors
+    /// - forwards `io` errors
+    pub async fn connect(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        Ok(Self::from_stream(TcpStream::connect(addr).await?))
+    }
+
+    #[must_use]
+    pub const fn from_stream(stream: TcpStream) -> Self {
+        Self { stream }
+    }
+}
+
+impl Transport for Tcp {
+    type Incoming = LineIncoming<BufReader<tcp::OwnedReadHalf>>;
+    type Outgoing = LineOutgoing<tcp::OwnedWriteHalf>;
+
+    fn split(self) -> io::Result<(Self::Incoming, Self::Outgoing)> {
+        self.stream.set_nodelay(true)?;
+        let (read, write) = self.stream.into_split();
+        Ok((
+            LineIncoming::new(BufReader::new(read)),
+            LineOutgoing::new(write),
+        ))
+    }
+}
+
+/// In-process transport passing messages over channels, without any serialization.
+#[derive(Debug)]
+pub struct Channel {
+    incoming: mpsc::UnboundedReceiver<Message>,
+    outgoing: mpsc::UnboundedSender<Message>,
+}
+
+impl Channel {
+    #[must_use]
+    pub const fn new(
+        incoming: mpsc::UnboundedReceiver<Message>,
+        outgoing: mpsc::UnboundedSender<Message>,
+    ) -> Self {
+        Self { incoming, outgoing }
+    }
+
+    /// Two transports wired to each other, what one sends the other receives.
+    #[must_use]
+    pub fn pair() -> (Self, Self) {
+        let (a_tx, a_rx) = mpsc::unbounded_channel();
+        let (b_tx, b_rx) = mpsc::unbounded_channel();
+        (Self::new(a_rx, b_tx), Self::new(b_rx, a_tx))
+    }
+
+    /// Receives a message sent by the other end.
+    pub async fn recv(&mut self) -> Option<Message> {
+        self.incoming.recv().await
+    }
+
+    /// Sends a message to the other end.
+    ///
+    /// # Errors
+    /// - returns [`io::ErrorKind::BrokenPipe`] if the other end is gone
+    pub fn send(&self, msg: Message) -> io::Result<()> {
+        self.outgoing.send(msg).map_err(|_| broken_pipe())
+    }
+}
+
+fn broken_pipe() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::BrokenPipe,
+        "other end of the channel is gone",
+    )
+}
+
+#[derive(Debug)]
+pub struct ChannelIncoming(mpsc::UnboundedReceiver<Message>);
+
+impl Incoming for ChannelIncoming {
+    async fn recv(&mut self) -> io::Result<Option<Message>> {
+        Ok(self.0.recv().await)
+    }
+}
+
+#[derive(Debug)]
+pub struct ChannelOutgoing(mpsc::UnboundedSender<Message>);
+
+impl Outgoing for ChannelOutgoing {
+    async fn send_batch(&mut self, batch: Vec<Message>) -> io::Result<u64> {
+        for msg in batch {
+            self.0.send(msg).map_err(|_| broken_pipe())?;
+        }
+        Ok(0)
+    }
+}
+
+impl Transport for Channel {
+    type Incoming = ChannelIncoming;
+    type Outgoing = ChannelOutgoing;
+
+    fn split(self) -> io::Result<(Self::Incoming, Self::Outgoing)> {
+        Ok((
+            ChannelIncoming(self.incoming),
+            ChannelOutgoing(self.outgoing),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use serde_json::{Value, json};
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::{TcpListener, TcpStream};
+
+    use super::Tcp;
+    use crate::error::Error;
+    use crate::types::Node;
+
+    #[tokio::test]
+    async fn tcp_serves_an_echo_round_trip() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let mut handlers = crate::build_default_handlers();
+        handlers.insert(
+            "echo",
+            Arc::new(|srv_mutex, msg| {
+                Box::pin(async move {
+                    let srv = srv_mutex.lock().unwrap();
+                    let echo = json!({"echo": msg.body["echo"]});
+                    let reply = srv
+                        .build_reply("echo_ok", &msg, echo)
+                        .ok_or_else(|| Error::malformed("a request without a `msg_id`"))?;
+                    Ok(srv.send(&reply)?)
+                })
+            }),
+        );
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let node = Arc::new(Mutex::new(Node::default()));
+            crate::serve_with(node, handlers, Tcp::from_stream(stream)).await
+        });
+
+        let (read, mut write) = TcpStream::connect(addr).await.unwrap().into_split();
+        let mut lines = BufReader::new(read).lines();
+        let mut roundtrip = async |body: Value| {
+            let msg = json!({"src": "c1", "dest": "n1", "body": body});
+            write
+                .write_all(format!("{msg}\n").as_bytes())
+                .await
+                .unwrap();
+            let line = lines.next_line().await.unwrap().unwrap();
+            serde_json::from_str::<Value>(&line).unwrap()
+        };
+
+        let init = json!({"type": "init", "msg_id": 1, "node_id": "n1", "node_ids": ["n1"]});
+        let reply = roundtrip(init).await;
+        assert_eq!(reply["body"]["type"], "init_ok");
+        let reply = roundtrip(json!({"type": "echo", "msg_id": 2, "echo": "hi"})).await;
+        assert_eq!(reply["src"], "n1");
+        assert_eq!(reply["dest"], "c1");
+        assert_eq!(
+            reply["body"],
+            json!({"type": "echo_ok", "in_reply_to": 2, "echo": "hi"})
+        );
+
+        // closing the connection shuts the server down cleanly
+        drop(write);
+        server.await.unwrap().unwrap();
+    }
+}
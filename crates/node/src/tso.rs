@@ -0,0 +1,41 @@
+//! Client for Maelstrom's `lin-tso` timestamp oracle.
+
+use std::sync::{Arc, Mutex};
+
+use serde_json::json;
+
+use crate::kv::KvError;
+use crate::server::Server;
+use crate::service::{LIN_TSO, Service};
+
+/// Hands out timestamps that only ever go up, across the whole cluster.
+#[derive(Debug, Clone)]
+pub struct TsoClient {
+    service: Service,
+}
+
+impl TsoClient {
+    #[must_use]
+    pub const fn new(service: Service) -> Self {
+        Self { service }
+    }
+
+    pub fn lin_tso(server: Arc<Mutex<dyn Server + Send + Sync + 'static>>) -> Self {
+        Self::new(Service::new(server, LIN_TSO))
+    }
+
+    #[must_use]
+    pub const fn service(&self) -> &Service {
+        &self.service
+    }
+
+    /// A timestamp greater than every one handed out before this call.
+    ///
+    /// # Errors
+    /// - [`KvError::Malformed`] if the reply has no timestamp
+    /// - [`KvError::Rpc`] if the oracle couldn't be reached
+    pub async fn ts(&self) -> Result<u64, KvError> {
+        let reply = self.service.call(json!({"type": "ts"})).await?;
+        serde_json::from_value(reply.body["ts"].clone()).map_err(KvError::Malformed)
+    }
+}
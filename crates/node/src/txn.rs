@@ -0,0 +1,357 @@
+//! Totally-available transactions over registers, as Maelstrom's `txn-rw-register` workload
+//! sends them.
+//!
+//! Every server runs transactions against its own copy of the registers and replicates the
+//! writes to every other server, retrying until they are acknowledged. A transaction's writes are
+//! stamped with one Lamport timestamp and a register keeps the write with the highest one, so
+//! replicas converge and any two transactions order their writes the same way on every register,
+//! ruling out dirty writes (G0). Histories only show that order for writes of keys the
+//! transaction read first, unless [`TxnExecutor::with_stamps`] has replies carry the stamps, which
+//! [`txn_rw_register`](crate::checker::txn_rw_register) then orders every write by.
+//!
+//! Under [`Isolation::ReadUncommitted`] each write is applied and replicated on its own.
+//! Under [`Isolation::ReadCommitted`] a transaction's writes are buffered and installed at once
+//! with a single stamp, so nobody reads a write its transaction overwrote (G1b) and, as nothing
+//! aborts, nobody reads an aborted one either (G1a).
+//!
+//! Transactions over lists, for `txn-list-append`, are in [`list_append`].
+
+pub mod list_append;
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::time::Duration;
+
+use crate::error::Error;
+use crate::handlers::HandlersMap;
+use crate::rpc::rpc;
+use crate::server::Server;
+
+/// Type of the messages shipping a batch of writes to a peer.
+pub const REPLICATE: &str = "txn_replicate";
+/// Type of the replies acknowledging a batch.
+pub const REPLICATE_OK: &str = "txn_replicate_ok";
+/// How long to wait for an ack before shipping a batch again.
+const REPLICATE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// One operation of a transaction, `["r", key, value]` or `["w", key, value]` on the wire.
+///
+/// Reads carry `None` in requests and what they read in replies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(
+    try_from = "(String, u64, Option<u64>)",
+    into = "(String, u64, Option<u64>)"
+)]
+pub enum MicroOp {
+    Read { key: u64, value: Option<u64> },
+    Write { key: u64, value: u64 },
+}
+
+impl MicroOp {
+    #[must_use]
+    pub const fn key(&self) -> u64 {
+        match self {
+            Self::Read { key, .. } | Self::Write { key, .. } => *key,
+        }
+    }
+}
+
+impl TryFrom<(String, u64, Option<u64>)> for MicroOp {
+    type Error = String;
+
+    fn try_from((f, key, value): (String, u64, Option<u64>)) -> Result<Self, Self::Error> {
+        match (f.as_str(), value) {
+            ("r", value) => Ok(Self::Read { key, value }),
+            ("w", Some(value)) => Ok(Self::Write { key, value }),
+            ("w", None) => Err(format!("write to {key} without a value")),
+            _ => Err(format!("unknown micro-op `{f}`, expected `r` or `w`")),
+        }
+    }
+}
+
+impl From<MicroOp> for (String, u64, Option<u64>) {
+    fn from(op: MicroOp) -> Self {
+        match op {
+            MicroOp::Read { key, value } => ("r".to_string(), key, value),
+            MicroOp::Write { key, value } => ("w".to_string(), key, Some(value)),
+        }
+    }
+}
+
+/// The isolation level transactions run at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Isolation {
+    ReadUncommitted,
+    ReadCommitted,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseIsolationError(String);
+
+impl fmt::Display for ParseIsolationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "unknown isolation `{}`, expected `read-uncommitted` or `read-committed`",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for ParseIsolationError {}
+
+impl FromStr for Isolation {
+    type Err = ParseIsolationError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "read-uncommitted" => Ok(Self::ReadUncommitted),
+            "read-committed" => Ok(Self::ReadCommitted),
+            _ => Err(ParseIsolationError(s.to_string())),
+        }
+    }
+}
+
+/// A Lamport timestamp, ties broken by the server that took it, then the position of the write
+/// in its transaction. `[clock, node, position]` on the wire.
+pub type Stamp = (u64, String, usize);
+
+/// Writes of one transaction, installed and replicated together.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Batch {
+    stamp: Stamp,
+    writes: Vec<(u64, u64)>,
+}
+
+#[derive(Debug, Default)]
+struct Store {
+    registers: HashMap<u64, (Stamp, u64)>,
+    clock: u64,
+}
+
+impl Store {
+    fn stamp(&mut self, node_id: &str) -> Stamp {
+        self.clock += 1;
+        (self.clock, node_id.to_string(), 0)
+    }
+
+    /// Installs the writes of `batch` that are newer than what their register holds.
+    fn apply(&mut self, batch: &Batch) {
+        self.clock = self.clock.max(batch.stamp.0);
+        for &(key, value) in &batch.writes {
+            match self.registers.get(&key) {
+                Some((stamp, _)) if *stamp >= batch.stamp => {}
+                _ => {
+                    self.registers.insert(key, (batch.stamp.clone(), value));
+                }
+            }
+        }
+    }
+
+    fn read(&self, key: u64) -> Option<u64> {
+        self.registers.get(&key).map(|&(_, value)| value)
+    }
+
+    /// Runs `txn`, filling in what its reads read, and returns the batches to replicate along
+    /// with the stamp each micro-op was installed with, `None` for reads and overwritten writes.
+    fn execute(
+        &mut self,
+        node_id: &str,
+        isolation: Isolation,
+        txn: &mut [MicroOp],
+    ) -> (Vec<Batch>, Vec<Option<Stamp>>) {
+        // all writes share a stamp, so two transactions order their writes the same on every
+        // register they both write
+        let mut stamp = self.stamp(node_id);
+        let mut batches = Vec::new();
+        let mut stamps = vec![None; txn.len()];
+        // the transaction's own writes and where they are, installed once it's done under read
+        // committed
+        let mut buffered = BTreeMap::new();
+        for (position, op) in txn.iter_mut().enumerate() {
+            match (op, isolation) {
+                (MicroOp::Read { key, value }, _) => {
+                    *value = buffered
+                        .get(key)
+                        .map(|&(value, _)| value)
+                        .or_else(|| self.read(*key));
+                }
+                (&mut MicroOp::Write { key, value }, Isolation::ReadUncommitted) => {
+                    stamp.2 = position;
+                    let batch = Batch {
+                        stamp: stamp.clone(),
+                        writes: vec![(key, value)],
+                    };
+                    self.apply(&batch);
+                    batches.push(batch);
+                    stamps[position] = Some(stamp.clone());
+                }
+                (&mut MicroOp::Write { key, value }, Isolation::ReadCommitted) => {
+                    buffered.insert(key, (value, position));
+                }
+            }
+        }
+        if !buffered.is_empty() {
+            for &(_, position) in buffered.values() {
+                stamps[position] = Some(stamp.clone());
+            }
+            let batch = Batch {
+                stamp,
+                writes: buffered
+                    .into_iter()
+                    .map(|(key, (value, _))| (key, value))
+                    .collect(),
+            };
+            self.apply(&batch);
+            batches.push(batch);
+        }
+        (batches, stamps)
+    }
+}
+
+/// Runs the `txn` requests of a server once [`TxnExecutor::install`]ed.
+///
+/// Clones share the registers.
+#[derive(Debug, Clone)]
+pub struct TxnExecutor {
+    store: Arc<Mutex<Store>>,
+    isolation: Isolation,
+    stamps: bool,
+}
+
+impl TxnExecutor {
+    #[must_use]
+    pub fn new(isolation: Isolation) -> Self {
+        Self {
+            store: Arc::default(),
+            isolation,
+            stamps: false,
+        }
+    }
+
+    /// Has `txn_ok` replies carry `stamps`, the stamp every micro-op was installed with and
+    /// `null` for the others, so a checker knows the order writes took effect in.
+    ///
+    /// Off by default: Maelstrom's `txn_ok` only has `txn`.
+    #[must_use]
+    pub const fn with_stamps(mut self) -> Self {
+        self.stamps = true;
+        self
+    }
+
+    #[must_use]
+    pub const fn isolation(&self) -> Isolation {
+        self.isolation
+    }
+
+    /// What this server's copy of `key` holds.
+    ///
+    /// # Panics
+    /// Panics if the mutex on the registers is poisoned.
+    #[must_use]
+    pub fn read(&self, key: u64) -> Option<u64> {
+        self.store.lock().unwrap().read(key)
+    }
+
+    /// Runs `txn` against this server's registers, filling in what its reads read, and starts
+    /// replicating its writes to every other server. Returns the stamp each micro-op was
+    /// installed with, `None` for reads and writes the transaction overwrote.
+    ///
+    /// # Panics
+    /// Panics if a mutex is poisoned.
+    pub fn execute(
+        &self,
+        srv_mutex: &Arc<Mutex<dyn Server + Send + Sync>>,
+        txn: &mut [MicroOp],
+    ) -> Vec<Option<Stamp>> {
+        let node_id = srv_mutex.lock().unwrap().get_id();
+        let (batches, stamps) = self
+            .store
+            .lock()
+            .unwrap()
+            .execute(&node_id, self.isolation, txn);
+        for batch in batches {
+            replicate(srv_mutex, &batch);
+        }
+        stamps
+    }
+
+    /// Registers the `txn` handler and the [`REPLICATE`] one, installing batches from peers.
+    ///
+    /// # Panics
+    /// The handlers panic if a mutex is poisoned.
+    pub fn install(&self, handlers: &mut HandlersMap<dyn Server + Send + Sync>) {
+        let executor = self.clone();
+        handlers.insert(
+            "txn",
+            Arc::new(move |srv_mutex, msg| {
+                let executor = executor.clone();
+                Box::pin(async move {
+                    let mut txn: Vec<MicroOp> = serde_json::from_value(msg.body["txn"].clone())
+                        .map_err(|e| Error::malformed(e.to_string()))?;
+                    let stamps = executor.execute(&srv_mutex, &mut txn);
+                    let body = if executor.stamps {
+                        json!({"txn": txn, "stamps": stamps})
+                    } else {
+                        json!({"txn": txn})
+                    };
+                    let srv = srv_mutex.lock().unwrap();
+                    let Some(reply) = srv.build_reply("txn_ok", &msg, body) else {
+                        return Ok(());
+                    };
+                    Ok(srv.send(&reply)?)
+                })
+            }),
+        );
+        let executor = self.clone();
+        handlers.insert(
+            REPLICATE,
+            Arc::new(move |srv_mutex, msg| {
+                let executor = executor.clone();
+                Box::pin(async move {
+                    let batch: Batch = serde_json::from_value(msg.body["batch"].clone())
+                        .map_err(|e| Error::malformed(e.to_string()))?;
+                    executor.store.lock().unwrap().apply(&batch);
+                    let srv = srv_mutex.lock().unwrap();
+                    let Some(reply) = srv.build_reply(REPLICATE_OK, &msg, json!({})) else {
+                        return Ok(());
+                    };
+                    Ok(srv.send(&reply)?)
+                })
+            }),
+        );
+    }
+}
+
+/// Ships `batch` to every other server, each until it acknowledges it.
+fn replicate(srv_mutex: &Arc<Mutex<dyn Server + Send + Sync>>, batch: &Batch) {
+    let srv = srv_mutex.lock().unwrap();
+    let node_id = srv.get_id();
+    let shutdown = srv.get_shutdown();
+    let peers = srv.get_topology();
+    drop(srv);
+
+    for peer in peers.into_iter().filter(|peer| *peer != node_id) {
+        let srv_mutex = srv_mutex.clone();
+        let body = json!({"type": REPLICATE, "batch": batch});
+        let triggered = shutdown.clone();
+        shutdown.spawn(async move {
+            loop {
+                tokio::select! {
+                    () = triggered.triggered() => return,
+                    result = rpc(&srv_mutex, &peer, body.clone(), REPLICATE_TIMEOUT) => {
+                        match result {
+                            Ok(_) => return,
+                            Err(e) => log::debug!("replicating to {peer} failed, retrying: {e}"),
+                        }
+                    }
+                }
+            }
+        });
+    }
+}
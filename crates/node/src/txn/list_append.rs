@@ -0,0 +1,98 @@
+//! Transactions over lists of integers, as Maelstrom's `txn-list-append` workload sends them.
+//!
+//! [`Lists`] runs a transaction's micro-ops one after the other, all at once, so as the state
+//! machine of a replicated log, be it a [`Raft`](crate::Raft) one or a
+//! [`Sequencer`](crate::calvin::Sequencer)'s, transactions are strictly serializable.
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+
+use crate::consensus::StateMachine;
+use crate::error::{Error, ErrorCode};
+
+/// One operation of a transaction, `["append", key, value]` or `["r", key, list]` on the wire.
+///
+/// Reads carry `null` in requests and the whole list in replies.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "(String, u64, Value)", into = "(String, u64, Value)")]
+pub enum ListOp {
+    Append { key: u64, value: u64 },
+    Read { key: u64, value: Option<Vec<u64>> },
+}
+
+impl ListOp {
+    #[must_use]
+    pub const fn key(&self) -> u64 {
+        match self {
+            Self::Append { key, .. } | Self::Read { key, .. } => *key,
+        }
+    }
+}
+
+impl TryFrom<(String, u64, Value)> for ListOp {
+    type Error = String;
+
+    fn try_from((f, key, value): (String, u64, Value)) -> Result<Self, Self::Error> {
+        match f.as_str() {
+            "append" => value
+                .as_u64()
+                .map(|value| Self::Append { key, value })
+                .ok_or_else(|| format!("append to {key} of {value}, not an integer")),
+            "r" => serde_json::from_value(value)
+                .map(|value| Self::Read { key, value })
+                .map_err(|e| format!("read of {key}: {e}")),
+            _ => Err(format!("unknown micro-op `{f}`, expected `append` or `r`")),
+        }
+    }
+}
+
+impl From<ListOp> for (String, u64, Value) {
+    fn from(op: ListOp) -> Self {
+        match op {
+            ListOp::Append { key, value } => ("append".to_string(), key, json!(value)),
+            ListOp::Read { key, value } => ("r".to_string(), key, json!(value)),
+        }
+    }
+}
+
+/// The lists by key, a key nobody appended to reading as an empty list.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Lists {
+    lists: HashMap<u64, Vec<u64>>,
+}
+
+impl Lists {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[must_use]
+    pub fn get(&self, key: u64) -> &[u64] {
+        self.lists.get(&key).map_or(&[], Vec::as_slice)
+    }
+
+    /// Runs `txn`, filling in what its reads read.
+    pub fn execute(&mut self, txn: &mut [ListOp]) {
+        for op in txn {
+            match op {
+                ListOp::Append { key, value } => self.lists.entry(*key).or_default().push(*value),
+                ListOp::Read { key, value } => *value = Some(self.get(*key).to_vec()),
+            }
+        }
+    }
+}
+
+impl StateMachine for Lists {
+    fn apply(&mut self, command: &Value) -> Result<Value, Error> {
+        if command["type"] != "txn" {
+            return Err(Error::new(ErrorCode::NotSupported, "not a txn"));
+        }
+        let mut txn: Vec<ListOp> = serde_json::from_value(command["txn"].clone())
+            .map_err(|e| Error::malformed(e.to_string()))?;
+        self.execute(&mut txn);
+        Ok(json!({"type": "txn_ok", "txn": txn}))
+    }
+}
@@ -1,24 +1,33 @@
-use std::collections::{hash_map::DefaultHasher, HashSet};
+use std::collections::{HashMap, HashSet, hash_map::DefaultHasher};
 use std::hash::{Hash, Hasher};
 
 use rand::Rng;
+use tokio::sync::oneshot;
 
-#[derive(serde::Serialize, serde::Deserialize, Debug)]
-pub struct Message {
+use crate::outbox::Outbox;
+use crate::shutdown::Shutdown;
+use crate::topology::Topology;
+
+/// Replies awaited by in-flight requests, keyed by the `msg_id` of the request.
+pub type PendingReplies = HashMap<u64, oneshot::Sender<Message>>;
+
+/// A Maelstrom message, untyped by default. See [`Body`](crate::Body) for typed bodies.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct Message<B = serde_json::Value> {
     pub src: String,
     pub dest: String,
-    pub body: serde_json::Value,
+    pub body: B,
 }
 
-impl Message {
+impl<B: serde::Serialize> Message<B> {
     #[must_use]
     pub fn hash(&self) -> u64 {
-        let mut message_str = String::new();
-        message_str.push_str(&self.src);
-        message_str.push_str(&self.dest);
-        message_str.push_str(&self.body.to_string());
         let mut hasher = DefaultHasher::new();
-        message_str.hash(&mut hasher);
+        self.src.hash(&mut hasher);
+        self.dest.hash(&mut hasher);
+        serde_json::to_string(&self.body)
+            .unwrap_or_default()
+            .hash(&mut hasher);
         hasher.finish()
     }
 }
@@ -28,16 +37,28 @@ pub struct Node {
     pub id: String,
     pub values: HashSet<u64>,
     pub topology: HashSet<String>,
+    pub graph: Topology,
     pub msg_count: u64,
+    pub pending: PendingReplies,
+    pub shutdown: Shutdown,
+    pub outbox: Outbox,
 }
 
+/// State of the flooding g-counter: a local total and the adds already counted.
+///
+/// Despite the name it keeps everything in memory, [`KvClient`](crate::KvClient) is what talks
+/// to Maelstrom's `seq-kv` service.
 #[derive(Debug)]
 pub struct SequentialKV {
     pub counter: u64,
     pub values: HashSet<u64>,
     pub id: String,
     pub topology: HashSet<String>,
+    pub graph: Topology,
     pub msg_count: u64,
+    pub pending: PendingReplies,
+    pub shutdown: Shutdown,
+    pub outbox: Outbox,
 }
 
 impl Default for Node {
@@ -46,7 +67,11 @@ impl Default for Node {
             id: String::default(),
             values: HashSet::default(),
             topology: HashSet::default(),
+            graph: Topology::default(),
             msg_count: rand::rng().random_range(0..10000),
+            pending: PendingReplies::default(),
+            shutdown: Shutdown::default(),
+            outbox: Outbox::default(),
         }
     }
 }
@@ -57,8 +82,12 @@ impl Default for SequentialKV {
             id: String::default(),
             values: HashSet::default(),
             topology: HashSet::default(),
+            graph: Topology::default(),
             counter: 0,
             msg_count: rand::rng().random_range(0..10000),
+            pending: PendingReplies::default(),
+            shutdown: Shutdown::default(),
+            outbox: Outbox::default(),
         }
     }
 }
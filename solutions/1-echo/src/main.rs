@@ -2,32 +2,71 @@ use std::io;
 
 use std::sync::{Arc, Mutex};
 
-use serde_json::json;
+use node::{Body, HandlersMap, Message, Node, Server, TypedHandlers, build_default_handlers};
 
-use node::{Node, build_default_handlers};
+node::payload! {
+    enum Request {
+        "echo" => Echo { echo: String },
+    }
+}
+
+node::payload! {
+    enum Reply {
+        "echo_ok" => Echo { echo: String },
+    }
+}
+
+fn handlers() -> HandlersMap<dyn Server + Send + Sync> {
+    let mut handlers = build_default_handlers();
+    handlers.insert_typed(|node_mutex, msg: Message<Body<Request>>| async move {
+        let Request::Echo { echo } = &msg.body.payload;
+
+        let reply = msg.reply(Reply::Echo { echo: echo.clone() });
+        let sent = node_mutex.lock().unwrap().send(&reply);
+        Ok(sent?)
+    });
+    handlers
+}
 
 #[tokio::main]
 async fn main() -> io::Result<()> {
     env_logger::init();
 
-    let mut handlers = build_default_handlers();
-    handlers.insert(
-        "echo",
-        Arc::new(|node_mutex, msg| {
-            Box::pin(async move {
-                let srv = node_mutex.lock().unwrap();
-                let echo = msg.body["echo"].as_str().ok_or_else(|| {
-                    log::error!("ignoring invalid echo message :(");
-                })?;
-                
-                let reply = &srv.build_reply("echo_ok", &msg, json!({"echo": echo})).ok_or(())?;
-                srv.send(reply).map_err(|e| {
-                    log::error!("failed to send echo_ok: {e}");
-                })
+    let node_mutex = Arc::new(Mutex::new(Node::default()));
+    node::serve(node_mutex, handlers()).await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use node::Node;
+    use node::checker::echo;
+    use node::sim::{Sim, SimConfig};
+    use serde_json::json;
+    use tokio::time::Duration;
+
+    #[test]
+    fn echoes_back() {
+        Sim::new(SimConfig::default())
+            .nodes(1, || {
+                (Arc::new(Mutex::new(Node::default())), super::handlers())
             })
-        }),
-    );
+            .run(|cluster| async move {
+                let mut client = cluster.client();
+                for i in 0..10 {
+                    let echo = format!("hello {i}");
+                    let body = json!({"type": "echo", "echo": echo});
+                    let reply = client
+                        .request("n0", body, Duration::from_secs(1))
+                        .await
+                        .unwrap();
+                    assert_eq!(reply.body["type"], "echo_ok");
+                    assert_eq!(reply.body["echo"], echo);
+                }
 
-    let node_mutex = Arc::new(Mutex::new(Node::default()));
-    node::serve(node_mutex, handlers).await
+                let report = echo::check(&cluster.history());
+                assert!(report.valid, "{report}");
+            });
+    }
 }
@@ -3,44 +3,82 @@ use std::{
     sync::{Arc, Mutex},
 };
 
-use serde_json::json;
 use uuid::Uuid;
 
-use node::{Node, Server, build_default_handlers};
+use node::{Body, HandlersMap, Message, Node, Server, TypedHandlers, build_default_handlers};
+
+node::payload! {
+    enum Request {
+        "generate" => Generate,
+    }
+}
+
+node::payload! {
+    enum Reply {
+        "generate_ok" => Generate { id: String },
+    }
+}
+
+fn handlers() -> HandlersMap<dyn Server + Send + Sync> {
+    let mut handlers = build_default_handlers();
+    handlers.insert_typed(|srv_mutex, msg: Message<Body<Request>>| async move {
+        let Request::Generate = msg.body.payload;
+
+        let reply = msg.reply(Reply::Generate {
+            id: Uuid::now_v7().to_string(),
+        });
+        let sent = srv_mutex.lock().unwrap().send(&reply);
+        Ok(sent?)
+    });
+    handlers
+}
 
 #[tokio::main]
 async fn main() -> io::Result<()> {
     env_logger::init();
 
-    let mut handlers = build_default_handlers();
-    handlers.insert(
-        "generate",
-        Arc::new(|srv_mutex, msg| {
-            Box::pin(async move {
-                let mut srv_any = srv_mutex.lock().unwrap();
-                let Some(node) = srv_any.as_any_mut().downcast_mut::<Node>() else {
-                    return Ok(());
-                };
-
-                let Some("generate") = msg.body["type"].as_str() else {
-                    log::error!("ignoring invalid generate message :(");
-                    return Err(());
-                };
-
-                let reply = &node
-                    .build_reply(
-                        "generate_ok",
-                        &msg,
-                        json!({"id": Uuid::now_v7().to_string()}),
-                    )
-                    .ok_or(())?;
-                node.send(reply).map_err(|e| {
-                    log::error!("failed to send generate_ok: {e}");
-                })
+    let node_mutex = Arc::new(Mutex::new(Node::default()));
+    node::serve(node_mutex, handlers()).await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::sync::{Arc, Mutex};
+
+    use node::Node;
+    use node::checker::unique_ids;
+    use node::sim::{Sim, SimConfig};
+    use serde_json::json;
+    use tokio::time::Duration;
+
+    #[test]
+    fn ids_are_unique_across_nodes() {
+        let config = SimConfig {
+            seed: 2,
+            ..SimConfig::default()
+        };
+        let ids = Sim::new(config)
+            .nodes(3, || {
+                (Arc::new(Mutex::new(Node::default())), super::handlers())
             })
-        }),
-    );
+            .run(|cluster| async move {
+                let mut client = cluster.client();
+                let mut ids = Vec::new();
+                for i in 0..60 {
+                    let dest = &cluster.node_ids()[i % 3];
+                    let reply = client
+                        .request(dest, json!({"type": "generate"}), Duration::from_secs(1))
+                        .await
+                        .unwrap();
+                    ids.push(reply.body["id"].as_str().unwrap().to_string());
+                }
 
-    let node_mutex = Arc::new(Mutex::new(Node::default()));
-    node::serve(node_mutex, handlers).await
+                let report = unique_ids::check(&cluster.history());
+                assert!(report.valid, "{report}");
+                ids
+            });
+
+        assert_eq!(ids.iter().collect::<HashSet<_>>().len(), ids.len());
+    }
 }
@@ -20,3 +20,39 @@ maelstrom test \
   --time-limit 20 \
   --rate 100 \
   --latency 100
+
+# the values kept in a G-Set CRDT and gossiped
+BROADCAST_MODE=crdt maelstrom test \
+  -w broadcast \
+  --bin ../../target/debug/broadcast \
+  --node-count 5 \
+  --time-limit 20 \
+  --rate 10 \
+  --nemesis partition
+
+# perf, with only the deltas gossiped
+BROADCAST_MODE=crdt maelstrom test \
+  -w broadcast \
+  --bin ../../target/debug/broadcast \
+  --node-count 25 \
+  --time-limit 20 \
+  --rate 100 \
+  --latency 100
+
+# 3d, values batched per neighbor, every origin reaching everyone in one hop
+BROADCAST_MODE=batch BROADCAST_OVERLAY=complete maelstrom test \
+  -w broadcast \
+  --bin ../../target/debug/broadcast \
+  --node-count 25 \
+  --time-limit 20 \
+  --rate 100 \
+  --latency 100
+
+# 3e, fewer messages for more hops over a spanning tree of the grid
+BROADCAST_MODE=batch BROADCAST_OVERLAY=spanning-tree maelstrom test \
+  -w broadcast \
+  --bin ../../target/debug/broadcast \
+  --node-count 25 \
+  --time-limit 20 \
+  --rate 100 \
+  --latency 100
@@ -0,0 +1,213 @@
+//! Broadcast along an overlay of the graph the `topology` message gives, new values buffered per
+//! neighbor and shipped many at a time in one `gossip` message, acknowledged as a whole.
+//!
+//! Batches are retried until acknowledged, so a node relies on whoever sent it a value to reach
+//! that sender's own neighbors, and only forwards the value to the others. Until the graph
+//! arrives, it forwards to every other node.
+
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{Arc, Mutex};
+
+use serde_json::json;
+use tokio::time::{self, Duration, MissedTickBehavior};
+
+use node::{Body, Error, HandlersMap, Message, Node, Overlay, Server, TypedHandlers};
+
+use crate::{Reply, Request};
+
+/// How often the buffers are flushed.
+const FLUSH_INTERVAL: Duration = Duration::from_millis(50);
+/// Buffered values that get a neighbor's buffer flushed right away.
+const MAX_BATCH: usize = 64;
+/// How long to wait for a batch ack before shipping its values again.
+const ACK_TIMEOUT: Duration = Duration::from_secs(1);
+
+node::payload! {
+    enum Gossip {
+        "gossip" => Gossip { messages: BTreeSet<u64> },
+    }
+}
+
+node::payload! {
+    enum GossipReply {
+        "gossip_ok" => Gossip,
+    }
+}
+
+#[derive(Debug, Default)]
+struct Buffers {
+    pending: HashMap<String, BTreeSet<u64>>,
+    flushing: bool,
+}
+
+type Srv = Arc<Mutex<dyn Server + Send + Sync>>;
+
+/// Keeps the values new to this node and buffers them for its neighbors that aren't the node
+/// they came `from` or one of its neighbors in the graph, `from` being `None` for values from
+/// clients.
+///
+/// Returns the neighbors whose buffer is full.
+fn enqueue(
+    srv_mutex: &Srv,
+    buffers: &Mutex<Buffers>,
+    values: impl IntoIterator<Item = u64>,
+    from: Option<&str>,
+) -> Result<Vec<String>, Error> {
+    let mut srv_any = srv_mutex.lock().unwrap();
+    let node = srv_any
+        .as_any_mut()
+        .downcast_mut::<Node>()
+        .ok_or_else(|| Error::crash("server wasn't a node when calling a node handler"))?;
+    let new: Vec<u64> = values
+        .into_iter()
+        .filter(|&value| node.values.insert(value))
+        .collect();
+    if new.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let graph = node.get_graph();
+    let skipped: Vec<String> = match from {
+        Some(from) if graph.contains(from) => graph.neighbors(from).cloned().collect(),
+        // before the `topology` message, nothing says who else the sender reached
+        _ => Vec::new(),
+    };
+    let neighbors = node.get_neighbors();
+    drop(srv_any);
+
+    let mut buffers = buffers.lock().unwrap();
+    let mut full = Vec::new();
+    for neighbor in neighbors {
+        if Some(neighbor.as_str()) == from || skipped.contains(&neighbor) {
+            continue;
+        }
+        let pending = buffers.pending.entry(neighbor.clone()).or_default();
+        pending.extend(&new);
+        if pending.len() >= MAX_BATCH {
+            full.push(neighbor);
+        }
+    }
+    drop(buffers);
+    Ok(full)
+}
+
+/// Ships what's buffered for `neighbor` in one message, putting it back if no ack comes.
+fn flush(srv_mutex: &Srv, buffers: &Arc<Mutex<Buffers>>, neighbor: String) {
+    let Some(messages) = buffers.lock().unwrap().pending.remove(&neighbor) else {
+        return;
+    };
+    if messages.is_empty() {
+        return;
+    }
+
+    let (srv_mutex, buffers) = (srv_mutex.clone(), buffers.clone());
+    let shutdown = srv_mutex.lock().unwrap().get_shutdown();
+    shutdown.spawn(async move {
+        let body = json!(Body::new(Gossip::Gossip {
+            messages: messages.clone()
+        }));
+        if let Err(e) = node::rpc(&srv_mutex, &neighbor, body, ACK_TIMEOUT).await {
+            log::debug!("batch to {neighbor} failed, shipping it again: {e}");
+            let mut buffers = buffers.lock().unwrap();
+            buffers
+                .pending
+                .entry(neighbor)
+                .or_default()
+                .extend(messages);
+        }
+    });
+}
+
+/// Flushes every buffer each [`FLUSH_INTERVAL`], started with the first value to forward.
+fn start_flushing(srv_mutex: &Srv, buffers: &Arc<Mutex<Buffers>>) {
+    if std::mem::replace(&mut buffers.lock().unwrap().flushing, true) {
+        return;
+    }
+
+    let (srv_mutex, buffers) = (srv_mutex.clone(), buffers.clone());
+    let shutdown = srv_mutex.lock().unwrap().get_shutdown();
+    shutdown.clone().spawn(async move {
+        let mut ticks = time::interval(FLUSH_INTERVAL);
+        ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                () = shutdown.triggered() => return,
+                _ = ticks.tick() => {}
+            }
+            let neighbors: Vec<String> = buffers.lock().unwrap().pending.keys().cloned().collect();
+            for neighbor in neighbors {
+                flush(&srv_mutex, &buffers, neighbor);
+            }
+        }
+    });
+}
+
+/// Buffers `values` and ships the buffers that filled up.
+fn forward(
+    srv_mutex: &Srv,
+    buffers: &Arc<Mutex<Buffers>>,
+    values: impl IntoIterator<Item = u64>,
+    from: Option<&str>,
+) -> Result<(), Error> {
+    start_flushing(srv_mutex, buffers);
+    for neighbor in enqueue(srv_mutex, buffers, values, from)? {
+        flush(srv_mutex, buffers, neighbor);
+    }
+    Ok(())
+}
+
+pub fn handlers(overlay: Overlay) -> HandlersMap<dyn Server + Send + Sync> {
+    let mut handlers = node::build_default_handlers();
+    let buffers = Arc::new(Mutex::new(Buffers::default()));
+
+    let client_buffers = buffers.clone();
+    handlers.insert_typed(move |srv_mutex, msg: Message<Body<Request>>| {
+        let buffers = client_buffers.clone();
+        async move {
+            let reply = match msg.body.payload {
+                Request::Topology { ref topology } => {
+                    let mut srv = srv_mutex.lock().unwrap();
+                    let graph = overlay.build(&srv.get_topology(), &topology.clone().into());
+                    log::debug!("gossiping over {}", graph.stats());
+                    srv.set_graph(graph);
+                    drop(srv);
+                    Reply::Topology
+                }
+                Request::Read => {
+                    let mut srv_any = srv_mutex.lock().unwrap();
+                    let node = srv_any.as_any_mut().downcast_mut::<Node>().ok_or_else(|| {
+                        Error::crash("server wasn't a node when calling a node handler")
+                    })?;
+                    let messages = node.values.iter().copied().collect();
+                    drop(srv_any);
+                    Reply::Read { messages }
+                }
+                Request::Broadcast { message } => {
+                    forward(&srv_mutex, &buffers, [message], None)?;
+                    Reply::Broadcast
+                }
+            };
+            let sent = srv_mutex.lock().unwrap().send(&msg.reply(reply));
+            Ok(sent?)
+        }
+    });
+
+    handlers.insert_typed(move |srv_mutex, msg: Message<Body<Gossip>>| {
+        let buffers = buffers.clone();
+        async move {
+            let Gossip::Gossip { ref messages } = msg.body.payload;
+            forward(
+                &srv_mutex,
+                &buffers,
+                messages.iter().copied(),
+                Some(&msg.src),
+            )?;
+            let sent = srv_mutex
+                .lock()
+                .unwrap()
+                .send(&msg.reply(GossipReply::Gossip));
+            Ok(sent?)
+        }
+    });
+    handlers
+}
@@ -0,0 +1,33 @@
+//! Broadcast served by a grow-only set CRDT: the values are gossiped instead of flooded, each
+//! round only shipping what a peer hasn't acknowledged yet.
+
+use node::crdt::GSet;
+use node::{Body, Gossip, HandlersMap, Message, Server, TypedHandlers};
+
+use crate::{Reply, Request};
+
+pub fn handlers() -> HandlersMap<dyn Server + Send + Sync> {
+    let mut handlers = node::build_default_handlers();
+    let gossip = Gossip::new(GSet::new()).with_deltas();
+    gossip.install(&mut handlers);
+
+    handlers.insert_typed(move |srv_mutex, msg: Message<Body<Request>>| {
+        let gossip = gossip.clone();
+        async move {
+            let reply = match msg.body.payload {
+                // the gossip goes to every node, whatever the topology
+                Request::Topology { .. } => Reply::Topology,
+                Request::Read => Reply::Read {
+                    messages: gossip.read(|set| set.iter().copied().collect()),
+                },
+                Request::Broadcast { message } => {
+                    gossip.update(|set| set.insert(message));
+                    Reply::Broadcast
+                }
+            };
+            let sent = srv_mutex.lock().unwrap().send(&msg.reply(reply));
+            Ok(sent?)
+        }
+    });
+    handlers
+}
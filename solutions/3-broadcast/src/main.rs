@@ -1,106 +1,420 @@
+mod batch;
+mod crdt;
+
 use std::{
-    io,
+    collections::HashMap,
+    env, io,
     sync::{Arc, Mutex},
 };
 
 use serde_json::json;
 
-use node::{Message, Node, Server, build_default_handlers};
+use node::{
+    Body, Error, HandlersMap, Message, Node, Overlay, Server, TypedHandlers, build_default_handlers,
+};
 
-#[tokio::main]
-async fn main() -> io::Result<()> {
-    env_logger::init();
+/// Environment variable picking the implementation: `flood` (the default), `crdt` or `batch`.
+const MODE_VAR: &str = "BROADCAST_MODE";
+/// Environment variable picking the graph the `batch` mode gossips over, see [`Overlay`]'s
+/// `FromStr`. The one the `topology` message gives by default.
+const OVERLAY_VAR: &str = "BROADCAST_OVERLAY";
+
+node::payload! {
+    enum Request {
+        "topology" => Topology { topology: HashMap<String, Vec<String>> },
+        "read" => Read,
+        "broadcast" => Broadcast { message: u64 },
+    }
+}
 
+node::payload! {
+    enum Reply {
+        "topology_ok" => Topology,
+        "read_ok" => Read { messages: Vec<u64> },
+        "broadcast_ok" => Broadcast,
+    }
+}
+
+fn handlers() -> HandlersMap<dyn Server + Send + Sync> {
     let mut handlers = build_default_handlers();
-    handlers.insert(
-        "topology",
-        Arc::new(|srv_mutex, msg| {
-            Box::pin(async move {
-                let mut srv_any = srv_mutex.lock().unwrap();
-                let node = srv_any.as_any_mut().downcast_mut::<Node>().ok_or_else(|| {
-                    log::error!("server wasn't a node while calling a node handler");
-                })?;
-                let topo = msg.body["topology"].as_object().ok_or_else(|| {
-                    log::error!("ignoring invalid topology message :(");
-                })?;
-
-                node.topology.extend(topo.keys().cloned());
-
-                let reply = node.build_reply("topology_ok", &msg, json!({})).ok_or(())?;
-                node.send(&reply).map_err(|e| {
-                    log::error!("failed to send topology_ok: {e}");
-                })
-            })
-        }),
-    );
-    handlers.insert(
-        "read",
-        Arc::new(|srv_mutex, msg| {
-            Box::pin(async move {
-                let mut srv = srv_mutex.lock().unwrap();
-                let node= srv.as_any_mut().downcast_mut::<Node>().ok_or_else(|| {
-                    log::error!("server wasn't a node when calling a node handler");
-                })?;
-
-                let values: Vec<&u64> = node.values.iter().collect();
-                let reply = node
-                    .build_reply("read_ok", &msg, json!({"messages": values}))
-                    .ok_or(())?;
-
-                srv.send(&reply).map_err(|e| {
-                    log::error!("failed to send read_ok: {e}");
-                })
-            })
-        }),
-    );
-    handlers.insert(
-        "broadcast",
-        Arc::new(|srv_mutex, msg| {
-            Box::pin(async move {
-                let mut srv_any = srv_mutex.lock().unwrap();
-                let node= srv_any.as_any_mut().downcast_mut::<Node>().ok_or_else(|| {
-                    log::error!("server wasn't a node when calling a node handler");
-                })?;
-
-                let number = msg.body["message"].as_u64().ok_or(())?;
-                if node.values.contains(&number) {
-                    return Ok(());
-                }
+    handlers.insert_typed(|srv_mutex, msg: Message<Body<Request>>| async move {
+        let mut srv_any = srv_mutex.lock().unwrap();
+        let node = srv_any
+            .as_any_mut()
+            .downcast_mut::<Node>()
+            .ok_or_else(|| Error::crash("server wasn't a node when calling a node handler"))?;
 
-                node.values.insert(number);
+        match msg.body.payload {
+            Request::Topology { ref topology } => {
+                node.set_graph(topology.clone().into());
 
-                let reply = node
-                    .build_reply("broadcast_ok", &msg, json!({}))
-                    .ok_or(())?;
+                let sent = node.send(&msg.reply(Reply::Topology));
+                drop(srv_any);
+                Ok(sent?)
+            }
+            Request::Read => {
+                let messages = node.values.iter().copied().collect();
 
-                _ = node.send(&reply);
+                let sent = node.send(&msg.reply(Reply::Read { messages }));
+                drop(srv_any);
+                Ok(sent?)
+            }
+            Request::Broadcast { message } => {
+                // always ack, the sender keeps retrying until it hears back
+                _ = node.send(&msg.reply(Reply::Broadcast));
+
+                if !node.values.insert(message) {
+                    return Ok(());
+                }
 
                 let node_id = node.get_id();
-                let all_nodes = node.get_topology();
+                let neighbors = node.get_neighbors();
                 drop(srv_any);
 
-                // send it to everyone else
-                for n in all_nodes {
-                    if *n == node_id {
-                        continue;
-                    }
+                for n in neighbors {
                     let new_msg = Message {
                         dest: n,
                         src: node_id.clone(),
-                        body: msg.body.clone(),
+                        body: json!(Body::new(Request::Broadcast { message })),
                     };
-                    let node_mut = srv_mutex.clone();
                     let _ = node::send_synchronous(
-                        &node_mut,
+                        &srv_mutex,
                         new_msg,
                         tokio::time::Duration::from_millis(500),
                     );
                 }
                 Ok(())
-            })
-        }),
-    );
+            }
+        }
+    });
+    handlers
+}
 
+#[tokio::main]
+async fn main() -> io::Result<()> {
+    env_logger::init();
+
+    let handlers = match env::var(MODE_VAR).as_deref() {
+        Ok("flood") | Err(_) => handlers(),
+        Ok("crdt") => crdt::handlers(),
+        Ok("batch") => {
+            let overlay = env::var(OVERLAY_VAR)
+                .map_or(Ok(Overlay::Given), |overlay| overlay.parse())
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+            batch::handlers(overlay)
+        }
+        Ok(mode) => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("unknown {MODE_VAR} `{mode}`, expected `flood`, `crdt` or `batch`"),
+            ));
+        }
+    };
     let node_mutex = Arc::new(Mutex::new(Node::default()));
     node::serve(node_mutex, handlers).await
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, HashSet};
+    use std::sync::{Arc, Mutex};
+
+    use node::checker::broadcast;
+    use node::sim::{Cluster, Fault, Nemesis, Sim, SimConfig};
+    use node::{Node, Overlay, Topology};
+    use serde_json::json;
+    use tokio::time::Duration;
+
+    #[test]
+    fn values_reach_every_node_despite_faults() {
+        let config = SimConfig {
+            seed: 3,
+            drop_rate: 0.2,
+            duplicate_rate: 0.1,
+            reorder_rate: 0.2,
+            ..SimConfig::default()
+        };
+        Sim::new(config)
+            .nodes(5, || {
+                (Arc::new(Mutex::new(Node::default())), super::handlers())
+            })
+            .run(|cluster| async move {
+                let mut client = cluster.client();
+                let timeout = Duration::from_secs(1);
+                for message in 0..20_u64 {
+                    let dest = &cluster.node_ids()[usize::try_from(message).unwrap() % 5];
+                    let body = json!({"type": "broadcast", "message": message});
+                    client.request(dest, body, timeout).await.unwrap();
+                }
+
+                tokio::time::sleep(Duration::from_secs(5)).await;
+
+                for id in cluster.node_ids() {
+                    let reply = client
+                        .request(id, json!({"type": "read"}), timeout)
+                        .await
+                        .unwrap();
+                    let read: HashSet<u64> =
+                        serde_json::from_value(reply.body["messages"].clone()).unwrap();
+                    assert_eq!(read, (0..20).collect(), "{id} is missing values");
+                }
+            });
+    }
+
+    #[test]
+    fn values_reach_every_node_after_partitions_heal() {
+        let second = Duration::from_secs(1);
+        let nemesis = Nemesis::new()
+            .at(Duration::ZERO, Fault::MajorityMinority)
+            .at(second, Fault::MajoritiesRing)
+            .at(second * 2, Fault::Bridge)
+            .at(second * 3, Fault::RandomHalves)
+            .at(second * 4, Fault::Heal);
+        Sim::new(SimConfig {
+            seed: 5,
+            ..SimConfig::default()
+        })
+        .nodes(5, || {
+            (Arc::new(Mutex::new(Node::default())), super::handlers())
+        })
+        .nemesis(nemesis)
+        .run(|cluster| async move {
+            let mut client = cluster.client();
+            let timeout = Duration::from_secs(1);
+            for message in 0..40_u64 {
+                let dest = &cluster.node_ids()[usize::try_from(message).unwrap() % 5];
+                let body = json!({"type": "broadcast", "message": message});
+                client.request(dest, body, timeout).await.unwrap();
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+            assert!(cluster.stats().partitioned > 0);
+
+            // healed at 4s, give the retries time to get through
+            tokio::time::sleep(Duration::from_secs(3)).await;
+
+            for id in cluster.node_ids() {
+                let reply = client
+                    .request(id, json!({"type": "read"}), timeout)
+                    .await
+                    .unwrap();
+                let read: HashSet<u64> =
+                    serde_json::from_value(reply.body["messages"].clone()).unwrap();
+                assert_eq!(read, (0..40).collect(), "{id} is missing values");
+            }
+
+            let report = broadcast::check(&cluster.history());
+            assert!(report.valid, "{report}");
+            assert_eq!(report.stats["stale_reads"], 0);
+        });
+    }
+
+    #[test]
+    fn crdt_mode_converges_after_partitions_heal() {
+        let nemesis = Nemesis::new().flapping(
+            &Fault::RandomHalves,
+            Duration::ZERO,
+            Duration::from_secs(3),
+            Duration::from_millis(500),
+        );
+        Sim::new(SimConfig {
+            seed: 9,
+            drop_rate: 0.2,
+            ..SimConfig::default()
+        })
+        .nodes(5, || {
+            (
+                Arc::new(Mutex::new(Node::default())),
+                super::crdt::handlers(),
+            )
+        })
+        .nemesis(nemesis)
+        .run(|cluster| async move {
+            let mut client = cluster.client();
+            let timeout = Duration::from_secs(1);
+            for message in 0..30_u64 {
+                let dest = &cluster.node_ids()[usize::try_from(message).unwrap() % 5];
+                let body = json!({"type": "broadcast", "message": message});
+                client.request(dest, body, timeout).await.unwrap();
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+            assert!(cluster.stats().partitioned > 0);
+
+            tokio::time::sleep(Duration::from_secs(2)).await;
+
+            for id in cluster.node_ids() {
+                let reply = client
+                    .request(id, json!({"type": "read"}), timeout)
+                    .await
+                    .unwrap();
+                let read: HashSet<u64> =
+                    serde_json::from_value(reply.body["messages"].clone()).unwrap();
+                assert_eq!(read, (0..30).collect(), "{id} is missing values");
+            }
+
+            let report = broadcast::check(&cluster.history());
+            assert!(report.valid, "{report}");
+        });
+    }
+
+    /// Tells every node its neighbors on a grid, like Maelstrom does by default.
+    async fn send_grid(cluster: &Cluster) {
+        let grid = Topology::grid(cluster.node_ids());
+        let topology: HashMap<&String, Vec<&String>> = grid
+            .nodes()
+            .map(|id| (id, grid.neighbors(id).collect()))
+            .collect();
+        let mut client = cluster.client();
+        for id in cluster.node_ids() {
+            let body = json!({"type": "topology", "topology": topology});
+            client
+                .request(id, body, Duration::from_secs(1))
+                .await
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn batch_mode_meets_the_perf_targets() {
+        Sim::new(SimConfig {
+            seed: 12,
+            min_latency: Duration::from_millis(100),
+            max_latency: Duration::from_millis(100),
+            ..SimConfig::default()
+        })
+        .nodes(25, || {
+            (
+                Arc::new(Mutex::new(Node::default())),
+                super::batch::handlers(Overlay::Complete),
+            )
+        })
+        .run(|cluster| async move {
+            send_grid(&cluster).await;
+            let clients: Vec<_> = (0..10_u64)
+                .map(|c| {
+                    let cluster = cluster.clone();
+                    tokio::spawn(async move {
+                        let mut client = cluster.client();
+                        let ids = cluster.node_ids();
+                        for i in 0..10_u64 {
+                            let message = c * 10 + i;
+                            let dest = &ids[usize::try_from(message).unwrap() % ids.len()];
+                            let body = json!({"type": "broadcast", "message": message});
+                            client
+                                .request(dest, body, Duration::from_secs(1))
+                                .await
+                                .unwrap();
+                            let dest = &ids[usize::try_from(message * 7).unwrap() % ids.len()];
+                            let body = json!({"type": "read"});
+                            client
+                                .request(dest, body, Duration::from_secs(1))
+                                .await
+                                .unwrap();
+                        }
+                    })
+                })
+                .collect();
+            for client in clients {
+                client.await.unwrap();
+            }
+
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            let mut client = cluster.client();
+            for id in cluster.node_ids() {
+                let reply = client
+                    .request(id, json!({"type": "read"}), Duration::from_secs(1))
+                    .await
+                    .unwrap();
+                let read: HashSet<u64> =
+                    serde_json::from_value(reply.body["messages"].clone()).unwrap();
+                assert_eq!(read, (0..100).collect(), "{id} is missing values");
+            }
+
+            let report = broadcast::check(&cluster.history());
+            assert!(report.valid, "{report}");
+            // 100 broadcasts, 100 reads and the final reads, counting the last batches and acks
+            let msgs_per_op = cluster.stats().server_msgs / 225;
+            assert!(msgs_per_op < 30, "{msgs_per_op} messages per operation");
+            let median = report.stats["stable_latency_median"].as_u64().unwrap();
+            assert!(median < 400_000_000, "median latency of {median}ns");
+        });
+    }
+
+    #[test]
+    fn batch_mode_forwards_values_reaching_a_node_before_its_topology() {
+        Sim::new(SimConfig::default())
+            .nodes(3, || {
+                (
+                    Arc::new(Mutex::new(Node::default())),
+                    super::batch::handlers(Overlay::Given),
+                )
+            })
+            .run(|cluster| async move {
+                // a line through n1, which doesn't know it yet
+                let topology = json!({"n0": ["n1"], "n1": ["n0", "n2"], "n2": ["n1"]});
+                let mut client = cluster.client();
+                let timeout = Duration::from_secs(1);
+                for id in ["n0", "n2"] {
+                    let body = json!({"type": "topology", "topology": topology});
+                    client.request(id, body, timeout).await.unwrap();
+                }
+
+                let body = json!({"type": "broadcast", "message": 1});
+                client.request("n0", body, timeout).await.unwrap();
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                let reply = client
+                    .request("n2", json!({"type": "read"}), timeout)
+                    .await
+                    .unwrap();
+                assert_eq!(reply.body["messages"], json!([1]));
+            });
+    }
+
+    #[test]
+    fn batch_mode_retries_batches_across_partitions() {
+        let nemesis = Nemesis::new().flapping(
+            &Fault::MajorityMinority,
+            Duration::ZERO,
+            Duration::from_secs(3),
+            Duration::from_millis(500),
+        );
+        Sim::new(SimConfig {
+            seed: 13,
+            drop_rate: 0.2,
+            ..SimConfig::default()
+        })
+        .nodes(5, || {
+            (
+                Arc::new(Mutex::new(Node::default())),
+                super::batch::handlers(Overlay::SpanningTree),
+            )
+        })
+        .nemesis(nemesis)
+        .run(|cluster| async move {
+            send_grid(&cluster).await;
+            let mut client = cluster.client();
+            let timeout = Duration::from_secs(1);
+            for message in 0..30_u64 {
+                let dest = &cluster.node_ids()[usize::try_from(message).unwrap() % 5];
+                let body = json!({"type": "broadcast", "message": message});
+                client.request(dest, body, timeout).await.unwrap();
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+            assert!(cluster.stats().partitioned > 0);
+
+            tokio::time::sleep(Duration::from_secs(3)).await;
+
+            for id in cluster.node_ids() {
+                let reply = client
+                    .request(id, json!({"type": "read"}), timeout)
+                    .await
+                    .unwrap();
+                let read: HashSet<u64> =
+                    serde_json::from_value(reply.body["messages"].clone()).unwrap();
+                assert_eq!(read, (0..30).collect(), "{id} is missing values");
+            }
+
+            let report = broadcast::check(&cluster.history());
+            assert!(report.valid, "{report}");
+        });
+    }
+}
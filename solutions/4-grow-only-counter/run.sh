@@ -8,4 +8,20 @@ maelstrom test \
   --bin ../../target/debug/grow-only-counter \
   --node-count 3 \
   --time-limit 20 \
-  --rate 100 --nemesis partition
\ No newline at end of file
+  --rate 100 --nemesis partition
+
+# same workload, with the counter kept in seq-kv
+G_COUNTER_MODE=seq-kv maelstrom test \
+  -w g-counter \
+  --bin ../../target/debug/grow-only-counter \
+  --node-count 3 \
+  --time-limit 20 \
+  --rate 100 --nemesis partition
+
+# a PN-counter CRDT, also passing the pn-counter workload
+G_COUNTER_MODE=crdt maelstrom test \
+  -w pn-counter \
+  --bin ../../target/debug/grow-only-counter \
+  --node-count 3 \
+  --time-limit 20 \
+  --rate 100 --nemesis partition
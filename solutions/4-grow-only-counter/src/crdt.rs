@@ -0,0 +1,43 @@
+//! Counter kept as a PN-counter CRDT gossiped between the nodes, which also serves the
+//! `pn-counter` workload since deltas may be negative.
+
+use node::crdt::PNCounter;
+use node::{Body, Gossip, HandlersMap, Message, Server, TypedHandlers};
+
+node::payload! {
+    enum Request {
+        "add" => Add { delta: i64 },
+        "read" => Read,
+    }
+}
+
+node::payload! {
+    enum Reply {
+        "add_ok" => Add,
+        "read_ok" => Read { value: i64 },
+    }
+}
+
+pub fn handlers() -> HandlersMap<dyn Server + Send + Sync> {
+    let mut handlers = node::build_default_handlers();
+    let gossip = Gossip::new(PNCounter::new());
+    gossip.install(&mut handlers);
+
+    handlers.insert_typed(move |srv_mutex, msg: Message<Body<Request>>| {
+        let gossip = gossip.clone();
+        async move {
+            let srv = srv_mutex.lock().unwrap();
+            let reply = match msg.body.payload {
+                Request::Add { delta } => {
+                    gossip.update(|counter| counter.add(&srv.get_id(), delta));
+                    Reply::Add
+                }
+                Request::Read => Reply::Read {
+                    value: gossip.read(PNCounter::value),
+                },
+            };
+            Ok(srv.send(&msg.reply(reply))?)
+        }
+    });
+    handlers
+}
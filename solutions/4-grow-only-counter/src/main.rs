@@ -1,101 +1,325 @@
+mod crdt;
+mod seq_kv;
+
 use std::{
-    io,
+    env, io,
     sync::{Arc, Mutex},
 };
 
 use serde_json::json;
 
-use node::{Message, SequentialKV, Server, build_default_handlers};
+use node::{
+    Body, Error, HandlersMap, Message, Node, SequentialKV, Server, TypedHandlers,
+    build_default_handlers,
+};
+
+/// Environment variable picking the implementation: `flood` (the default), `seq-kv` or `crdt`.
+const MODE_VAR: &str = "G_COUNTER_MODE";
+
+node::payload! {
+    enum Request {
+        "add" => Add {
+            delta: u64,
+            // set when a node forwards an add it got from a client
+            #[serde(default, skip_serializing_if = "Option::is_none")]
+            hash: Option<u64>,
+        },
+        "read" => Read,
+    }
+}
+
+node::payload! {
+    enum Reply {
+        "add_ok" => Add,
+        "read_ok" => Read { value: u64 },
+    }
+}
+
+fn handlers() -> HandlersMap<dyn Server + Send + Sync> {
+    let mut handlers = build_default_handlers();
+
+    handlers.insert_typed(|srv_mutex, msg: Message<Body<Request>>| async move {
+        let mut skv_any = srv_mutex.lock().unwrap();
+        let skv = skv_any
+            .as_any_mut()
+            .downcast_mut::<SequentialKV>()
+            .ok_or_else(|| {
+                Error::crash("server wasn't a sequential kv when calling a sequential kv handler")
+            })?;
+
+        let (delta, hash) = match msg.body.payload {
+            Request::Read => {
+                let sent = skv.send(&msg.reply(Reply::Read { value: skv.counter }));
+                drop(skv_any);
+                return Ok(sent?);
+            }
+            Request::Add { delta, hash } => (delta, hash),
+        };
+
+        let msg_hash = match msg.src.as_bytes()[0] as char {
+            'n' => hash.ok_or_else(|| Error::malformed("missing hash in message body"))?,
+            'c' => msg.hash(),
+            _ => {
+                return Err(Error::malformed(
+                    "message has a sender that doesn't start with a c or n",
+                ));
+            }
+        };
+
+        let _ = skv.send(&msg.reply(Reply::Add)).map_err(|e| {
+            log::error!("failed to send add_ok: {e}");
+        });
+
+        if !skv.values.insert(msg_hash) {
+            return Ok(());
+        }
+        skv.counter += delta;
+
+        let node_id = skv.get_id();
+        let all_nodes = skv.get_topology();
+        drop(skv_any);
+
+        // send it to everyone else
+        for n in all_nodes {
+            if *n == node_id {
+                continue;
+            }
+            let new_msg = Message {
+                dest: n,
+                src: node_id.clone(),
+                body: json!(Body::new(Request::Add {
+                    delta,
+                    hash: Some(msg_hash),
+                })),
+            };
+            let _ = node::send_synchronous(
+                &srv_mutex,
+                new_msg,
+                tokio::time::Duration::from_millis(500),
+            );
+        }
+        Ok(())
+    });
+    handlers
+}
 
 #[tokio::main]
 async fn main() -> io::Result<()> {
     env_logger::init();
-    let seq_kv = Arc::new(Mutex::new(SequentialKV::default()));
-    let mut handlers = build_default_handlers();
+    match env::var(MODE_VAR).as_deref() {
+        Ok("flood") | Err(_) => {
+            let seq_kv = Arc::new(Mutex::new(SequentialKV::default()));
+            node::serve(seq_kv, handlers()).await
+        }
+        Ok("seq-kv") => {
+            let node = Arc::new(Mutex::new(Node::default()));
+            node::serve(node, seq_kv::handlers()).await
+        }
+        Ok("crdt") => {
+            let node = Arc::new(Mutex::new(Node::default()));
+            node::serve(node, crdt::handlers()).await
+        }
+        Ok(mode) => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("unknown {MODE_VAR} `{mode}`, expected `flood`, `seq-kv` or `crdt`"),
+        )),
+    }
+}
 
-    handlers.insert(
-        "add",
-        Arc::new(|srv_mutex, msg| {
-            Box::pin(async move {
-                let mut skv_any = srv_mutex.lock().unwrap();
-                let skv = skv_any.as_any_mut().downcast_mut::<SequentialKV>().ok_or_else(|| {
-                    log::error!("server wasn't a sequential kv when calling a sequential kv handler");
-                })?;
-
-                let msg_hash = match msg.src.as_bytes()[0] as char {
-                    'n' => {
-                        if let Some(incoming_hash) = msg.body["hash"].as_u64() {
-                            incoming_hash
-                        } else {
-                            log::error!("missing hash in message body");
-                            return Err(());
-                        }
-                    }
-                    'c' => msg.hash(),
-                    _ => {
-                        log::error!("message has a sender that doesn't start with a c or n");
-                        return Err(());
-                    }
-                };
-                let reply = &skv.build_reply("add_ok", &msg, json!({})).ok_or(())?;
-
-                let _ = skv.send(reply).map_err(|e| {
-                    log::error!("failed to send add_ok: {e}");
-                });
-
-                if skv.values.contains(&msg_hash) {
-                    return Ok(());
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use node::checker::g_counter;
+    use node::sim::{Fault, Nemesis, Sim, SimConfig};
+    use node::{Node, SequentialKV};
+    use serde_json::json;
+    use tokio::time::Duration;
+
+    #[test]
+    fn every_node_reads_the_total() {
+        let config = SimConfig {
+            seed: 4,
+            drop_rate: 0.1,
+            ..SimConfig::default()
+        };
+        Sim::new(config)
+            .nodes(3, || {
+                (
+                    Arc::new(Mutex::new(SequentialKV::default())),
+                    super::handlers(),
+                )
+            })
+            .run(|cluster| async move {
+                let mut client = cluster.client();
+                let timeout = Duration::from_secs(1);
+                for delta in 1..=30_u64 {
+                    let dest = &cluster.node_ids()[usize::try_from(delta).unwrap() % 3];
+                    let body = json!({"type": "add", "delta": delta});
+                    client.request(dest, body, timeout).await.unwrap();
                 }
 
-                if let Some(number) = msg.body["delta"].as_u64() {
-                    skv.values.insert(msg_hash);
-                    skv.counter += number;
+                tokio::time::sleep(Duration::from_secs(5)).await;
+
+                for id in cluster.node_ids() {
+                    let reply = client
+                        .request(id, json!({"type": "read"}), timeout)
+                        .await
+                        .unwrap();
+                    assert_eq!(reply.body["value"], 465, "{id} has the wrong total");
+                }
+            });
+    }
+
+    #[test]
+    fn every_node_reads_the_total_after_partitions_heal() {
+        let nemesis = Nemesis::new().flapping(
+            &Fault::MajorityMinority,
+            Duration::ZERO,
+            Duration::from_secs(4),
+            Duration::from_millis(500),
+        );
+        Sim::new(SimConfig {
+            seed: 6,
+            ..SimConfig::default()
+        })
+        .nodes(3, || {
+            (
+                Arc::new(Mutex::new(SequentialKV::default())),
+                super::handlers(),
+            )
+        })
+        .nemesis(nemesis)
+        .run(|cluster| async move {
+            let mut client = cluster.client();
+            let timeout = Duration::from_secs(1);
+            for delta in 1..=30_u64 {
+                let dest = &cluster.node_ids()[usize::try_from(delta).unwrap() % 3];
+                let body = json!({"type": "add", "delta": delta});
+                client.request(dest, body, timeout).await.unwrap();
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+            assert!(cluster.stats().partitioned > 0);
+
+            tokio::time::sleep(Duration::from_secs(3)).await;
+
+            for id in cluster.node_ids() {
+                let reply = client
+                    .request(id, json!({"type": "read"}), timeout)
+                    .await
+                    .unwrap();
+                assert_eq!(reply.body["value"], 465, "{id} has the wrong total");
+            }
 
-                    let node_id = skv.get_id();
-                    let all_nodes = skv.get_topology();
-                    drop(skv_any);
+            let report = g_counter::check(&cluster.history());
+            assert!(report.valid, "{report}");
+        });
+    }
 
-                    // send it to everyone else
-                    for n in all_nodes {
-                        if *n == node_id {
-                            continue;
+    #[test]
+    fn seq_kv_mode_reads_the_total() {
+        let nemesis = Nemesis::new().flapping(
+            &Fault::RandomHalves,
+            Duration::ZERO,
+            Duration::from_secs(2),
+            Duration::from_millis(300),
+        );
+        Sim::new(SimConfig {
+            seed: 7,
+            drop_rate: 0.1,
+            ..SimConfig::default()
+        })
+        .nodes(3, || {
+            (
+                Arc::new(Mutex::new(Node::default())),
+                super::seq_kv::handlers(),
+            )
+        })
+        .nemesis(nemesis)
+        .run(|cluster| async move {
+            // concurrent adds on the same node race on its key
+            let adds: Vec<_> = (0..3)
+                .map(|c| {
+                    let cluster = cluster.clone();
+                    tokio::spawn(async move {
+                        let mut client = cluster.client();
+                        for delta in 1..=10_u64 {
+                            let body = json!({"type": "add", "delta": delta + c * 10});
+                            let dest = &cluster.node_ids()[usize::try_from(delta).unwrap() % 2];
+                            client
+                                .request(dest, body, Duration::from_secs(1))
+                                .await
+                                .unwrap();
+                            client
+                                .request("n2", json!({"type": "read"}), Duration::from_secs(1))
+                                .await
+                                .unwrap();
                         }
-                        let mut new_msg = Message {
-                            dest: n,
-                            src: node_id.clone(),
-                            body: msg.body.clone(),
-                        };
-                        new_msg.body["hash"] = msg_hash.into();
-                        let _ = node::send_synchronous(
-                            &srv_mutex,
-                            new_msg,
-                            tokio::time::Duration::from_millis(500),
-                        );
-                    }
-                }
-                Ok(())
-            })
-        }),
-    );
-    handlers.insert(
-        "read",
-        Arc::new(|seq_kv_mutex, msg| {
-            Box::pin(async move {
-                let mut skv_any = seq_kv_mutex.lock().unwrap();
-                
-                let skv = skv_any.as_any_mut().downcast_mut::<SequentialKV>().ok_or_else(|| {
-                    log::error!("server wasn't a sequential kv when calling a sequential kv handler");
-                })?;
-
-                let reply = &skv
-                    .build_reply("read_ok", &msg, json!({"value": skv.counter}))
-                    .ok_or(())?;
-
-                skv.send(reply).map_err(|e| {
-                    log::error!("failed to send read_ok: {e}");
+                    })
                 })
-            })
-        }),
-    );
-    node::serve(seq_kv, handlers).await
+                .collect();
+            for add in adds {
+                add.await.unwrap();
+            }
+
+            let mut client = cluster.client();
+            for id in cluster.node_ids() {
+                let reply = client
+                    .request(id, json!({"type": "read"}), Duration::from_secs(1))
+                    .await
+                    .unwrap();
+                assert_eq!(reply.body["value"], 465, "{id} has the wrong total");
+            }
+
+            let report = g_counter::check(&cluster.history());
+            assert!(report.valid, "{report}");
+        });
+    }
+
+    #[test]
+    fn crdt_mode_reads_the_total_with_negative_deltas() {
+        let nemesis = Nemesis::new().flapping(
+            &Fault::MajorityMinority,
+            Duration::ZERO,
+            Duration::from_secs(3),
+            Duration::from_millis(400),
+        );
+        Sim::new(SimConfig {
+            seed: 8,
+            drop_rate: 0.1,
+            ..SimConfig::default()
+        })
+        .nodes(3, || {
+            (
+                Arc::new(Mutex::new(Node::default())),
+                super::crdt::handlers(),
+            )
+        })
+        .nemesis(nemesis)
+        .run(|cluster| async move {
+            let mut client = cluster.client();
+            let timeout = Duration::from_secs(1);
+            for delta in 1..=30_i64 {
+                let dest = &cluster.node_ids()[usize::try_from(delta).unwrap() % 3];
+                let delta = if delta % 3 == 0 { -delta } else { delta };
+                let body = json!({"type": "add", "delta": delta});
+                client.request(dest, body, timeout).await.unwrap();
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+            assert!(cluster.stats().partitioned > 0);
+
+            tokio::time::sleep(Duration::from_secs(2)).await;
+
+            for id in cluster.node_ids() {
+                let reply = client
+                    .request(id, json!({"type": "read"}), timeout)
+                    .await
+                    .unwrap();
+                assert_eq!(reply.body["value"], 135, "{id} has the wrong total");
+            }
+
+            let report = g_counter::check(&cluster.history());
+            assert!(report.valid, "{report}");
+        });
+    }
 }
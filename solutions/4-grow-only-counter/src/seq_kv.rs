@@ -0,0 +1,62 @@
+//! Counter kept in Maelstrom's `seq-kv`: every node adds to a subtotal under its own key, reads
+//! sum the subtotals of every node.
+
+use node::{Body, HandlersMap, KvClient, KvError, Message, Server, TypedHandlers};
+
+use crate::{Reply, Request};
+
+fn key(node_id: &str) -> String {
+    format!("counter-{node_id}")
+}
+
+/// The subtotal under `key`, a key nobody wrote to yet counting as 0.
+async fn subtotal(kv: &KvClient, key: &str) -> Result<u64, KvError> {
+    match kv.read(key).await {
+        Err(KvError::KeyDoesNotExist) => Ok(0),
+        result => result,
+    }
+}
+
+pub fn handlers() -> HandlersMap<dyn Server + Send + Sync> {
+    let mut handlers = node::build_default_handlers();
+
+    handlers.insert_typed(|srv_mutex, msg: Message<Body<Request>>| async move {
+        let kv = KvClient::seq_kv(srv_mutex.clone());
+        let (node_id, node_ids) = {
+            let srv = srv_mutex.lock().unwrap();
+            (srv.get_id(), srv.get_topology())
+        };
+
+        let reply = match msg.body.payload {
+            Request::Add { delta, .. } => {
+                // only this node writes its key, but adds it handles concurrently still race
+                let key = key(&node_id);
+                loop {
+                    let current = subtotal(&kv, &key).await?;
+                    match kv.cas(&*key, current, current + delta, true).await {
+                        Err(KvError::PreconditionFailed) => {
+                            log::debug!("{key} changed since it was read, retrying");
+                        }
+                        result => break result?,
+                    }
+                }
+                Reply::Add
+            }
+            Request::Read => {
+                // seq-kv may serve stale reads, but not older than this node's own last write
+                let sync = srv_mutex.lock().unwrap().next_msg_id();
+                kv.write(format!("sync-{node_id}"), sync).await?;
+
+                let mut value = 0;
+                for node in node_ids {
+                    value += subtotal(&kv, &key(&node)).await?;
+                }
+                Reply::Read { value }
+            }
+        };
+
+        let sent = srv_mutex.lock().unwrap().send(&msg.reply(reply));
+        Ok(sent?)
+    });
+    handlers
+}
@@ -0,0 +1,22 @@
+#!/usr/bin/env bash
+set -xeuo pipefail
+
+cargo build
+
+# 5a: a single node
+maelstrom test \
+  -w kafka \
+  --bin ../../target/debug/kafka-log \
+  --node-count 1 \
+  --concurrency 2n \
+  --time-limit 20 \
+  --rate 1000
+
+# 5b: keys partitioned over two nodes, offsets allocated in lin-kv
+KAFKA_MODE=partitioned maelstrom test \
+  -w kafka \
+  --bin ../../target/debug/kafka-log \
+  --node-count 2 \
+  --concurrency 2n \
+  --time-limit 20 \
+  --rate 1000
@@ -0,0 +1,199 @@
+mod partitioned;
+
+use std::{
+    env, io,
+    sync::{Arc, Mutex},
+};
+
+use node::kafka::{Logs, Offsets, Reply, Request};
+use node::{Body, HandlersMap, Message, Node, Server, TypedHandlers};
+
+/// Environment variable picking the implementation: `single` (the default) or `partitioned`.
+const MODE_VAR: &str = "KAFKA_MODE";
+
+/// The logs kept on the one node there is, committed offsets in `lin-kv`.
+fn handlers() -> HandlersMap<dyn Server + Send + Sync> {
+    let mut handlers = node::build_default_handlers();
+    let logs = Arc::new(Mutex::new(Logs::default()));
+
+    handlers.insert_typed(move |srv_mutex, msg: Message<Body<Request>>| {
+        let logs = logs.clone();
+        async move {
+            let offsets = Offsets::new(srv_mutex.clone());
+            let reply = match msg.body.payload.clone() {
+                Request::Send { key, msg } => Reply::Send {
+                    offset: logs.lock().unwrap().append(key, msg),
+                },
+                Request::Poll { offsets } => Reply::Poll {
+                    msgs: logs.lock().unwrap().poll(&offsets),
+                },
+                Request::CommitOffsets { offsets: committed } => {
+                    for (key, offset) in committed {
+                        offsets.commit(&key, offset).await?;
+                    }
+                    Reply::CommitOffsets
+                }
+                Request::ListCommittedOffsets { keys } => Reply::ListCommittedOffsets {
+                    offsets: offsets.committed(keys).await?,
+                },
+            };
+            let sent = srv_mutex.lock().unwrap().send(&msg.reply(reply));
+            Ok(sent?)
+        }
+    });
+    handlers
+}
+
+#[tokio::main]
+async fn main() -> io::Result<()> {
+    env_logger::init();
+    let node = Arc::new(Mutex::new(Node::default()));
+    match env::var(MODE_VAR).as_deref() {
+        Ok("single") | Err(_) => node::serve(node, handlers()).await,
+        Ok("partitioned") => node::serve(node, partitioned::handlers()).await,
+        Ok(mode) => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("unknown {MODE_VAR} `{mode}`, expected `single` or `partitioned`"),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use node::checker::kafka;
+    use node::sim::{Cluster, Sim, SimConfig};
+    use node::{HandlersMap, Node, Server};
+    use serde_json::json;
+    use tokio::time::Duration;
+
+    /// Has three clients send to three shared keys through every node, polling and committing
+    /// as they go, then checks the history.
+    async fn workload(cluster: Cluster) {
+        let timeout = Duration::from_secs(2);
+        let clients: Vec<_> = (0..3_u64)
+            .map(|c| {
+                let cluster = cluster.clone();
+                tokio::spawn(async move {
+                    let mut client = cluster.client();
+                    let nodes = cluster.node_ids().len();
+                    for i in 0..20_u64 {
+                        let dest = &cluster.node_ids()[usize::try_from(i + c).unwrap() % nodes];
+                        let key = format!("k{}", i % 3);
+                        let body = json!({"type": "send", "key": key, "msg": c * 100 + i});
+                        client.request(dest, body, timeout).await.unwrap();
+
+                        let body = json!({"type": "poll", "offsets": {"k0": 0, "k1": 2, "k2": 4}});
+                        client.request(dest, body, timeout).await.unwrap();
+                        let body = json!({"type": "commit_offsets", "offsets": {key: i}});
+                        client.request(dest, body, timeout).await.unwrap();
+                    }
+                })
+            })
+            .collect();
+        for client in clients {
+            client.await.unwrap();
+        }
+
+        let mut client = cluster.client();
+        let dest = &cluster.node_ids()[0];
+        let body = json!({"type": "poll", "offsets": {"k0": 0, "k1": 0, "k2": 0}});
+        let reply = client.request(dest, body, timeout).await.unwrap();
+        for (key, sent) in [("k0", 21), ("k1", 21), ("k2", 18)] {
+            let msgs = reply.body["msgs"][key].as_array().unwrap();
+            assert_eq!(msgs.len(), sent, "{key} is missing messages");
+        }
+
+        let body = json!({"type": "list_committed_offsets", "keys": ["k0", "k1", "k2", "k3"]});
+        let reply = client.request(dest, body, timeout).await.unwrap();
+        assert_eq!(reply.body["offsets"], json!({"k0": 18, "k1": 19, "k2": 17}));
+
+        let report = kafka::check(&cluster.history());
+        assert!(report.valid, "{report}");
+        assert_eq!(report.stats["acknowledged_sends"], 60);
+    }
+
+    fn sim(nodes: usize, handlers: fn() -> HandlersMap<dyn Server + Send + Sync>) -> Sim {
+        let config = SimConfig {
+            seed: 4,
+            // replies overtaking each other on the way back from lin-kv
+            max_latency: Duration::from_millis(100),
+            ..SimConfig::default()
+        };
+        Sim::new(config).nodes(nodes, move || {
+            (Arc::new(Mutex::new(Node::default())), handlers())
+        })
+    }
+
+    /// Has eight clients send to one key through every node while four consumers poll from the
+    /// offset after the last one they saw, committing as they go, then checks none of them moved
+    /// past an offset before its message was stored.
+    async fn consumer_workload(cluster: Cluster) {
+        let timeout = Duration::from_secs(2);
+        let senders: Vec<_> = (0..8_u64)
+            .map(|c| {
+                let cluster = cluster.clone();
+                tokio::spawn(async move {
+                    let mut client = cluster.client();
+                    let nodes = cluster.node_ids().len();
+                    for i in 0..20_u64 {
+                        let dest = &cluster.node_ids()[usize::try_from(i + c).unwrap() % nodes];
+                        let body = json!({"type": "send", "key": "k", "msg": c * 100 + i});
+                        // errors and timeouts are part of the history
+                        let _ = client.request(dest, body, timeout).await;
+                    }
+                })
+            })
+            .collect();
+
+        let senders = Arc::new(senders);
+        let consumers: Vec<_> = (0..4_usize)
+            .map(|c| {
+                let cluster = cluster.clone();
+                let senders = senders.clone();
+                tokio::spawn(async move {
+                    let mut client = cluster.client();
+                    let dest = &cluster.node_ids()[c % cluster.node_ids().len()];
+                    let mut next = 0;
+                    loop {
+                        let sent = senders.iter().all(tokio::task::JoinHandle::is_finished);
+                        let body = json!({"type": "poll", "offsets": {"k": next}});
+                        let reply = client.request(dest, body, timeout).await.unwrap();
+                        let Some(msgs) = reply.body["msgs"]["k"].as_array() else {
+                            if sent {
+                                return;
+                            }
+                            continue;
+                        };
+                        next = msgs.last().unwrap()[0].as_u64().unwrap() + 1;
+                        let body = json!({"type": "commit_offsets", "offsets": {"k": next - 1}});
+                        client.request(dest, body, timeout).await.unwrap();
+                    }
+                })
+            })
+            .collect();
+        for consumer in consumers {
+            consumer.await.unwrap();
+        }
+
+        let report = kafka::check(&cluster.history());
+        assert!(report.valid, "{report}");
+        assert!(report.stats["acknowledged_sends"].as_u64() > Some(0));
+    }
+
+    #[test]
+    fn single_node_keeps_the_logs() {
+        sim(1, super::handlers).run(workload);
+    }
+
+    #[test]
+    fn partitioned_nodes_keep_the_logs() {
+        sim(3, super::partitioned::handlers).run(workload);
+    }
+
+    #[test]
+    fn partitioned_consumers_never_skip_offsets() {
+        sim(3, super::partitioned::handlers).run(consumer_workload);
+    }
+}
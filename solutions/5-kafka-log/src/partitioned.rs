@@ -0,0 +1,126 @@
+//! Keys hashed over the nodes: the owner of a key appends its messages to its own log, other
+//! nodes relay sends to it and split polls over the owners. Offsets are allocated and committed
+//! in `lin-kv`, so no two sends share an offset even if the owners change, and any node serves
+//! committed offsets.
+//!
+//! The owner of a key takes its offsets one send at a time, storing each message before taking
+//! the next offset: no poll can see a later offset before an earlier one.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use serde_json::{Value, json};
+use tokio::time::Duration;
+
+use node::kafka::{Logs, Offsets, Reply, Request};
+use node::{Body, Error, HandlersMap, Message, Partitioner, Server, TypedHandlers};
+
+/// How long to wait for the owner of a key.
+const RELAY_TIMEOUT: Duration = Duration::from_secs(1);
+
+type Srv = Arc<Mutex<dyn Server + Send + Sync>>;
+
+/// One lock per key, held from taking an offset until its message is stored.
+type Sending = Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>;
+
+/// Takes the next offset of `key` and stores `msg` there.
+async fn append(
+    offsets: &Offsets,
+    logs: &Mutex<Logs>,
+    sending: &Sending,
+    key: String,
+    msg: Value,
+) -> Result<u64, Error> {
+    let turn = sending
+        .lock()
+        .unwrap()
+        .entry(key.clone())
+        .or_default()
+        .clone();
+    let _turn = turn.lock().await;
+    let offset = offsets.allocate(&key).await?;
+    logs.lock().unwrap().insert(key, offset, msg);
+    Ok(offset)
+}
+
+/// Polls the keys in `offsets` from their owners, this node included.
+async fn poll(
+    srv_mutex: &Srv,
+    logs: &Mutex<Logs>,
+    partitioner: &Partitioner,
+    offsets: HashMap<String, u64>,
+) -> Result<HashMap<String, Vec<(u64, Value)>>, Error> {
+    let node_id = srv_mutex.lock().unwrap().get_id();
+    let mut by_owner: HashMap<&str, HashMap<String, u64>> = HashMap::new();
+    for (key, offset) in offsets {
+        by_owner
+            .entry(partitioner.owner(&key))
+            .or_default()
+            .insert(key, offset);
+    }
+
+    let mut msgs = by_owner
+        .remove(node_id.as_str())
+        .map(|offsets| logs.lock().unwrap().poll(&offsets))
+        .unwrap_or_default();
+    for (owner, offsets) in by_owner {
+        let body = json!(Body::new(Request::Poll { offsets }));
+        let reply = node::rpc(srv_mutex, owner, body, RELAY_TIMEOUT)
+            .await
+            .map_err(|e| Error::crash(format!("polling {owner} failed: {e}")))?;
+        match serde_json::from_value(reply.body) {
+            Ok(Body {
+                payload: Reply::Poll { msgs: owned },
+                ..
+            }) => msgs.extend(owned),
+            _ => return Err(Error::crash(format!("{owner} didn't reply with a poll_ok"))),
+        }
+    }
+    Ok(msgs)
+}
+
+pub fn handlers() -> HandlersMap<dyn Server + Send + Sync> {
+    let mut handlers = node::build_default_handlers();
+    let logs = Arc::new(Mutex::new(Logs::default()));
+    let sending = Arc::new(Sending::default());
+
+    handlers.insert_typed(move |srv_mutex, msg: Message<Body<Request>>| {
+        let logs = logs.clone();
+        let sending = sending.clone();
+        async move {
+            let offsets = Offsets::new(srv_mutex.clone());
+            let (node_id, partitioner) = {
+                let srv = srv_mutex.lock().unwrap();
+                (srv.get_id(), Partitioner::new(srv.get_topology()))
+            };
+
+            let reply = match msg.body.payload.clone() {
+                Request::Send { key, msg: value } => {
+                    let owner = partitioner.owner(&key);
+                    if owner != node_id {
+                        return node::relay(&srv_mutex, &msg, owner, RELAY_TIMEOUT).await;
+                    }
+                    Reply::Send {
+                        offset: append(&offsets, &logs, &sending, key, value).await?,
+                    }
+                }
+                Request::Poll { offsets } => Reply::Poll {
+                    msgs: poll(&srv_mutex, &logs, &partitioner, offsets).await?,
+                },
+                Request::CommitOffsets { offsets: committed } => {
+                    for (key, offset) in committed {
+                        offsets.commit(&key, offset).await?;
+                    }
+                    Reply::CommitOffsets
+                }
+                Request::ListCommittedOffsets { keys } => Reply::ListCommittedOffsets {
+                    offsets: offsets.committed(keys).await?,
+                },
+            };
+
+            let sent = srv_mutex.lock().unwrap().send(&msg.reply(reply));
+            Ok(sent?)
+        }
+    });
+    handlers
+}
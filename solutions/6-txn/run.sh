@@ -0,0 +1,37 @@
+#!/usr/bin/env bash
+set -xeuo pipefail
+
+cargo build
+
+# 6a: a single node
+TXN_MODE=read-uncommitted maelstrom test \
+  -w txn-rw-register \
+  --bin ../../target/debug/txn \
+  --node-count 1 \
+  --time-limit 20 \
+  --rate 1000 \
+  --concurrency 2n \
+  --consistency-models read-uncommitted \
+  --availability total
+
+# 6b: replicated, read uncommitted
+TXN_MODE=read-uncommitted maelstrom test \
+  -w txn-rw-register \
+  --bin ../../target/debug/txn \
+  --node-count 2 \
+  --concurrency 2n \
+  --time-limit 20 \
+  --rate 1000 \
+  --consistency-models read-uncommitted \
+  --availability total --nemesis partition
+
+# 6c: replicated, read committed
+TXN_MODE=read-committed maelstrom test \
+  -w txn-rw-register \
+  --bin ../../target/debug/txn \
+  --node-count 2 \
+  --concurrency 2n \
+  --time-limit 20 \
+  --rate 1000 \
+  --consistency-models read-committed \
+  --availability total --nemesis partition
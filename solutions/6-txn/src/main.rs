@@ -0,0 +1,127 @@
+use std::{
+    env, io,
+    sync::{Arc, Mutex},
+};
+
+use node::{HandlersMap, Isolation, Node, Server, TxnExecutor};
+
+/// Environment variable picking the isolation level: `read-committed` (the default) or
+/// `read-uncommitted`.
+const MODE_VAR: &str = "TXN_MODE";
+
+fn handlers(executor: &TxnExecutor) -> HandlersMap<dyn Server + Send + Sync> {
+    let mut handlers = node::build_default_handlers();
+    executor.install(&mut handlers);
+    handlers
+}
+
+#[tokio::main]
+async fn main() -> io::Result<()> {
+    env_logger::init();
+    let isolation = match env::var(MODE_VAR) {
+        Ok(mode) => mode
+            .parse()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, format!("{MODE_VAR}: {e}")))?,
+        Err(_) => Isolation::ReadCommitted,
+    };
+
+    let node = Arc::new(Mutex::new(Node::default()));
+    node::serve(node, handlers(&TxnExecutor::new(isolation))).await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use node::checker::txn_rw_register;
+    use node::sim::{Fault, Nemesis, Sim, SimConfig};
+    use node::{Isolation, Node, TxnExecutor};
+    use serde_json::{Value, json};
+    use tokio::time::Duration;
+
+    /// Has three clients run transactions over four keys across partitions, then checks the
+    /// history at `isolation`, writes ordered by the stamps the nodes reply with, and that every
+    /// node ends up with the same registers.
+    fn run(isolation: Isolation, seed: u64) {
+        let nemesis = Nemesis::new().flapping(
+            &Fault::RandomHalves,
+            Duration::ZERO,
+            Duration::from_secs(2),
+            Duration::from_millis(300),
+        );
+        Sim::new(SimConfig {
+            seed,
+            drop_rate: 0.1,
+            ..SimConfig::default()
+        })
+        .nodes(3, move || {
+            (
+                Arc::new(Mutex::new(Node::default())),
+                super::handlers(&TxnExecutor::new(isolation).with_stamps()),
+            )
+        })
+        .nemesis(nemesis)
+        .run(|cluster| async move {
+            let timeout = Duration::from_secs(1);
+            let clients: Vec<_> = (0..3_u64)
+                .map(|c| {
+                    let cluster = cluster.clone();
+                    tokio::spawn(async move {
+                        let mut client = cluster.client();
+                        for i in 0..30_u64 {
+                            let dest = &cluster.node_ids()[usize::try_from(i + c).unwrap() % 3];
+                            // values are unique per key, so reads tell which write they saw
+                            let value = c * 100 + i;
+                            let txn = json!([
+                                ["r", i % 4, null],
+                                ["w", i % 4, value],
+                                ["w", (i + 1) % 4, value],
+                                ["r", (i + 2) % 4, null],
+                                ["w", i % 4, value + 1000],
+                            ]);
+                            let body = json!({"type": "txn", "txn": txn});
+                            client.request(dest, body, timeout).await.unwrap();
+                            tokio::time::sleep(Duration::from_millis(20)).await;
+                        }
+                    })
+                })
+                .collect();
+            for client in clients {
+                client.await.unwrap();
+            }
+            assert!(cluster.stats().partitioned > 0);
+
+            // writes reach every node once the partitions heal
+            tokio::time::sleep(Duration::from_secs(2)).await;
+            let mut client = cluster.client();
+            let txn = json!([
+                ["r", 0, null],
+                ["r", 1, null],
+                ["r", 2, null],
+                ["r", 3, null]
+            ]);
+            let read_all = json!({"type": "txn", "txn": txn});
+            let mut reads: Vec<Value> = Vec::new();
+            for id in cluster.node_ids() {
+                let reply = client.request(id, read_all.clone(), timeout).await.unwrap();
+                reads.push(reply.body["txn"].clone());
+            }
+            assert!(reads.iter().all(|txn| *txn == reads[0]), "{reads:?}");
+
+            let report = txn_rw_register::check(&cluster.history(), isolation);
+            assert!(report.valid, "{report}");
+            assert!(report.stats["ww_edges"].as_u64() > Some(0), "{report}");
+            assert!(report.stats["wr_edges"].as_u64() > Some(0), "{report}");
+        });
+    }
+
+    #[test]
+    fn read_uncommitted_replicas_converge() {
+        run(Isolation::ReadUncommitted, 12);
+    }
+
+    #[test]
+    fn read_committed_replicas_converge() {
+        run(Isolation::ReadCommitted, 13);
+    }
+}
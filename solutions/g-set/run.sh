@@ -0,0 +1,12 @@
+#!/usr/bin/env bash
+set -xeuo pipefail
+
+cargo build
+
+maelstrom test \
+  -w g-set \
+  --bin ../../target/debug/g-set \
+  --node-count 3 \
+  --time-limit 20 \
+  --rate 10 \
+  --nemesis partition
@@ -0,0 +1,106 @@
+use std::{
+    io,
+    sync::{Arc, Mutex},
+};
+
+use node::crdt::GSet;
+use node::{Body, Gossip, HandlersMap, Message, Node, Server, TypedHandlers};
+
+node::payload! {
+    enum Request {
+        "add" => Add { element: u64 },
+        "read" => Read,
+    }
+}
+
+node::payload! {
+    enum Reply {
+        "add_ok" => Add,
+        "read_ok" => Read { value: Vec<u64> },
+    }
+}
+
+fn handlers() -> HandlersMap<dyn Server + Send + Sync> {
+    let mut handlers = node::build_default_handlers();
+    let gossip = Gossip::new(GSet::new()).with_deltas();
+    gossip.install(&mut handlers);
+
+    handlers.insert_typed(move |srv_mutex, msg: Message<Body<Request>>| {
+        let gossip = gossip.clone();
+        async move {
+            let reply = match msg.body.payload {
+                Request::Add { element } => {
+                    gossip.update(|set| set.insert(element));
+                    Reply::Add
+                }
+                Request::Read => Reply::Read {
+                    value: gossip.read(|set| set.iter().copied().collect()),
+                },
+            };
+            let sent = srv_mutex.lock().unwrap().send(&msg.reply(reply));
+            Ok(sent?)
+        }
+    });
+    handlers
+}
+
+#[tokio::main]
+async fn main() -> io::Result<()> {
+    env_logger::init();
+
+    let node_mutex = Arc::new(Mutex::new(Node::default()));
+    node::serve(node_mutex, handlers()).await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::sync::{Arc, Mutex};
+
+    use node::Node;
+    use node::sim::{Fault, Nemesis, Sim, SimConfig};
+    use serde_json::json;
+    use tokio::time::Duration;
+
+    #[test]
+    fn every_node_reads_every_element_after_partitions_heal() {
+        let nemesis = Nemesis::new().flapping(
+            &Fault::MajorityMinority,
+            Duration::ZERO,
+            Duration::from_secs(2),
+            Duration::from_millis(300),
+        );
+        Sim::new(SimConfig {
+            seed: 10,
+            drop_rate: 0.2,
+            ..SimConfig::default()
+        })
+        .nodes(3, || {
+            (Arc::new(Mutex::new(Node::default())), super::handlers())
+        })
+        .nemesis(nemesis)
+        .run(|cluster| async move {
+            let mut client = cluster.client();
+            let timeout = Duration::from_secs(1);
+            for element in 0..20_u64 {
+                let dest = &cluster.node_ids()[usize::try_from(element).unwrap() % 3];
+                let body = json!({"type": "add", "element": element});
+                client.request(dest, body, timeout).await.unwrap();
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+            assert!(cluster.stats().partitioned > 0);
+
+            tokio::time::sleep(Duration::from_secs(1)).await;
+
+            for id in cluster.node_ids() {
+                let reply = client
+                    .request(id, json!({"type": "read"}), timeout)
+                    .await
+                    .unwrap();
+                let read: HashSet<u64> =
+                    serde_json::from_value(reply.body["value"].clone()).unwrap();
+                assert_eq!(read, (0..20).collect(), "{id} is missing elements");
+            }
+        });
+    }
+}
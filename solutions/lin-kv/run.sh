@@ -0,0 +1,17 @@
+#!/usr/bin/env bash
+set -xeuo pipefail
+
+cargo build
+
+# replicated by raft, by multi-paxos, then a paxos register per key
+for engine in raft multi-paxos registers; do
+  LIN_KV_ENGINE=$engine maelstrom test \
+    -w lin-kv \
+    --bin ../../target/debug/lin-kv \
+    --node-count 5 \
+    --concurrency 2n \
+    --time-limit 20 \
+    --rate 100 \
+    --consistency-models linearizable \
+    --nemesis partition
+done
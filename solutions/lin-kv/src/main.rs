@@ -0,0 +1,154 @@
+use std::{
+    env, io,
+    sync::{Arc, Mutex},
+};
+
+use node::consensus::kv::{KV_TYPES, LinKv};
+use node::{HandlersMap, MultiPaxos, Node, Raft, Server};
+
+mod registers;
+
+/// Environment variable picking the consensus engine: `raft` (the default), `multi-paxos`, or
+/// `registers` for a Paxos register per key.
+const ENGINE_VAR: &str = "LIN_KV_ENGINE";
+
+fn raft() -> HandlersMap<dyn Server + Send + Sync> {
+    let mut handlers = node::build_default_handlers();
+    Raft::new(LinKv::new()).install(&mut handlers, KV_TYPES);
+    handlers
+}
+
+fn multi_paxos() -> HandlersMap<dyn Server + Send + Sync> {
+    let mut handlers = node::build_default_handlers();
+    MultiPaxos::new(LinKv::new()).install(&mut handlers, KV_TYPES);
+    handlers
+}
+
+#[tokio::main]
+async fn main() -> io::Result<()> {
+    env_logger::init();
+    let node = Arc::new(Mutex::new(Node::default()));
+    match env::var(ENGINE_VAR).as_deref() {
+        Ok("raft") | Err(_) => node::serve(node, raft()).await,
+        Ok("multi-paxos") => node::serve(node, multi_paxos()).await,
+        Ok("registers") => node::serve(node, registers::handlers()).await,
+        Ok(engine) => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "unknown {ENGINE_VAR} `{engine}`, expected `raft`, `multi-paxos` or `registers`"
+            ),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use node::checker::linearizable;
+    use node::sim::{Fault, Nemesis, Sim, SimConfig};
+    use node::{ErrorCode, HandlersMap, Node, RpcError, Server};
+    use rand::Rng;
+    use serde_json::json;
+    use tokio::time::Duration;
+
+    /// Has five clients read, write and cas ten keys on five nodes while a minority keeps
+    /// getting cut off, then checks the history is linearizable.
+    fn run(seed: u64, handlers: fn() -> HandlersMap<dyn Server + Send + Sync>) {
+        let nemesis = Nemesis::new().flapping(
+            &Fault::MajorityMinority,
+            Duration::from_millis(500),
+            Duration::from_secs(4),
+            Duration::from_millis(600),
+        );
+        Sim::new(SimConfig {
+            seed,
+            drop_rate: 0.05,
+            ..SimConfig::default()
+        })
+        .nodes(5, move || {
+            (Arc::new(Mutex::new(Node::default())), handlers())
+        })
+        .nemesis(nemesis)
+        .run(|cluster| async move {
+            let pause = Duration::from_millis(40);
+            cluster
+                .random_requests(seed, 5, 40, pause, |rng, c, i| {
+                    let key = rng.random_range(0..10_u64);
+                    match rng.random_range(0..3) {
+                        0 => json!({"type": "read", "key": key}),
+                        1 => json!({"type": "write", "key": key, "value": c * 100 + i}),
+                        _ => json!({
+                            "type": "cas",
+                            "key": key,
+                            "from": c * 100 + rng.random_range(0..i + 1),
+                            "to": c * 100 + i,
+                        }),
+                    }
+                })
+                .await;
+            assert!(cluster.stats().partitioned > 0);
+
+            let report = linearizable::check(&cluster.history());
+            assert!(report.valid, "{report}");
+            assert!(report.counts.ok > 80, "{report}");
+        });
+    }
+
+    #[test]
+    fn raft_stays_linearizable() {
+        run(40, super::raft);
+    }
+
+    #[test]
+    fn multi_paxos_stays_linearizable() {
+        run(41, super::multi_paxos);
+    }
+
+    #[test]
+    fn registers_stay_linearizable() {
+        run(42, super::registers::handlers);
+    }
+
+    #[test]
+    fn missing_keys_and_failed_cas_reply_with_their_error_codes() {
+        for handlers in [super::raft, super::multi_paxos, super::registers::handlers] {
+            Sim::new(SimConfig::default())
+                .nodes(3, move || {
+                    (Arc::new(Mutex::new(Node::default())), handlers())
+                })
+                .run(|cluster| async move {
+                    // leave time to elect a leader
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                    let mut client = cluster.client();
+                    let dest = &cluster.node_ids()[1];
+                    let timeout = Duration::from_secs(1);
+                    let code = |result: Result<_, RpcError>| match result {
+                        Err(RpcError::Remote(e)) => Some(e.code),
+                        _ => None,
+                    };
+
+                    let read = json!({"type": "read", "key": 1});
+                    let missing = client.request(dest, read.clone(), timeout).await;
+                    assert_eq!(code(missing), Some(ErrorCode::KeyDoesNotExist));
+                    let cas = json!({"type": "cas", "key": 1, "from": 2, "to": 3});
+                    let missing = client.request(dest, cas, timeout).await;
+                    assert_eq!(code(missing), Some(ErrorCode::KeyDoesNotExist));
+
+                    let create = json!({
+                        "type": "cas",
+                        "key": 1,
+                        "from": 2,
+                        "to": 3,
+                        "create_if_not_exists": true,
+                    });
+                    client.request(dest, create, timeout).await.unwrap();
+                    let cas = json!({"type": "cas", "key": 1, "from": 2, "to": 4});
+                    let failed = client.request(dest, cas, timeout).await;
+                    assert_eq!(code(failed), Some(ErrorCode::PreconditionFailed));
+                    let reply = client.request(dest, read, timeout).await.unwrap();
+                    assert_eq!(reply.body["value"], 3);
+                });
+        }
+    }
+}
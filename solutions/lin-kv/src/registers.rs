@@ -0,0 +1,77 @@
+//! Every key its own single-decree Paxos register: there is no leader nor log, so keys don't
+//! queue behind each other and any node on the majority side of a partition serves any key.
+
+use serde_json::Value;
+
+use node::consensus::kv::cas_precondition;
+use node::{Body, Error, ErrorCode, HandlersMap, Message, Registers, Server, TypedHandlers};
+
+node::payload! {
+    enum Request {
+        "read" => Read { key: Value },
+        "write" => Write { key: Value, value: Value },
+        "cas" => Cas {
+            key: Value,
+            from: Value,
+            to: Value,
+            #[serde(default)]
+            create_if_not_exists: bool,
+        },
+    }
+}
+
+node::payload! {
+    enum Reply {
+        "read_ok" => Read { value: Value },
+        "write_ok" => Write,
+        "cas_ok" => Cas,
+    }
+}
+
+pub fn handlers() -> HandlersMap<dyn Server + Send + Sync> {
+    let mut handlers = node::build_default_handlers();
+    let registers = Registers::new();
+    registers.install(&mut handlers);
+
+    handlers.insert_typed(move |srv_mutex, msg: Message<Body<Request>>| {
+        let registers = registers.clone();
+        async move {
+            let reply = match msg.body.payload.clone() {
+                Request::Read { key } => {
+                    // reads write back what they found, so they're ordered like any change
+                    let value = registers
+                        .change(&srv_mutex, &key.to_string(), |value| Ok(value.cloned()))
+                        .await?
+                        .ok_or_else(|| {
+                            Error::new(ErrorCode::KeyDoesNotExist, "key does not exist")
+                        })?;
+                    Reply::Read { value }
+                }
+                Request::Write { key, value } => {
+                    registers
+                        .change(&srv_mutex, &key.to_string(), |_| Ok(Some(value.clone())))
+                        .await?;
+                    Reply::Write
+                }
+                Request::Cas {
+                    key,
+                    from,
+                    to,
+                    create_if_not_exists,
+                } => {
+                    registers
+                        .change(&srv_mutex, &key.to_string(), |current| {
+                            cas_precondition(current, &from, create_if_not_exists)?;
+                            Ok(Some(to.clone()))
+                        })
+                        .await?;
+                    Reply::Cas
+                }
+            };
+
+            let sent = srv_mutex.lock().unwrap().send(&msg.reply(reply));
+            Ok(sent?)
+        }
+    });
+    handlers
+}
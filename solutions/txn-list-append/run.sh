@@ -0,0 +1,17 @@
+#!/usr/bin/env bash
+set -xeuo pipefail
+
+cargo build
+
+# replicated by raft, then sequenced in batches calvin-style
+for engine in raft calvin; do
+  TXN_ENGINE=$engine maelstrom test \
+    -w txn-list-append \
+    --bin ../../target/debug/txn-list-append \
+    --node-count 3 \
+    --concurrency 2n \
+    --time-limit 20 \
+    --rate 100 \
+    --consistency-models strict-serializable \
+    --nemesis partition
+done
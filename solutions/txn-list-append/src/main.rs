@@ -0,0 +1,104 @@
+use std::{
+    env, io,
+    sync::{Arc, Mutex},
+};
+
+use node::{HandlersMap, Lists, Node, Raft, Sequencer, Server};
+
+/// Environment variable picking how transactions are ordered: `raft` (the default) replicates
+/// each through the log, `calvin` sequences them in batches.
+const ENGINE_VAR: &str = "TXN_ENGINE";
+
+fn raft() -> HandlersMap<dyn Server + Send + Sync> {
+    let mut handlers = node::build_default_handlers();
+    Raft::new(Lists::new()).install(&mut handlers, &["txn"]);
+    handlers
+}
+
+fn calvin() -> HandlersMap<dyn Server + Send + Sync> {
+    let mut handlers = node::build_default_handlers();
+    Sequencer::new(Lists::new()).install(&mut handlers, &["txn"]);
+    handlers
+}
+
+#[tokio::main]
+async fn main() -> io::Result<()> {
+    env_logger::init();
+    let node = Arc::new(Mutex::new(Node::default()));
+    match env::var(ENGINE_VAR).as_deref() {
+        Ok("raft") | Err(_) => node::serve(node, raft()).await,
+        Ok("calvin") => node::serve(node, calvin()).await,
+        Ok(engine) => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("unknown {ENGINE_VAR} `{engine}`, expected `raft` or `calvin`"),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use node::checker::txn_list_append;
+    use node::sim::{Fault, Nemesis, Sim, SimConfig};
+    use node::{HandlersMap, Node, Server};
+    use rand::Rng;
+    use serde_json::json;
+    use tokio::time::Duration;
+
+    /// Has six clients run transactions of up to four appends and reads over eight keys on
+    /// three nodes while a minority keeps getting cut off, then checks the history is strictly
+    /// serializable.
+    fn run(seed: u64, handlers: fn() -> HandlersMap<dyn Server + Send + Sync>) {
+        let nemesis = Nemesis::new().flapping(
+            &Fault::MajorityMinority,
+            Duration::from_millis(500),
+            Duration::from_secs(4),
+            Duration::from_millis(600),
+        );
+        Sim::new(SimConfig {
+            seed,
+            drop_rate: 0.05,
+            ..SimConfig::default()
+        })
+        .nodes(3, move || {
+            (Arc::new(Mutex::new(Node::default())), handlers())
+        })
+        .nemesis(nemesis)
+        .run(|cluster| async move {
+            let pause = Duration::from_millis(40);
+            cluster
+                .random_requests(seed, 6, 40, pause, |rng, c, i| {
+                    let txn: Vec<_> = (0..rng.random_range(1..=4_u64))
+                        .map(|j| {
+                            let key = rng.random_range(0..8_u64);
+                            if rng.random_bool(0.5) {
+                                json!(["append", key, c * 1000 + i * 10 + j])
+                            } else {
+                                json!(["r", key, null])
+                            }
+                        })
+                        .collect();
+                    json!({"type": "txn", "txn": txn})
+                })
+                .await;
+            assert!(cluster.stats().partitioned > 0);
+
+            let report = txn_list_append::check(&cluster.history());
+            assert!(report.valid, "{report}");
+            assert!(report.counts.ok > 100, "{report}");
+            assert!(report.stats["wr_edges"].as_u64() > Some(0), "{report}");
+            assert!(report.stats["rw_edges"].as_u64() > Some(0), "{report}");
+        });
+    }
+
+    #[test]
+    fn raft_stays_strictly_serializable() {
+        run(50, super::raft);
+    }
+
+    #[test]
+    fn calvin_stays_strictly_serializable() {
+        run(51, super::calvin);
+    }
+}